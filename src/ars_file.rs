@@ -1,32 +1,244 @@
 use serde::{Serialize, Deserialize};
+use ron::error::SpannedError;
 use crate::syntax_elements::{
     attributes::ARSAttribute,
     constants::ARSConst,
     enums::ARSEnum,
+    foreign_mods::ARSForeignMod,
     functions::ARSFunction,
     impl_blocks::ARSImpl,
-    macros::ARSMacro,
+    macros::{ARSMacro, ARSMacroInvocation},
     modules::ARSModule,
     statics::ARSStatic,
     structs::ARSStruct,
     traits::ARSTrait,
+    trivia::{ARSTrivia, ARSTriviaPiece},
     type_aliases::ARSTypeAlias,
+    unions::ARSUnion,
     use_statements::ARSUse,
+    verbatim::ARSVerbatimItem,
 };
 
+/// Version of the .ars format written by this build, see `migrate` for older ones
+pub const FORMAT_VERSION: u32 = 20;
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ARSFile {
+    #[serde(default)]
+    pub header: ARSHeader,              // Missing in files older than format version 1
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,              // Inner `//!` doc lines of the file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs_trivia: Vec<ARSTriviaPiece>, // Comments and blank lines before the inner docs
+    #[serde(default)]
     pub attributes: Vec<ARSAttribute>,
-    pub uses: Vec<ARSUse>,
-    pub constants: Vec<ARSConst>,
-    pub statics: Vec<ARSStatic>,
-    pub type_aliases: Vec<ARSTypeAlias>,
-    pub macros: Vec<ARSMacro>,
-    pub structs: Vec<ARSStruct>,
-    pub enums: Vec<ARSEnum>,
-    pub traits: Vec<ARSTrait>,
-    pub impl_blocks: Vec<ARSImpl>,
-    pub modules: Vec<ARSModule>,
-    pub functions: Vec<ARSFunction>,
+    #[serde(default)]
+    pub items: Vec<ARSItem>,            // In source order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closing_trivia: Vec<ARSTriviaPiece>, // Comments and blank lines after the last item
+    #[serde(default)]
+    pub unsupported: Vec<ARSUnsupported>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_text: Option<ARSSourceText>, // Only set by lossless encoding
+}
+
+/// Describes how and from what an .ars file was produced
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ARSHeader {
+    pub format_version: u32,            // 0 for files written before headers existed
+    #[serde(default)]
+    pub producer_version: String,       // Version of walt that wrote the file
+    #[serde(default)]
+    pub source_path: Option<String>,    // Absolute path of the encoded file
+    #[serde(default)]
+    pub source_hash: Option<String>,    // FNV-1a 64-bit hash of the source text, in hex
+    #[serde(default)]
+    pub mode: ARSEncodingMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ARSEncodingMode {
+    #[default]
+    Structured,
+    Lossless, // `source_text` is set
+}
+
+impl ARSHeader {
+    /// A header for a file encoded by this build
+    pub fn current(source: &str, mode: ARSEncodingMode) -> Self {
+        ARSHeader {
+            format_version: FORMAT_VERSION,
+            producer_version: env!("CARGO_PKG_VERSION").to_string(),
+            source_path: None,
+            source_hash: Some(hash_source(source)),
+            mode,
+        }
+    }
+}
+
+/// Serializes a value as .ars text.
+///
+/// Expressions nest one RON level per level of Rust nesting, so a long method
+/// chain passes RON's default recursion limit of 128. The limit is lifted;
+/// `ARSExpression` grows the stack as it nests instead.
+pub fn to_ron<T: Serialize + ?Sized>(value: &T) -> Result<String, ron::Error> {
+    let mut output = Vec::new();
+    let options = ron::Options::default().without_recursion_limit();
+    value.serialize(&mut ron::Serializer::with_options(&mut output, None, options)?)?;
+    Ok(String::from_utf8(output).expect("RON output is UTF-8"))
+}
+
+/// Parses .ars text, with the same unlimited nesting as `to_ron`.
+///
+/// `serde_stacker` grows the stack for the parts that are skipped rather
+/// than parsed into an `ARSExpression`, such as the items of a version probe.
+pub fn from_ron<'de, T: Deserialize<'de>>(text: &'de str) -> Result<T, SpannedError> {
+    let options = ron::Options::default().without_recursion_limit();
+    let mut deserializer = ron::Deserializer::from_str_with_options(text, options)?;
+    let value = T::deserialize(serde_stacker::Deserializer::new(&mut deserializer))
+        .map_err(|e| deserializer.span_error(e))?;
+    deserializer.end().map_err(|e| deserializer.span_error(e))?;
+    Ok(value)
+}
+
+/// Reads one value from the start of RON text, returning it and the length read.
+pub fn from_ron_prefix<'de, T: Deserialize<'de>>(text: &'de str) -> Result<(T, usize), SpannedError> {
+    let options = ron::Options::default().without_recursion_limit();
+    let mut deserializer = ron::Deserializer::from_str_with_options(text, options)?;
+    let value = T::deserialize(serde_stacker::Deserializer::new(&mut deserializer))
+        .map_err(|e| deserializer.span_error(e))?;
+    Ok((value, text.len() - deserializer.remainder().len()))
+}
+
+/// FNV-1a 64-bit hash of source text, in hex
+pub fn hash_source(source: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in source.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// The original source text of a file, split at its top-level items.
+///
+/// Stored by lossless encoding so decoding reproduces the input byte for
+/// byte, including whitespace, line endings and any byte-order mark. Each
+/// part keeps a hash of the model it was encoded to: a part whose model has
+/// been edited since no longer matches its text, and is decoded from the
+/// model instead, see `decoder::decode`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ARSSourceText {
+    #[serde(default)]
+    pub head: String,       // Text up to the end of the file's inner attributes and docs
+    pub items: Vec<String>, // Text up to the end of each top-level syn item, unsupported ones included
+    pub tail: String,       // Text after the last item
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_hash: Option<String>, // Model hash of the file's docs, attributes and trivia, for `head` and `tail`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub item_hashes: Vec<String>,  // Model hash of each item, for `items`
+}
+
+impl ARSSourceText {
+    /// The source text this was split from.
+    pub fn join(&self) -> String {
+        let mut text = self.head.clone();
+        text.push_str(&self.items.concat());
+        text.push_str(&self.tail);
+        text
+    }
+
+    /// Records the model hashes of `ars_file`, whose text this is.
+    pub fn record_models(&mut self, ars_file: &ARSFile) {
+        self.file_hash = Some(file_model_hash(ars_file));
+        self.item_hashes = ars_file.items.iter().map(model_hash).collect();
+    }
+
+    /// Whether the text was stored without model hashes, which files from
+    /// before they were kept are. Their text is used as it is.
+    pub fn is_unchecked(&self) -> bool {
+        self.file_hash.is_none()
+    }
+}
+
+/// Hash of a model as .ars text, telling whether it was edited
+pub fn model_hash<T: Serialize + ?Sized>(value: &T) -> String {
+    hash_source(&to_ron(value).unwrap_or_default())
+}
+
+/// Hash of the parts of a file that are not its items
+pub fn file_model_hash(ars_file: &ARSFile) -> String {
+    model_hash(&(&ars_file.docs, &ars_file.docs_trivia, &ars_file.attributes, &ars_file.closing_trivia))
+}
+
+/// A single item, tagged with its kind
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSItem {
+    Use(ARSUse),
+    Const(ARSConst),
+    Static(ARSStatic),
+    TypeAlias(ARSTypeAlias),
+    Macro(ARSMacro),
+    MacroInvocation(ARSMacroInvocation),
+    Struct(ARSStruct),
+    Union(ARSUnion),
+    Enum(ARSEnum),
+    Trait(ARSTrait),
+    Impl(ARSImpl),
+    Module(ARSModule),
+    ForeignMod(ARSForeignMod),
+    Function(ARSFunction),
+    Verbatim(ARSVerbatimItem), // Any item not modelled yet
+}
+
+impl ARSItem {
+    /// The comments and blank lines attached to the item
+    pub fn trivia(&self) -> &ARSTrivia {
+        match self {
+            ARSItem::Use(u) => &u.trivia,
+            ARSItem::Const(c) => &c.trivia,
+            ARSItem::Static(s) => &s.trivia,
+            ARSItem::TypeAlias(ta) => &ta.trivia,
+            ARSItem::Macro(m) => &m.trivia,
+            ARSItem::MacroInvocation(m) => &m.trivia,
+            ARSItem::Struct(s) => &s.trivia,
+            ARSItem::Union(u) => &u.trivia,
+            ARSItem::Enum(e) => &e.trivia,
+            ARSItem::Trait(t) => &t.trivia,
+            ARSItem::Impl(i) => &i.trivia,
+            ARSItem::Module(m) => &m.trivia,
+            ARSItem::ForeignMod(f) => &f.trivia,
+            ARSItem::Function(f) => &f.trivia,
+            ARSItem::Verbatim(v) => &v.trivia,
+        }
+    }
+
+    pub fn trivia_mut(&mut self) -> &mut ARSTrivia {
+        match self {
+            ARSItem::Use(u) => &mut u.trivia,
+            ARSItem::Const(c) => &mut c.trivia,
+            ARSItem::Static(s) => &mut s.trivia,
+            ARSItem::TypeAlias(ta) => &mut ta.trivia,
+            ARSItem::Macro(m) => &mut m.trivia,
+            ARSItem::MacroInvocation(m) => &mut m.trivia,
+            ARSItem::Struct(s) => &mut s.trivia,
+            ARSItem::Union(u) => &mut u.trivia,
+            ARSItem::Enum(e) => &mut e.trivia,
+            ARSItem::Trait(t) => &mut t.trivia,
+            ARSItem::Impl(i) => &mut i.trivia,
+            ARSItem::Module(m) => &mut m.trivia,
+            ARSItem::ForeignMod(f) => &mut f.trivia,
+            ARSItem::Function(f) => &mut f.trivia,
+            ARSItem::Verbatim(v) => &mut v.trivia,
+        }
+    }
+}
 
+/// A construct the encoder could not map onto the .ars model, in whole or in part
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ARSUnsupported {
+    pub kind: String,   // e.g. "extern block", "struct where clause"
+    pub line: usize,    // 1-based line of the construct in the source file
+    pub column: usize,  // 0-based column, as reported by syn
+    pub detail: String,
 }
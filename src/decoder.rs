@@ -1,67 +1,226 @@
 use std::fs;
 use std::path::{Path};
-use crate::ars_file::ARSFile;
+use std::slice;
+use crate::ars_file::{file_model_hash, model_hash, ARSFile, ARSItem, ARSSourceText};
+use crate::error::WaltError;
+use crate::migrate;
 
 // Import syntax_elements from top-level folder
 use crate::syntax_elements::{
-    attributes, constants, enums, functions, impl_blocks, macros, modules,
-    statics, structs, traits, type_aliases, use_statements
+    attributes, constants, enums, foreign_mods, functions, impl_blocks, macros, modules,
+    statics, structs, traits, trivia, type_aliases, unions, use_statements, verbatim
 };
 
-/// Decodes a single source file into an output path.
-pub fn decode_file(source_lines: &[String], output_path: &Path) -> std::io::Result<()> {
-    let ron_string = source_lines.join("\n");
-
-    match ron::from_str::<ARSFile>(&ron_string) {
-        Ok(ars_file) => {
-            let mut output = String::new();
-
-            // Reconstruct each syntax element type in order
-            output.push_str(&attributes::reconstruct(&ars_file.attributes));
-            output.push_str(&use_statements::reconstruct(&ars_file.uses));
-            output.push_str(&constants::reconstruct(&ars_file.constants));
-            output.push_str(&statics::reconstruct(&ars_file.statics));
-            output.push_str(&type_aliases::reconstruct(&ars_file.type_aliases));
-            output.push_str(&macros::reconstruct(&ars_file.macros));
-            output.push_str(&structs::reconstruct(&ars_file.structs));
-            output.push_str(&enums::reconstruct(&ars_file.enums));
-            output.push_str(&traits::reconstruct(&ars_file.traits));
-            output.push_str(&impl_blocks::reconstruct(&ars_file.impl_blocks));
-            output.push_str(&modules::reconstruct(&ars_file.modules));
-            output.push_str(&functions::reconstruct(&ars_file.functions));
-
-
-            if let Some(parent) = output_path.parent() {
-                fs::create_dir_all(parent)?;
+/// Reconstructs Rust source text from an ARSFile struct, keeping the original item order.
+///
+/// Files encoded losslessly are reproduced from their stored source text,
+/// except for the parts whose model was edited since, see `decode_source_text`.
+pub fn decode(ars_file: &ARSFile) -> String {
+    if let Some(source_text) = &ars_file.source_text {
+        return decode_source_text(ars_file, source_text);
+    }
+
+    let mut output = reconstruct_head(ars_file);
+    output.push_str(&reconstruct_items(&ars_file.items));
+    output.push_str(&trivia::render_lines(&ars_file.closing_trivia));
+    output
+}
+
+/// The inner docs and attributes of a file, with the lines before them
+fn reconstruct_head(ars_file: &ARSFile) -> String {
+    let mut output = trivia::render_lines(&ars_file.docs_trivia);
+    output.push_str(&attributes::render_inner_docs(&ars_file.docs));
+    output.push_str(&attributes::reconstruct(&ars_file.attributes));
+    output
+}
+
+/// Reproduces a lossless file from its stored text, part by part.
+///
+/// A part is taken from the text only while its model hashes as it did when
+/// the text was stored. Items edited or added since are reconstructed from
+/// their model in their place, and stored items no longer in the model are
+/// left out. The same goes for the file's own docs, attributes and trivia.
+fn decode_source_text(ars_file: &ARSFile, source_text: &ARSSourceText) -> String {
+    if source_text.is_unchecked() {
+        return source_text.join();
+    }
+    let file_unchanged = source_text.file_hash.as_deref() == Some(file_model_hash(ars_file).as_str());
+
+    let mut output = match file_unchanged {
+        true => source_text.head.clone(),
+        false => reconstruct_head(ars_file),
+    };
+    // Whether the last part came from the model, which ends it with a newline
+    let mut after_model = !output.is_empty() && !file_unchanged;
+    let mut stored = 0;
+    for item in &ars_file.items {
+        let hash = model_hash(item);
+        let found = (stored..source_text.items.len()).find(|&i| source_text.item_hashes.get(i) == Some(&hash));
+        match found {
+            Some(i) => {
+                push_stored(&mut output, &source_text.items[i], after_model);
+                stored = i + 1;
+                after_model = false;
+            }
+            None => {
+                if !output.is_empty() && !output.ends_with('\n') {
+                    output.push('\n');
+                }
+                output.push_str(reconstruct_item(item).trim_end());
+                output.push('\n');
+                after_model = true;
             }
-            fs::write(output_path, output)
         }
-        Err(e) => {
-            // Handle RON deserialization error
-            // For now, we'll print the error and return an io::Error
-            eprintln!("Failed to decode RON: {}", e);
-            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+    match file_unchanged {
+        true => push_stored(&mut output, &source_text.tail, after_model),
+        false => {
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&trivia::render_lines(&ars_file.closing_trivia));
         }
     }
+    output
+}
+
+/// Appends stored text, which starts with the line break ending the part
+/// before it unless that part came from the model and has its own.
+fn push_stored(output: &mut String, text: &str, after_model: bool) {
+    let text = match after_model {
+        true => text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(text),
+        false => text,
+    };
+    output.push_str(text);
+}
+
+/// Reconstructs a sequence of items in order.
+///
+/// Blank lines between items come from each item's recorded trivia.
+pub fn reconstruct_items(items: &[ARSItem]) -> String {
+    let mut output = String::new();
+    for item in items {
+        output.push_str(reconstruct_item(item).trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+pub(crate) fn reconstruct_item(item: &ARSItem) -> String {
+    match item {
+        ARSItem::Use(u) => use_statements::reconstruct(slice::from_ref(u)),
+        ARSItem::Const(c) => constants::reconstruct(slice::from_ref(c)),
+        ARSItem::Static(s) => statics::reconstruct(slice::from_ref(s)),
+        ARSItem::TypeAlias(ta) => type_aliases::reconstruct(slice::from_ref(ta)),
+        ARSItem::Macro(m) => macros::reconstruct(slice::from_ref(m)),
+        ARSItem::MacroInvocation(m) => macros::reconstruct_invocations(slice::from_ref(m)),
+        ARSItem::Struct(s) => structs::reconstruct(slice::from_ref(s)),
+        ARSItem::Union(u) => unions::reconstruct(slice::from_ref(u)),
+        ARSItem::Enum(e) => enums::reconstruct(slice::from_ref(e)),
+        ARSItem::Trait(t) => traits::reconstruct(slice::from_ref(t)),
+        ARSItem::Impl(i) => impl_blocks::reconstruct(slice::from_ref(i)),
+        ARSItem::Module(m) => modules::reconstruct(slice::from_ref(m)),
+        ARSItem::ForeignMod(f) => foreign_mods::reconstruct(slice::from_ref(f)),
+        ARSItem::Function(f) => functions::reconstruct(slice::from_ref(f)),
+        ARSItem::Verbatim(v) => verbatim::reconstruct(slice::from_ref(v)),
+    }
+}
+
+/// Parses the RON text of an .ars file, upgrading older format versions.
+pub fn parse_ars(ron_string: &str) -> Result<ARSFile, WaltError> {
+    migrate::upgrade(ron_string).map(|(ars_file, _)| ars_file)
+}
+
+/// Decodes a single .ars file into an output path.
+pub fn decode_file(input_path: &Path, output_path: &Path) -> Result<(), WaltError> {
+    let parse = || parse_ars(&fs::read_to_string(input_path)?);
+    let output = decode(&parse().map_err(|e| e.with_path(input_path))?);
+
+    let write = || {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(output_path, output)
+    };
+    write().map_err(|e| WaltError::from(e).with_path(output_path))
 }
 
 /// Recursively decodes all `.ars` files in a directory, preserving structure
-pub fn decode_project(input_dir: &Path, output_dir: &Path) -> std::io::Result<()> {
-    for entry in fs::read_dir(input_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+pub fn decode_project(input_dir: &Path, output_dir: &Path) -> Result<(), WaltError> {
+    let entries = fs::read_dir(input_dir).map_err(|e| WaltError::from(e).with_path(input_dir))?;
+    for entry in entries {
+        let path = entry.map_err(|e| WaltError::from(e).with_path(input_dir))?.path();
 
         if path.is_dir() {
             let sub_output = output_dir.join(path.file_name().unwrap());
             decode_project(&path, &sub_output)?;
-        } else if path.extension().map_or(false, |e| e == "ars") {
-            let content = fs::read_to_string(&path)?;
-            let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        } else if path.extension().is_some_and(|e| e == "ars") {
             // Output file should be .rs
             let file_name = path.file_stem().unwrap().to_str().unwrap();
             let output_path = output_dir.join(format!("{}.rs", file_name));
-            decode_file(&lines, &output_path)?;
+            decode_file(&path, &output_path)?;
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ars_file::{from_ron, to_ron};
+    use crate::encoder;
+
+    #[test]
+    fn test_items_keep_source_order() {
+        let source = "fn first() {}\nstruct Second;\nuse third::Item;\nconst FOURTH: u8 = 4;\n";
+        let ars_file = encoder::encode_source(source).unwrap();
+        let output = decode(&ars_file);
+
+        let positions: Vec<usize> = ["fn first", "struct Second", "use third", "const FOURTH"]
+            .iter()
+            .map(|needle| output.find(needle).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{}", output);
+    }
+
+    #[test]
+    fn test_lossless_roundtrip_is_byte_exact() {
+        let source = "\u{feff}// header\r\nfn main()  {\r\n\tprintln!(\"Hello\");\r\n}\r\nextern \"C\" {}\r\n\r\n";
+        let ars_file = encoder::encode_lossless(source).unwrap();
+        let ron_string = to_ron(&ars_file).unwrap();
+        let decoded = decode(&from_ron::<ARSFile>(&ron_string).unwrap());
+        assert_eq!(decoded, source);
+    }
+
+    #[test]
+    fn test_lossless_files_follow_model_edits() {
+        let source = "//! Docs\n#![allow(unused)] // why\n\nfn  first()  {}   // kept\n\nstruct   Second;\n\nfn third( ) {}\n";
+        let mut ars_file = from_ron::<ARSFile>(&to_ron(&encoder::encode_lossless(source).unwrap()).unwrap()).unwrap();
+        assert_eq!(decode(&ars_file), source);
+
+        // The edited item comes from the model, the removed one is left out
+        let ARSItem::Struct(second) = &mut ars_file.items[1] else { panic!() };
+        second.name = "Renamed".to_string();
+        ars_file.items.pop();
+        assert_eq!(decode(&ars_file), "//! Docs\n#![allow(unused)] // why\n\nfn  first()  {}   // kept\n\nstruct Renamed;\n");
+
+        // As do edited attributes, around items still taken from the text
+        ars_file.attributes.clear();
+        assert_eq!(decode(&ars_file), "//! Docs\n\nfn  first()  {}   // kept\n\nstruct Renamed;\n");
+    }
+
+    #[test]
+    fn test_deeply_nested_expressions_survive_ron() {
+        let chain: String = (0..200).map(|i| format!(".step({})", i)).collect();
+        let mut branches = String::from("0");
+        for depth in 0..40 {
+            branches = format!("if x > {} {{ {} }} else {{ {} }}", depth, depth, branches);
+        }
+        let source = format!("fn main() {{\n    let built = Builder::new(){};\n    let x = {};\n}}\n", chain, branches);
+
+        for ars_file in [encoder::encode_source(&source).unwrap(), encoder::encode_lossless(&source).unwrap()] {
+            let ron_string = to_ron(&ars_file).unwrap();
+            assert_eq!(decode(&parse_ars(&ron_string).unwrap()), decode(&ars_file));
+        }
+    }
+}
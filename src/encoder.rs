@@ -1,61 +1,244 @@
 use std::fs;
-use crate::ars_file::ARSFile;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use syn::spanned::Spanned;
+use crate::ars_file::{to_ron, ARSEncodingMode, ARSFile, ARSHeader, ARSItem, ARSSourceText, ARSUnsupported};
+use crate::decoder;
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::trivia::{self, GapStart};
 
 // Import syntax_elements from top-level folder
 use crate::syntax_elements::{
-    attributes, constants, enums, functions, impl_blocks, macros, modules,
-    structs, statics, traits, type_aliases, use_statements
+    attributes, constants, enums, foreign_mods, functions, impl_blocks, macros, modules,
+    structs, statics, traits, type_aliases, unions, use_statements, verbatim
 };
 
-/// Encodes a source file by extracting all syntax elements into an ARSFile struct.
-pub fn encode(source_lines: &[String]) -> ARSFile {
-    let source_string = source_lines.join("\n");
+/// Encodes a source file by parsing it once and walking its items into an ARSFile struct.
+pub fn encode(source_lines: &[String]) -> Result<ARSFile, WaltError> {
+    encode_source(&source_lines.join("\n"))
+}
+
+/// Encodes Rust source text into an ARSFile struct.
+pub fn encode_source(source: &str) -> Result<ARSFile, WaltError> {
+    let file = syn::parse_file(source)?;
+    let mut ars_file = encode_parsed(&file, source);
+    ars_file.header = ARSHeader::current(source, ARSEncodingMode::Structured);
+    report_lost_comments(&mut ars_file, source);
+    Ok(ars_file)
+}
+
+/// Records the comments that decoding would not bring back, in places the
+/// model keeps no trivia for.
+fn report_lost_comments(ars_file: &mut ARSFile, source: &str) {
+    let decoded = decoder::decode(ars_file);
+    for (offset, text) in trivia::lost_comments(source, &decoded) {
+        let line_start = source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        ars_file.unsupported.push(ARSUnsupported {
+            kind: "comment".to_string(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count(),
+            detail: format!("dropped: {}", text.lines().next().unwrap_or_default()),
+        });
+    }
+}
+
+/// Encodes Rust source text and also stores the text itself, split at the
+/// spans of its top-level items, so decoding reproduces it byte for byte.
+pub fn encode_lossless(source: &str) -> Result<ARSFile, WaltError> {
+    let file = syn::parse_file(source)?;
+    let mut ars_file = encode_parsed(&file, source);
+    ars_file.header = ARSHeader::current(source, ARSEncodingMode::Lossless);
+    let mut text = source_text(&file, &SourceMap::new(source));
+    text.record_models(&ars_file);
+    ars_file.source_text = Some(text);
+    Ok(ars_file)
+}
+
+fn encode_parsed(file: &syn::File, source: &str) -> ARSFile {
+    let source_map = SourceMap::new(source);
+
+    // Trivia of the first inner attribute or item starts after any shebang.
+    let (start, start_kind) = if source_map.content_start() > 0 {
+        (source_map.content_start(), GapStart::AfterToken)
+    } else {
+        (0, GapStart::LineStart)
+    };
+    encode_items(&file.attrs, &file.items, &source_map, start..source.len(), start_kind)
+}
+
+/// Splits the source text at the end of the inner attributes and of each top-level item.
+///
+/// A line comment after an item or the last inner attribute stays with it,
+/// as its trivia does.
+fn source_text(file: &syn::File, source: &SourceMap) -> ARSSourceText {
+    let with_comment = |end: usize| {
+        let rest = &source.text()[end..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        match line.trim_start().starts_with("//") {
+            true => end + line.trim_end_matches('\r').len(),
+            false => end,
+        }
+    };
+    let mut text = ARSSourceText::default();
+    let mut position = file.attrs.last().map_or(source.content_start(), |attr| with_comment(source.range(attr).end));
+    text.head = source.slice(0..position).to_string();
+    for item in &file.items {
+        let end = with_comment(source.range(item).end);
+        text.items.push(source.slice(position..end).to_string());
+        position = end;
+    }
+    text.tail = source.slice(position..source.text().len()).to_string();
+    text
+}
+
+/// Walks the inner attributes and items of a file or module, converting each
+/// item through its syntax_elements module.
+///
+/// `region` is the byte range of the source they live in, used to attach
+/// the comments and blank lines around them. Items or parts of items the .ars
+/// model has no slot for are recorded in `ARSFile::unsupported` rather than
+/// dropped silently.
+fn encode_items(
+    inner_attrs: &[syn::Attribute],
+    items: &[syn::Item],
+    source: &SourceMap,
+    region: Range<usize>,
+    start_kind: GapStart,
+) -> ARSFile {
     let mut ars_file = ARSFile::default();
 
-    // Populate ARSFile with extracted elements
-    ars_file.attributes = attributes::encode_rust(&source_string);
-    ars_file.uses = use_statements::extract_ars_uses(&source_string);
-    ars_file.constants = constants::extract_ars_consts(&source_string);
-    ars_file.statics = statics::extract_ars_statics(&source_string);
-    ars_file.type_aliases = type_aliases::extract_ars_type_aliases(&source_string);
-    ars_file.macros = macros::extract_ars_macros(&source_string);
-    ars_file.structs = structs::extract_ars_structs(&source_string);
-    ars_file.enums = enums::extract_ars_enums(&source_string);
-    ars_file.traits = traits::extract_ars_traits(&source_string);
-    ars_file.impl_blocks = impl_blocks::extract_ars_impls(&source_string);
-    ars_file.modules = modules::extract_ars_modules(&source_string);
-    ars_file.functions = functions::extract_ars_functions(&source_string);
+    // Inner attributes come first, so they are siblings of the items for trivia.
+    let ranges: Vec<_> = inner_attrs.iter().map(|attr| source.range(attr))
+        .chain(items.iter().map(|item| source.range(item)))
+        .collect();
+    let (mut sibling_trivia, closing) = trivia::for_siblings(source, region.start, start_kind, &ranges, region.end);
+    ars_file.closing_trivia = closing;
+    let item_trivia = sibling_trivia.split_off(inner_attrs.len());
 
+    // Inner doc comments are gathered into one block, with the lines before them
+    let attr_refs: Vec<_> = inner_attrs.iter().collect();
+    let movable = attributes::movable_docs(&attr_refs);
+    for ((attr, attr_trivia), is_doc) in inner_attrs.iter().zip(sibling_trivia).zip(movable) {
+        match attributes::doc_text(attr) {
+            Some(text) if is_doc => {
+                ars_file.docs.push(text);
+                ars_file.docs_trivia.extend(attr_trivia.leading);
+            }
+            _ => {
+                let mut ars_attribute = attributes::from_syn(attr);
+                ars_attribute.trivia = attr_trivia;
+                ars_file.attributes.push(ars_attribute);
+            }
+        }
+    }
+
+    for (item, mut item_trivia) in items.iter().zip(item_trivia) {
+        let mut ars_item = encode_item(item, source, &mut ars_file.unsupported);
+        let target = ars_item.trivia_mut();
+        target.leading = std::mem::take(&mut item_trivia.leading);
+        target.trailing = item_trivia.trailing.take();
+        ars_file.items.push(ars_item);
+    }
 
     ars_file
 }
 
+/// Converts a single syn item, at file level or inside a module or block.
+///
+/// Items the .ars model has no structure for are kept as verbatim source and
+/// recorded in `unsupported`, so no item is ever dropped.
+pub(crate) fn encode_item(item: &syn::Item, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSItem {
+    match item {
+        syn::Item::Use(u) => ARSItem::Use(use_statements::from_syn_use(u, source)),
+        syn::Item::ExternCrate(e) => ARSItem::Use(use_statements::from_syn_extern_crate(e, source)),
+        syn::Item::Const(c) => ARSItem::Const(constants::from_syn(c, source, unsupported)),
+        syn::Item::Static(s) => ARSItem::Static(statics::from_syn(s, source, unsupported)),
+        syn::Item::Type(ta) => ARSItem::TypeAlias(type_aliases::from_syn(ta, source, unsupported)),
+        syn::Item::Macro(m) => match macros::from_syn(m, source) {
+            Some(ars_macro) => ARSItem::Macro(ars_macro),
+            None => ARSItem::MacroInvocation(macros::invocation_from_syn(m, source)),
+        },
+        syn::Item::Struct(s) => ARSItem::Struct(structs::from_syn(s, source, unsupported)),
+        syn::Item::Union(u) => ARSItem::Union(unions::from_syn(u, source, unsupported)),
+        syn::Item::Enum(e) => ARSItem::Enum(enums::from_syn(e, source, unsupported)),
+        syn::Item::Trait(t) => ARSItem::Trait(traits::from_syn(t, source, unsupported)),
+        syn::Item::Impl(i) => ARSItem::Impl(impl_blocks::from_syn(i, source, unsupported)),
+        syn::Item::Mod(m) => {
+            let nested = match &m.content {
+                Some((brace, nested_items)) => {
+                    let inner_attrs: Vec<_> = m.attrs.iter()
+                        .filter(|attr| matches!(attr.style, syn::AttrStyle::Inner(_)))
+                        .cloned()
+                        .collect();
+                    let start = source.span_range(brace.span.open()).end;
+                    let end = source.span_range(brace.span.close()).start;
+                    let mut nested = encode_items(&inner_attrs, nested_items, source, start..end, GapStart::AfterToken);
+                    unsupported.append(&mut nested.unsupported);
+                    nested
+                }
+                None => ARSFile::default(),
+            };
+            ARSItem::Module(modules::from_syn(m, nested, source))
+        }
+        syn::Item::ForeignMod(f) => ARSItem::ForeignMod(foreign_mods::from_syn(f, source, unsupported)),
+        syn::Item::Fn(f) => ARSItem::Function(functions::from_syn(f, source, unsupported)),
+        syn::Item::TraitAlias(_) => keep_verbatim(unsupported, "trait alias", item),
+        _ => keep_verbatim(unsupported, "item", item),
+    }
+}
+
+/// Keeps an item as verbatim source, recording that it was not structured
+fn keep_verbatim(unsupported: &mut Vec<ARSUnsupported>, kind: &str, item: &syn::Item) -> ARSItem {
+    let start = item.span().start();
+    unsupported.push(ARSUnsupported {
+        kind: kind.to_string(),
+        line: start.line,
+        column: start.column,
+        detail: "kept as verbatim source".to_string(),
+    });
+    ARSItem::Verbatim(verbatim::from_syn(item, kind))
+}
+
 /// Encodes a file to the .ars format using RON.
-pub fn encode_file(lines: &[String], output_path: &std::path::Path) -> std::io::Result<()> {
-    let ars_file = encode(lines);
-    let ron_string = ron::to_string(&ars_file).expect("Failed to serialize to RON");
-    fs::write(output_path, ron_string)
+///
+/// With `lossless` set the source text is stored as well, see `encode_lossless`.
+/// Returns the constructs that could not be fully represented.
+pub fn encode_file(input_path: &Path, output_path: &Path, lossless: bool) -> Result<Vec<ARSUnsupported>, WaltError> {
+    let encode = || {
+        let source = fs::read_to_string(input_path)?;
+        let mut ars_file = if lossless { encode_lossless(&source)? } else { encode_source(&source)? };
+        ars_file.header.source_path = Some(fs::canonicalize(input_path)?.display().to_string());
+        Ok::<_, WaltError>(ars_file)
+    };
+    let ars_file = encode().map_err(|e| e.with_path(input_path))?;
+
+    let ron_string = to_ron(&ars_file)?;
+    fs::write(output_path, ron_string).map_err(|e| WaltError::from(e).with_path(output_path))?;
+    Ok(ars_file.unsupported)
 }
 
-pub fn encode_project(input_dir: &std::path::Path, output_dir: &std::path::Path) -> std::io::Result<()> {
+/// Recursively encodes all `.rs` files in a directory, preserving structure.
+///
+/// Returns the unsupported constructs found, paired with the file they were found in.
+pub fn encode_project(input_dir: &Path, output_dir: &Path, lossless: bool) -> Result<Vec<(PathBuf, ARSUnsupported)>, WaltError> {
     if !output_dir.exists() {
-        fs::create_dir_all(output_dir)?;
+        fs::create_dir_all(output_dir).map_err(|e| WaltError::from(e).with_path(output_dir))?;
     }
 
-    for entry in std::fs::read_dir(input_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    let mut unsupported = Vec::new();
+    let entries = fs::read_dir(input_dir).map_err(|e| WaltError::from(e).with_path(input_dir))?;
+    for entry in entries {
+        let path = entry.map_err(|e| WaltError::from(e).with_path(input_dir))?.path();
 
         if path.is_dir() {
             let sub_output = output_dir.join(path.file_name().unwrap());
-            encode_project(&path, &sub_output)?;
-        } else if path.extension().map_or(false, |e| e == "rs") {
-            let content = std::fs::read_to_string(&path)?;
-            let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-            let new_extension = path.extension().map_or("ars", |_| "ars");
-            let output_path = output_dir.join(path.with_extension(new_extension).file_name().unwrap());
-            encode_file(&lines, &output_path)?;
+            unsupported.extend(encode_project(&path, &sub_output, lossless)?);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            let output_path = output_dir.join(path.with_extension("ars").file_name().unwrap());
+            let found = encode_file(&path, &output_path, lossless)?;
+            unsupported.extend(found.into_iter().map(|u| (path.clone(), u)));
         }
     }
-    Ok(())
+    Ok(unsupported)
 }
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::ars_file::ARSUnsupported;
+
+/// Errors returned by the encoder, decoder and verifier.
+///
+/// Each variant carries the path of the file involved once it is known;
+/// functions working on source text leave it empty and the file-level
+/// functions fill it in.
+#[derive(Debug)]
+pub enum WaltError {
+    /// Reading or writing a file failed
+    Io {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    /// The input is not valid Rust
+    Parse {
+        path: Option<PathBuf>,
+        line: usize,   // 1-based
+        column: usize, // 1-based
+        message: String,
+    },
+    /// The .ars input is not valid RON
+    Ron {
+        path: Option<PathBuf>,
+        line: usize,   // 1-based, 0 when RON reports no position
+        column: usize, // 1-based, 0 when RON reports no position
+        message: String,
+    },
+    /// An .ars file could not be serialized from the encoded source
+    Serialize {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// The .ars file was written in a format version this build does not know
+    Version {
+        path: Option<PathBuf>,
+        found: u32,
+        supported: u32, // Newest version this build reads
+    },
+    /// The .ars file is from an older format version and cannot be upgraded
+    Migration {
+        path: Option<PathBuf>,
+        found: u32,
+        reason: String,
+    },
+    /// The input uses constructs the .ars model cannot represent
+    Unsupported {
+        path: Option<PathBuf>,
+        constructs: Vec<ARSUnsupported>,
+    },
+}
+
+impl WaltError {
+    /// The file the error is about, if known
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            WaltError::Io { path, .. }
+            | WaltError::Parse { path, .. }
+            | WaltError::Ron { path, .. }
+            | WaltError::Serialize { path, .. }
+            | WaltError::Version { path, .. }
+            | WaltError::Migration { path, .. }
+            | WaltError::Unsupported { path, .. } => path.as_deref(),
+        }
+    }
+
+    /// Sets the file the error is about, keeping a more specific one already set.
+    pub fn with_path(mut self, file: &Path) -> Self {
+        match &mut self {
+            WaltError::Io { path, .. }
+            | WaltError::Parse { path, .. }
+            | WaltError::Ron { path, .. }
+            | WaltError::Serialize { path, .. }
+            | WaltError::Version { path, .. }
+            | WaltError::Migration { path, .. }
+            | WaltError::Unsupported { path, .. } => {
+                if path.is_none() {
+                    *path = Some(file.to_path_buf());
+                }
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for WaltError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.path() {
+            write!(f, "{:?}:", path)?;
+        }
+        match self {
+            WaltError::Io { source, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", source)
+            }
+            WaltError::Parse { line, column, message, .. } => {
+                write!(f, "{}:{}: invalid Rust: {}", line, column, message)
+            }
+            WaltError::Ron { line: 0, message, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, "invalid RON: {}", message)
+            }
+            WaltError::Ron { line, column, message, .. } => {
+                write!(f, "{}:{}: invalid RON: {}", line, column, message)
+            }
+            WaltError::Serialize { message, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, "cannot write .ars: {}", message)
+            }
+            WaltError::Version { found, supported, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, ".ars format version {} is newer than the supported version {}", found, supported)
+            }
+            WaltError::Migration { found, reason, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, "cannot upgrade .ars format version {}: {}", found, reason)
+            }
+            WaltError::Unsupported { constructs, .. } => {
+                if self.path().is_some() {
+                    write!(f, " ")?;
+                }
+                write!(f, "{} unsupported construct(s)", constructs.len())?;
+                for u in constructs {
+                    write!(f, "\n  {}:{}: {}: {}", u.line, u.column + 1, u.kind, u.detail)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for WaltError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WaltError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WaltError {
+    fn from(source: std::io::Error) -> Self {
+        WaltError::Io { path: None, source }
+    }
+}
+
+impl From<syn::Error> for WaltError {
+    fn from(error: syn::Error) -> Self {
+        let start = error.span().start();
+        WaltError::Parse {
+            path: None,
+            line: start.line,
+            column: start.column + 1,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<ron::error::SpannedError> for WaltError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        WaltError::Ron {
+            path: None,
+            line: error.position.line,
+            column: error.position.col,
+            message: error.code.to_string(),
+        }
+    }
+}
+
+/// Reading .ars text reports a `SpannedError`, so a bare `ron::Error` comes from writing it
+impl From<ron::Error> for WaltError {
+    fn from(error: ron::Error) -> Self {
+        WaltError::Serialize {
+            path: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_carry_positions() {
+        let parse = WaltError::from(syn::parse_file("fn main() {\n    let = 5;\n}").err().unwrap());
+        assert!(matches!(parse, WaltError::Parse { line: 2, column: 9, .. }), "{:?}", parse);
+
+        let ron = WaltError::from(ron::from_str::<crate::ars_file::ARSFile>("\n  garbage").unwrap_err())
+            .with_path(Path::new("a.ars"));
+        assert!(matches!(ron, WaltError::Ron { line: 2, .. }), "{:?}", ron);
+        assert!(ron.to_string().starts_with("\"a.ars\":2:"), "{}", ron);
+
+        let serialize = WaltError::from(ron::Error::ExceededRecursionLimit).with_path(Path::new("a.rs"));
+        assert!(matches!(serialize, WaltError::Serialize { .. }), "{:?}", serialize);
+        assert!(serialize.to_string().starts_with("\"a.rs\": cannot write .ars:"), "{}", serialize);
+    }
+}
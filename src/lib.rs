@@ -3,3 +3,7 @@ pub mod project_scanner;
 pub mod encoder;
 pub mod decoder;
 pub mod ars_file;
+pub mod error;
+pub mod migrate;
+pub mod source_map;
+pub mod verifier;
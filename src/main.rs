@@ -1,67 +1,348 @@
 // src/main.rs
 
-use std::path::PathBuf;
-use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use walt_v1::ars_file::ARSUnsupported;
+use walt_v1::error::WaltError;
 use walt_v1::encoder;
 use walt_v1::decoder;
+use walt_v1::migrate;
+use walt_v1::verifier::{self, VerifyLevel, VerifyReport};
+
+/// Exit codes, one per class of failure
+const EXIT_FAILURE: u8 = 1;        // anything not covered below
+// 2 is used by clap for usage errors
+const EXIT_IO: u8 = 3;             // reading or writing a file failed
+const EXIT_INVALID_INPUT: u8 = 4;  // the input is not valid Rust or .ars
+const EXIT_OUTPUT_EXISTS: u8 = 5;  // the output exists and --force was not given
+const EXIT_LOSS: u8 = 6;           // `verify` found a lossy roundtrip
+const EXIT_UNSUPPORTED: u8 = 7;    // `encode --strict` found constructs it cannot represent
+const EXIT_SERIALIZE: u8 = 8;      // the encoded source could not be written as .ars
+
+/// Encode Rust source files or projects to .ars and back
+#[derive(Parser)]
+#[command(name = "walt", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+#[derive(Args)]
+struct GlobalArgs {
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print what is being done to each path
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Overwrite existing output files
+    #[arg(short, long, global = true)]
+    force: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode a .rs file, or a directory of them, to .ars
+    Encode {
+        /// Input .rs file or directory
+        input: PathBuf,
+        /// Output .ars file or directory
+        output: PathBuf,
+        /// Also store the source text so decoding is byte-for-byte identical
+        #[arg(long)]
+        lossless: bool,
+        /// Fail instead of warning when a construct cannot be represented
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Decode an .ars file, or a directory of them, back to .rs
+    Decode {
+        /// Input .ars file or directory
+        input: PathBuf,
+        /// Output .rs file or directory
+        output: PathBuf,
+    },
+    /// Upgrade .ars files written by older versions of walt, in place
+    Migrate {
+        /// .ars file or directory
+        input: PathBuf,
+    },
+    /// Roundtrip .rs files in memory and report anything lost
+    Verify {
+        /// Input .rs file or directory
+        input: PathBuf,
+        /// Verify lossless encoding instead of the structured encoding
+        #[arg(long)]
+        lossless: bool,
+        /// How strict the roundtrip has to be to pass
+        #[arg(long, value_enum, default_value_t = Level::Tokens)]
+        level: Level,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Level {
+    Bytes,
+    Tokens,
+    Items,
+}
+
+impl From<Level> for VerifyLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Bytes => VerifyLevel::Bytes,
+            Level::Tokens => VerifyLevel::Tokens,
+            Level::Items => VerifyLevel::Items,
+        }
+    }
+}
+
+/// The output already exists and --force was not given
+#[derive(Debug)]
+struct OutputExists(PathBuf);
+
+impl std::fmt::Display for OutputExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} already exists (use --force to overwrite)", self.0)
+    }
+}
+
+impl std::error::Error for OutputExists {}
+
+/// `verify` found files that do not roundtrip at the requested level
+#[derive(Debug)]
+struct RoundtripLoss {
+    failed: usize,
+    total: usize,
+    level: VerifyLevel,
+}
+
+impl std::fmt::Display for RoundtripLoss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} files lost information at the {:?} level", self.failed, self.total, self.level)
+    }
+}
+
+impl std::error::Error for RoundtripLoss {}
 
 /// CLI for encoding/decoding Rust source files or projects
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 4 {
-        eprintln!("Usage:");
-        eprintln!("  {} encode <input.rs|input_dir> <output.ars|output_dir>", args[0]);
-        eprintln!("  {} decode <input.ars|input_dir> <output.rs|output_dir>", args[0]);
-        std::process::exit(1);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("❌ Error: {:#}", error);
+            ExitCode::from(exit_code(&error))
+        }
     }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let global = &cli.global;
+    match &cli.command {
+        Command::Encode { input, output, lossless, strict } => {
+            check_output(output, global.force)?;
+            let unsupported = if input.is_file() {
+                let found = encoder::encode_file(input, output, *lossless).context("failed to encode")?;
+                found.into_iter().map(|u| (input.clone(), u)).collect()
+            } else if input.is_dir() {
+                encoder::encode_project(input, output, *lossless)
+                    .with_context(|| format!("failed to encode project {:?}", input))?
+            } else {
+                return Err(missing_input(input));
+            };
+
+            if *strict && !unsupported.is_empty() {
+                return Err(strict_failure(unsupported));
+            }
+            if !global.quiet {
+                for (path, u) in &unsupported {
+                    print_unsupported(path, u);
+                }
+            }
+            if global.verbose {
+                println!("{:?} -> {:?}{}", input, output, if *lossless { " (lossless)" } else { "" });
+            }
+            done(global, "encode");
+        }
 
-    let command = &args[1];
-    let input_path = PathBuf::from(&args[2]);
-    let output_path = PathBuf::from(&args[3]);
-
-    match command.as_str() {
-        "encode" => {
-            if input_path.is_file() {
-                // Single file
-                let content = std::fs::read_to_string(&input_path)
-                    .expect("Failed to read input file");
-                let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-                encoder::encode_file(&lines, &output_path)
-                    .expect("Encoding failed");
-            } else if input_path.is_dir() {
-                // Directory/project
-                encoder::encode_project(&input_path, &output_path)
-                    .expect("Encoding project failed");
+        Command::Decode { input, output } => {
+            check_output(output, global.force)?;
+            if input.is_file() {
+                decoder::decode_file(input, output).context("failed to decode")?;
+            } else if input.is_dir() {
+                decoder::decode_project(input, output)
+                    .with_context(|| format!("failed to decode project {:?}", input))?;
             } else {
-                eprintln!("Input path does not exist: {:?}", input_path);
-                std::process::exit(1);
+                return Err(missing_input(input));
+            }
+
+            if global.verbose {
+                println!("{:?} -> {:?}", input, output);
             }
+            done(global, "decode");
         }
 
-        "decode" => {
-            if input_path.is_file() {
-                // Single file
-                let content = std::fs::read_to_string(&input_path)
-                    .expect("Failed to read encoded input file");
-                let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-                decoder::decode_file(&lines, &output_path)
-                    .expect("Decoding failed");
-            } else if input_path.is_dir() {
-                decoder::decode_project(&input_path, &output_path)
-                    .expect("Decoding project failed");
+        Command::Migrate { input } => {
+            let migrated = if input.is_file() {
+                let from = migrate::migrate_file(input).context("failed to migrate")?;
+                vec![(input.clone(), from)]
+            } else if input.is_dir() {
+                migrate::migrate_project(input)
+                    .with_context(|| format!("failed to migrate project {:?}", input))?
             } else {
-                eprintln!("Input path does not exist: {:?}", input_path);
-                std::process::exit(1);
+                return Err(missing_input(input));
+            };
+
+            for (path, from) in &migrated {
+                match from {
+                    Some(version) if !global.quiet => println!("{:?}: upgraded from format version {}", path, version),
+                    None if global.verbose => println!("{:?}: already current", path),
+                    _ => {}
+                }
             }
+            done(global, "migrate");
+        }
+
+        Command::Verify { input, lossless, level } => {
+            verify(global, input, *lossless, (*level).into())?;
+            done(global, "verify");
+        }
+    }
+    Ok(())
+}
+
+/// Roundtrips files in memory and fails if any lose information at `level`
+fn verify(global: &GlobalArgs, input: &Path, lossless: bool, level: VerifyLevel) -> Result<()> {
+    let reports = if input.is_file() {
+        let report = verifier::verify_file(input, lossless).context("failed to verify")?;
+        vec![(input.to_path_buf(), report)]
+    } else if input.is_dir() {
+        verifier::verify_project(input, lossless)
+            .with_context(|| format!("failed to verify project {:?}", input))?
+    } else {
+        return Err(missing_input(input));
+    };
+
+    let mut failed = 0;
+    for (path, report) in &reports {
+        let passed = report.passes(level);
+        if !passed {
+            failed += 1;
+        }
+        if global.verbose || (!passed && !global.quiet) {
+            print_report(path, report, level);
         }
+    }
+
+    if failed > 0 {
+        bail!(RoundtripLoss { failed, total: reports.len(), level });
+    }
+    Ok(())
+}
+
+/// Refuses to overwrite an existing file, or write into a non-empty directory, without --force
+fn check_output(output: &Path, force: bool) -> Result<()> {
+    if force {
+        return Ok(());
+    }
+    let occupied = if output.is_dir() {
+        std::fs::read_dir(output)
+            .with_context(|| format!("failed to read {:?}", output))?
+            .next()
+            .is_some()
+    } else {
+        output.exists()
+    };
+    if occupied {
+        bail!(OutputExists(output.to_path_buf()));
+    }
+    Ok(())
+}
 
-        _ => {
-            eprintln!("Unknown command: {}", command);
-            std::process::exit(1);
+/// Reports the unsupported constructs of the first file that has any
+fn strict_failure(unsupported: Vec<(PathBuf, ARSUnsupported)>) -> anyhow::Error {
+    let path = unsupported[0].0.clone();
+    let constructs = unsupported
+        .into_iter()
+        .filter(|(file, _)| *file == path)
+        .map(|(_, u)| u)
+        .collect();
+    anyhow::Error::new(WaltError::Unsupported { path: Some(path), constructs })
+}
+
+fn missing_input(input: &Path) -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("input path does not exist: {:?}", input),
+    ))
+}
+
+/// Maps the first recognised error in the chain to its exit code
+fn exit_code(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if cause.is::<OutputExists>() {
+            return EXIT_OUTPUT_EXISTS;
+        }
+        if cause.is::<RoundtripLoss>() {
+            return EXIT_LOSS;
+        }
+        if let Some(walt_error) = cause.downcast_ref::<WaltError>() {
+            return match walt_error {
+                WaltError::Io { .. } => EXIT_IO,
+                WaltError::Parse { .. }
+                | WaltError::Ron { .. }
+                | WaltError::Version { .. }
+                | WaltError::Migration { .. } => EXIT_INVALID_INPUT,
+                WaltError::Unsupported { .. } => EXIT_UNSUPPORTED,
+                WaltError::Serialize { .. } => EXIT_SERIALIZE,
+            };
+        }
+        if cause.is::<std::io::Error>() {
+            return EXIT_IO;
         }
     }
+    EXIT_FAILURE
+}
 
-    println!("✅ {} completed successfully!", command);
+fn done(global: &GlobalArgs, command: &str) {
+    if !global.quiet {
+        println!("✅ {} completed successfully!", command);
+    }
+}
+
+fn print_unsupported(path: &Path, u: &ARSUnsupported) {
+    eprintln!("⚠️  {:?}:{}:{}: {}: {}", path, u.line, u.column + 1, u.kind, u.detail);
+}
+
+fn print_report(path: &Path, report: &VerifyReport, level: VerifyLevel) {
+    let mark = |ok: bool| if ok { "identical" } else { "differ" };
+    let status = if report.passes(level) { "✅" } else { "❌" };
+    println!(
+        "{} {:?}: bytes {}, tokens {}, items {}, comments {}",
+        status,
+        path,
+        mark(report.bytes_identical),
+        mark(report.tokens_identical),
+        mark(report.items_identical()),
+        mark(report.comments_identical),
+    );
+    if let Some(error) = &report.reparse_error {
+        println!("    decoded source does not parse: {}", error);
+    }
+    for mismatch in &report.item_mismatches {
+        println!("    {}", mismatch);
+    }
+    for comment in &report.lost_comments {
+        println!("    comment lost: {}", comment.lines().next().unwrap_or_default());
+    }
 }
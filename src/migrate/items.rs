@@ -0,0 +1,519 @@
+use serde::Deserialize;
+use crate::ars_file::ARSItem;
+use crate::encoder;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::{
+    statements::{self, ARSStatement}, trivia::{self, ARSTrivia},
+    verbatim::ARSVerbatimItem,
+};
+use super::leaves;
+use super::tree::Value;
+
+/// First format version storing statements as `ARSStatement` variants rather than source text
+pub const STRUCTURED_STATEMENTS_VERSION: u32 = 2;
+/// First format version splitting function signatures into attributes, qualifiers, generics and parameters
+pub const STRUCTURED_SIGNATURES_VERSION: u32 = 3;
+/// First format version storing impl members as `ARSImplItem` rather than source text
+pub const STRUCTURED_IMPL_ITEMS_VERSION: u32 = 4;
+/// First format version storing trait members as `ARSTraitItem` rather than source text
+pub const STRUCTURED_TRAIT_ITEMS_VERSION: u32 = 5;
+/// First format version storing enum variants as `ARSVariant` rather than source text
+pub const STRUCTURED_VARIANTS_VERSION: u32 = 6;
+/// First format version storing struct fields as `ARSField` rather than source text
+pub const STRUCTURED_FIELDS_VERSION: u32 = 7;
+/// First format version storing inline module bodies as nested items rather than source text
+pub const STRUCTURED_MODULES_VERSION: u32 = 8;
+/// First format version storing generics as `ARSGenerics` rather than strings
+pub const STRUCTURED_GENERICS_VERSION: u32 = 9;
+/// First format version storing `macro_rules!` bodies as arms rather than source text
+pub const STRUCTURED_MACROS_VERSION: u32 = 10;
+/// First format version storing attributes as `ARSAttribute` rather than source text
+pub const STRUCTURED_ATTRIBUTES_VERSION: u32 = 11;
+/// First format version storing use paths as `ARSUseTree` rather than strings
+pub const STRUCTURED_USE_VERSION: u32 = 13;
+
+/// Converts each item of a list from the shape it had in `version`.
+pub fn upgrade_items(items: &mut [Value], version: u32) -> Result<(), String> {
+    items.iter_mut().try_for_each(|item| upgrade_item(item, version))
+}
+
+/// Converts an `ARSItem` from the shape it had in `version`.
+///
+/// Items stored as source text in that version are rendered to source
+/// again and encoded with the current types. Other items are left for
+/// `leaves::upgrade`, after converting the items nested in them.
+pub fn upgrade_item(item: &mut Value, version: u32) -> Result<(), String> {
+    let Some((kind, old)) = item.newtype_parts_mut() else {
+        return Err(format!("expected an item, found `{}`", item));
+    };
+    let kind = kind.to_string();
+    let converted = match kind.as_str() {
+        "Use" if version < STRUCTURED_USE_VERSION => Some(from_source(old.to::<ARSUseV12>()?.source())),
+        "Const" if version < STRUCTURED_ATTRIBUTES_VERSION => Some(from_source(old.to::<ARSConstV10>()?.source())),
+        "Static" if version < STRUCTURED_ATTRIBUTES_VERSION => Some(from_source(old.to::<ARSStaticV10>()?.source())),
+        "TypeAlias" if version < STRUCTURED_GENERICS_VERSION => Some(from_source(old.to::<ARSTypeAliasV8>()?.source())),
+        "Macro" if version < STRUCTURED_MACROS_VERSION => Some(from_source(old.to::<ARSMacroV9>()?.source())),
+        "Struct" if version < STRUCTURED_FIELDS_VERSION => Some(from_source(old.to::<ARSStructV6>()?.source())),
+        "Enum" if version < STRUCTURED_VARIANTS_VERSION => Some(from_source(old.to::<ARSEnumV5>()?.source())),
+        "Trait" if version < STRUCTURED_TRAIT_ITEMS_VERSION => Some(from_source(old.to::<ARSTraitV4>()?.source())),
+        "Impl" if version < STRUCTURED_IMPL_ITEMS_VERSION => Some(from_source(old.to::<ARSImplV3>()?.source())),
+        "Module" if version < STRUCTURED_MODULES_VERSION => Some(from_source(old.to::<ARSModuleV7>()?.source())),
+        "Function" if version < STRUCTURED_SIGNATURES_VERSION => Some(old_function(old, version)?),
+        _ => None,
+    };
+    if let Some(converted) = converted {
+        *item = Value::of(&converted)?;
+        return Ok(());
+    }
+
+    if kind == "Module" {
+        if let Some(Value::List(items)) = old.field_mut("items") {
+            return upgrade_items(items, version);
+        }
+    }
+    upgrade_nested_items(old, version)
+}
+
+/// Converts the items declared in statements anywhere under `value`.
+fn upgrade_nested_items(value: &mut Value, version: u32) -> Result<(), String> {
+    let mut result = Ok(());
+    value.visit(&mut |value| match value.as_newtype_mut("Item") {
+        Some(item) => {
+            if result.is_ok() {
+                result = upgrade_item(item, version);
+            }
+            false
+        }
+        None => true,
+    });
+    result
+}
+
+/// An old item as the source text it stood for, with the trivia around it
+struct OldSource {
+    kind: &'static str,
+    text: String,
+    trivia: ARSTrivia, // Leading and trailing only, closing trivia is part of `text`
+}
+
+/// Encodes an old item again from its source text.
+///
+/// Text that no longer parses as a single item, as the first regex-based
+/// encoder could store, is kept as a verbatim item.
+fn from_source(old: OldSource) -> ARSItem {
+    let mut item = match syn::parse_file(&old.text) {
+        Ok(file) if file.items.len() == 1 && file.attrs.is_empty() => {
+            encoder::encode_item(&file.items[0], &SourceMap::new(&old.text), &mut Vec::new())
+        }
+        _ => ARSItem::Verbatim(ARSVerbatimItem {
+            kind: old.kind.to_string(),
+            content: old.text,
+            trivia: ARSTrivia::default(),
+        }),
+    };
+    let trivia = item.trivia_mut();
+    trivia.leading = old.trivia.leading;
+    trivia.trailing = old.trivia.trailing;
+    item
+}
+
+/// Attribute lines and visibility, as items started with before attributes were structured
+fn head(attributes: &[String], visibility: &Option<String>) -> String {
+    let mut text: String = attributes.iter().map(|attr| format!("{}\n", attr)).collect();
+    if let Some(vis) = visibility.as_deref().filter(|vis| !vis.is_empty()) {
+        text.push_str(vis);
+        text.push(' ');
+    }
+    text
+}
+
+/// Elements of a braced or parenthesized list, each on its own line with its comments.
+///
+/// Every element but the last is followed by a comma; the last only with `trailing_comma`.
+fn list_lines(
+    elements: &[String],
+    element_trivia: &[ARSTrivia],
+    closing: &[trivia::ARSTriviaPiece],
+    trailing_comma: bool,
+) -> String {
+    let mut lines = String::new();
+    for (i, element) in elements.iter().enumerate() {
+        let element_trivia = element_trivia.get(i).cloned().unwrap_or_default();
+        let comma = if trailing_comma || i + 1 < elements.len() { "," } else { "" };
+        lines.push_str(&trivia::render_lines(&element_trivia.leading));
+        lines.push_str(&trivia::with_trailing(&format!("{}{}", element, comma), &element_trivia));
+        lines.push('\n');
+    }
+    lines.push_str(&trivia::render_lines(closing));
+    lines.trim_end_matches('\n').to_string()
+}
+
+/// Members of an impl or trait body, each as the source text stored for it
+fn member_lines(members: &[String], closing: &[trivia::ARSTriviaPiece]) -> String {
+    let mut lines: String = members.iter().map(|member| format!("{}\n", member)).collect();
+    lines.push_str(&trivia::render_lines(closing));
+    lines.trim_end_matches('\n').to_string()
+}
+
+/// Leading and trailing trivia, leaving the closing trivia to the item text
+fn outer_trivia(trivia: ARSTrivia) -> ARSTrivia {
+    ARSTrivia { leading: trivia.leading, trailing: trivia.trailing, closing: Vec::new() }
+}
+
+/// A use or extern crate before `STRUCTURED_USE_VERSION`, with its path as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSUse")]
+struct ARSUseV12 {
+    stmt_type: String, // "use" or "extern"
+    path: String,      // Everything between `use` and `;`, alias included
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSUseV12 {
+    fn source(self) -> OldSource {
+        let text = match self.stmt_type.as_str() {
+            "extern" => format!("extern crate {};", self.path),
+            _ => format!("use {};", self.path),
+        };
+        OldSource { kind: "use", text, trivia: self.trivia }
+    }
+}
+
+/// A const before `STRUCTURED_ATTRIBUTES_VERSION`, all source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSConst")]
+struct ARSConstV10 {
+    name: String,
+    ty: String,
+    value: String,
+    visibility: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSConstV10 {
+    fn source(self) -> OldSource {
+        let text = format!("{}const {}: {} = {};", head(&self.attributes, &self.visibility), self.name, self.ty, self.value);
+        OldSource { kind: "const", text, trivia: self.trivia }
+    }
+}
+
+/// A static before `STRUCTURED_ATTRIBUTES_VERSION`, all source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSStatic")]
+struct ARSStaticV10 {
+    name: String,
+    ty: String,
+    value: String,
+    mutable: bool,
+    visibility: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSStaticV10 {
+    fn source(self) -> OldSource {
+        let mutability = if self.mutable { "mut " } else { "" };
+        let text = format!(
+            "{}static {}{}: {} = {};",
+            head(&self.attributes, &self.visibility), mutability, self.name, self.ty, self.value,
+        );
+        OldSource { kind: "static", text, trivia: self.trivia }
+    }
+}
+
+/// A type alias before `STRUCTURED_GENERICS_VERSION`, all source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSTypeAlias")]
+struct ARSTypeAliasV8 {
+    name: String, // Followed by the generics in the first files
+    original_type: String,
+    visibility: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSTypeAliasV8 {
+    fn source(self) -> OldSource {
+        let text = format!("{}type {} = {};", head(&self.attributes, &self.visibility), self.name, self.original_type);
+        OldSource { kind: "type alias", text, trivia: self.trivia }
+    }
+}
+
+/// A `macro_rules!` before `STRUCTURED_MACROS_VERSION`, with its body as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSMacro")]
+struct ARSMacroV9 {
+    name: String,
+    body: String, // Between the braces
+    attributes: Vec<String>,
+    visibility: Option<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSMacroV9 {
+    fn source(self) -> OldSource {
+        let body = join_lifetimes(self.body.trim());
+        let text = format!("{}macro_rules! {} {{ {} }}", head(&self.attributes, &self.visibility), self.name, body);
+        OldSource { kind: "macro", text, trivia: self.trivia }
+    }
+}
+
+/// Joins the `' a` that older versions printed for lifetimes in macro
+/// bodies, including those in the bodies of modules, back into `'a`, which
+/// is the only form that lexes.
+///
+/// Comments and string and character literals are copied as they are.
+fn join_lifetimes(body: &str) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut joined = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let literal_len = match chars[i] {
+            '/' if chars.get(i + 1) == Some(&'/') => Some(chars[i..].iter().take_while(|&&c| c != '\n').count()),
+            '/' if chars.get(i + 1) == Some(&'*') => chars[i + 2..].windows(2).position(|window| window == ['*', '/']).map(|end| end + 4),
+            '"' => string_len(&chars[i..]),
+            'r' if i == 0 || !is_ident_char(chars[i - 1]) => raw_string_len(&chars[i..]),
+            '\'' => char_len(&chars[i..]),
+            _ => None,
+        };
+        if let Some(len) = literal_len {
+            joined.extend(&chars[i..i + len]);
+            i += len;
+            continue;
+        }
+        joined.push(chars[i]);
+        if chars[i] == '\'' && chars.get(i + 1) == Some(&' ') && chars.get(i + 2).is_some_and(|&c| is_ident_char(c)) {
+            i += 1;
+        }
+        i += 1;
+    }
+    joined
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Length of the `".."` literal `chars` starts with, escapes included
+fn string_len(chars: &[char]) -> Option<usize> {
+    let mut i = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Length of the `r#".."#` literal `chars` starts with
+fn raw_string_len(chars: &[char]) -> Option<usize> {
+    let hashes = chars[1..].iter().take_while(|&&c| c == '#').count();
+    if chars.get(1 + hashes) != Some(&'"') {
+        return None;
+    }
+    let close: Vec<char> = std::iter::once('"').chain(std::iter::repeat_n('#', hashes)).collect();
+    let start = 2 + hashes;
+    chars[start..].windows(close.len()).position(|window| window == close.as_slice())
+        .map(|end| start + end + close.len())
+}
+
+/// Length of the `'x'` or `'\n'` literal `chars` starts with, if it is not a lifetime
+fn char_len(chars: &[char]) -> Option<usize> {
+    match chars.get(1)? {
+        '\\' => chars.iter().skip(3).position(|&c| c == '\'').map(|end| end + 4),
+        _ if chars.get(2) == Some(&'\'') => Some(3),
+        _ => None,
+    }
+}
+
+/// A struct before `STRUCTURED_FIELDS_VERSION`, with its fields as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSStruct")]
+struct ARSStructV6 {
+    name: String,
+    fields: Vec<String>,
+    is_tuple: bool,
+    is_unit: bool,
+    visibility: Option<String>,
+    generics: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    field_trivia: Vec<ARSTrivia>, // Parallel to `fields`
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSStructV6 {
+    fn source(self) -> OldSource {
+        let mut text = format!(
+            "{}struct {}{}",
+            head(&self.attributes, &self.visibility), self.name, self.generics.as_deref().unwrap_or(""),
+        );
+        if self.is_unit {
+            text.push(';');
+        } else {
+            let fields = list_lines(&self.fields, &self.field_trivia, &self.trivia.closing, !self.is_tuple);
+            let (open, close) = if self.is_tuple { ("(", ");") } else { (" {", "}") };
+            text.push_str(&format!("{}\n{}\n{}", open, fields, close));
+        }
+        OldSource { kind: "struct", text, trivia: outer_trivia(self.trivia) }
+    }
+}
+
+/// An enum before `STRUCTURED_VARIANTS_VERSION`, with its variants as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSEnum")]
+struct ARSEnumV5 {
+    name: String,
+    variants: Vec<String>,
+    visibility: Option<String>,
+    generics: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    variant_trivia: Vec<ARSTrivia>, // Parallel to `variants`
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSEnumV5 {
+    fn source(self) -> OldSource {
+        let text = format!(
+            "{}enum {}{} {{\n{}\n}}",
+            head(&self.attributes, &self.visibility),
+            self.name,
+            self.generics.as_deref().unwrap_or(""),
+            list_lines(&self.variants, &self.variant_trivia, &self.trivia.closing, false),
+        );
+        OldSource { kind: "enum", text, trivia: outer_trivia(self.trivia) }
+    }
+}
+
+/// A trait before `STRUCTURED_TRAIT_ITEMS_VERSION`, with its members as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSTrait")]
+struct ARSTraitV4 {
+    name: String,
+    items: Vec<String>, // Whole members, or lines of the body in the first files
+    visibility: Option<String>,
+    generics: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSTraitV4 {
+    fn source(self) -> OldSource {
+        let text = format!(
+            "{}trait {}{} {{\n{}\n}}",
+            head(&self.attributes, &self.visibility),
+            self.name,
+            self.generics.as_deref().unwrap_or(""),
+            member_lines(&self.items, &self.trivia.closing),
+        );
+        OldSource { kind: "trait", text, trivia: outer_trivia(self.trivia) }
+    }
+}
+
+/// An impl block before `STRUCTURED_IMPL_ITEMS_VERSION`, with its members as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSImpl")]
+struct ARSImplV3 {
+    target: String, // Followed by the where clause, if any
+    trait_name: Option<String>,
+    items: Vec<String>, // Whole members, or lines of the body in the first files
+    generics: Option<String>,
+    attributes: Vec<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSImplV3 {
+    fn source(self) -> OldSource {
+        let mut text = format!("{}impl{}", head(&self.attributes, &None), self.generics.as_deref().unwrap_or(""));
+        if let Some(trait_name) = &self.trait_name {
+            text.push_str(&format!(" {} for", trait_name));
+        }
+        text.push_str(&format!(" {} {{\n{}\n}}", self.target, member_lines(&self.items, &self.trivia.closing)));
+        OldSource { kind: "impl", text, trivia: outer_trivia(self.trivia) }
+    }
+}
+
+/// A module before `STRUCTURED_MODULES_VERSION`, with its inline body as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSModule")]
+struct ARSModuleV7 {
+    name: String,
+    visibility: Option<String>,
+    attributes: Vec<String>,
+    inline: bool,
+    body: Option<String>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+impl ARSModuleV7 {
+    fn source(self) -> OldSource {
+        let mut text = format!("{}mod {}", head(&self.attributes, &self.visibility), self.name);
+        match &self.body {
+            Some(body) if self.inline => text.push_str(&format!(" {{\n{}\n}}", join_lifetimes(body.trim_end()))),
+            _ if self.inline => text.push_str(" {}"),
+            _ => text.push(';'),
+        }
+        OldSource { kind: "module", text, trivia: self.trivia }
+    }
+}
+
+/// A function before `STRUCTURED_SIGNATURES_VERSION`, with its signature as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSFunction")]
+struct ARSFunctionV2<S> {
+    signature: String, // Attributes included
+    body: Vec<S>,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+/// A statement before `STRUCTURED_STATEMENTS_VERSION`, as source text
+#[derive(Deserialize)]
+#[serde(rename = "ARSStatement")]
+struct ARSStatementV1 {
+    content: String,
+    #[serde(default)]
+    trivia: ARSTrivia,
+}
+
+/// Converts an old function by parsing its signature and body again.
+///
+/// Its statements are source text before `STRUCTURED_STATEMENTS_VERSION`.
+/// After that they are structured, and are brought up to the current
+/// shape first to be rendered.
+fn old_function(old: &mut Value, version: u32) -> Result<ARSItem, String> {
+    let (signature, mut body, old_trivia) = if version < STRUCTURED_STATEMENTS_VERSION {
+        let old = old.to::<ARSFunctionV2<ARSStatementV1>>()?;
+        let body = old.body.iter().map(|statement| {
+            let content = trivia::with_trailing(&statement.content, &statement.trivia);
+            format!("{}{}", trivia::render_lines(&statement.trivia.leading), content)
+        }).collect::<Vec<_>>().join("\n");
+        (old.signature, body, old.trivia)
+    } else {
+        upgrade_nested_items(old, version)?;
+        leaves::upgrade(old, version);
+        let old = old.to::<ARSFunctionV2<ARSStatement>>()?;
+        (old.signature, statements::reconstruct(&old.body), old.trivia)
+    };
+    if !old_trivia.closing.is_empty() {
+        body.push('\n');
+        body.push_str(&trivia::render_lines(&old_trivia.closing));
+    }
+
+    let text = format!("{} {{\n{}\n}}", signature, body);
+    Ok(from_source(OldSource { kind: "function", text, trivia: outer_trivia(old_trivia) }))
+}
@@ -0,0 +1,197 @@
+use syn::parse::Parser;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::{
+    attributes::{self, ARSAttribute}, expressions, generics,
+    types::{self, ARSType, ARSTypeParamBound},
+};
+use super::items::{STRUCTURED_ATTRIBUTES_VERSION, STRUCTURED_FIELDS_VERSION, STRUCTURED_GENERICS_VERSION};
+use super::tree::{Owner, Value};
+
+/// First format version storing const, static and associated const values as `ARSExpression`
+pub const STRUCTURED_VALUES_VERSION: u32 = 15;
+/// First format version storing bounds as `ARSTypeParamBound` rather than strings.
+///
+/// Types became `ARSType` in version 16, except those of generic parameters
+/// and where predicates, which followed here.
+pub const STRUCTURED_BOUNDS_VERSION: u32 = 19;
+/// First format version storing enum discriminants as `ARSExpression`
+pub const STRUCTURED_DISCRIMINANTS_VERSION: u32 = 20;
+
+/// Converts the strings that `version` stored where the current format
+/// has a structure: attributes, generics, values, types, bounds and discriminants.
+///
+/// Applies to the fields of structured items; items that were still source
+/// text are encoded again by `items::upgrade_item` instead. Fields already
+/// in their current shape are left alone.
+pub fn upgrade(value: &mut Value, version: u32) {
+    value.visit_fields(&mut |owner, fields| {
+        if version < STRUCTURED_FIELDS_VERSION {
+            upgrade_variant_fields(fields);
+        }
+        if version < STRUCTURED_ATTRIBUTES_VERSION {
+            upgrade_attributes(fields);
+        }
+        if version < STRUCTURED_GENERICS_VERSION {
+            upgrade_generics(fields);
+        }
+        if version < STRUCTURED_VALUES_VERSION {
+            upgrade_values(owner, fields);
+        }
+        if version < STRUCTURED_BOUNDS_VERSION {
+            upgrade_types(owner, fields);
+            upgrade_bounds(owner, fields);
+        }
+        if version < STRUCTURED_DISCRIMINANTS_VERSION {
+            upgrade_discriminants(fields);
+        }
+    });
+}
+
+fn field<'a>(fields: &'a mut [(String, Value)], key: &str) -> Option<&'a mut Value> {
+    fields.iter_mut().find(|(name, _)| name == key).map(|(_, value)| value)
+}
+
+fn has_field(fields: &[(String, Value)], key: &str) -> bool {
+    fields.iter().any(|(name, _)| name == key)
+}
+
+/// Replaces a string, or the string in `Some(..)`, through `convert`
+fn replace_string(value: &mut Value, convert: impl FnOnce(&str) -> Value) {
+    if let Some(text) = value.as_str() {
+        *value = convert(text);
+    } else if let Some(text) = value.as_some_str() {
+        *value = Value::some(convert(text));
+    }
+}
+
+/// The strings of a list, if it holds nothing else
+fn strings(value: &Value) -> Option<Vec<&str>> {
+    match value {
+        Value::List(values) if !values.is_empty() => values.iter().map(Value::as_str).collect(),
+        _ => None,
+    }
+}
+
+/// Gives the fields of an `ARSVariant`, which came before struct fields had
+/// docs of their own, empty docs; their doc attributes stay attributes.
+fn upgrade_variant_fields(fields: &mut [(String, Value)]) {
+    if !has_field(fields, "discriminant") {
+        return;
+    }
+    let Some(Value::List(variant_fields)) = field(fields, "fields") else { return };
+    for variant_field in variant_fields {
+        if variant_field.field("docs").is_none() {
+            variant_field.set_field("docs", Value::List(Vec::new()));
+        }
+    }
+}
+
+/// `#[..]` strings as `ARSAttribute`s
+fn upgrade_attributes(fields: &mut [(String, Value)]) {
+    let Some(value) = field(fields, "attributes") else { return };
+    let Some(texts) = strings(value) else { return };
+    let converted: Vec<_> = texts.into_iter().filter_map(attribute).collect();
+    *value = Value::List(converted.iter().filter_map(|attr| Value::of(attr).ok()).collect());
+}
+
+fn attribute(text: &str) -> Option<ARSAttribute> {
+    let parser = if text.starts_with("#!") { syn::Attribute::parse_inner } else { syn::Attribute::parse_outer };
+    parser.parse_str(text).ok()?.first().map(attributes::from_syn)
+}
+
+/// `generics:Some("<T>")` and `where_clause:Some("where ..")` as one `ARSGenerics`
+fn upgrade_generics(fields: &mut Vec<(String, Value)>) {
+    let is_old = |key: &str, fields: &[(String, Value)]| {
+        fields.iter().any(|(name, value)| name == key && (value.is_none() || value.as_some_str().is_some()))
+    };
+    if !(is_old("generics", fields) || is_old("where_clause", fields)) {
+        return;
+    }
+    let mut take = |key: &str| {
+        let index = fields.iter().position(|(name, _)| name == key)?;
+        fields.remove(index).1.as_some_str().map(str::to_string)
+    };
+    let params = take("generics").unwrap_or_default();
+    let where_clause = take("where_clause").map_or_else(String::new, |text| match text.trim_start().starts_with("where") {
+        true => text,
+        false => format!("where {}", text),
+    });
+
+    let text = format!("struct S{} {} {{}}", params, where_clause);
+    let converted = match syn::parse_str::<syn::ItemStruct>(&text) {
+        Ok(item) => generics::from_syn(&item.generics, &SourceMap::new(&text), &mut Vec::new()),
+        Err(_) => Default::default(),
+    };
+    if let Ok(value) = Value::of(&converted) {
+        fields.push(("generics".to_string(), value));
+    }
+}
+
+/// Const, static and associated const values as `ARSExpression`
+fn upgrade_values(owner: Owner, fields: &mut [(String, Value)]) {
+    if !has_field(fields, "ty") {
+        return;
+    }
+    let key = match owner {
+        Owner::Newtype("Const") if has_field(fields, "default") => "default", // A trait const
+        _ => "value",
+    };
+    if let Some(value) = field(fields, key) {
+        replace_string(value, expression);
+    }
+}
+
+/// Enum discriminants as `ARSExpression`
+fn upgrade_discriminants(fields: &mut [(String, Value)]) {
+    if let Some(value) = field(fields, "discriminant") {
+        replace_string(value, expression);
+    }
+}
+
+fn expression(text: &str) -> Value {
+    let expression = expressions::extract_ars_expressions(text).into_iter().next();
+    expression.and_then(|expression| Value::of(&expression).ok()).unwrap_or_else(|| Value::string(text))
+}
+
+/// Types as `ARSType`
+fn upgrade_types(owner: Owner, fields: &mut [(String, Value)]) {
+    for key in ["ty", "target", "original_type", "output"] {
+        if let Some(value) = field(fields, key) {
+            replace_string(value, ty);
+        }
+    }
+    // The default of a type parameter or associated type, not of a const parameter
+    if matches!(owner, Owner::Variant("Type") | Owner::Newtype("Type")) {
+        if let Some(value) = field(fields, "default") {
+            replace_string(value, ty);
+        }
+    }
+}
+
+fn ty(text: &str) -> Value {
+    let converted = match syn::parse_str::<syn::Type>(text) {
+        Ok(ty) => types::from_syn(&ty, &SourceMap::new(text), &mut Vec::new()),
+        Err(_) => ARSType::Verbatim(text.to_string()),
+    };
+    Value::of(&converted).unwrap_or_else(|_| Value::string(text))
+}
+
+/// Bounds and supertraits as `ARSTypeParamBound`, except the lifetime bounds of lifetimes
+fn upgrade_bounds(owner: Owner, fields: &mut [(String, Value)]) {
+    if owner == Owner::Variant("Lifetime") {
+        return;
+    }
+    for key in ["bounds", "supertraits"] {
+        let Some(value) = field(fields, key) else { continue };
+        let Some(texts) = strings(value) else { continue };
+        let converted: Vec<_> = texts.into_iter().map(bound).collect();
+        *value = Value::List(converted.iter().filter_map(|bound| Value::of(bound).ok()).collect());
+    }
+}
+
+fn bound(text: &str) -> ARSTypeParamBound {
+    match syn::parse_str::<syn::TypeParamBound>(text) {
+        Ok(bound) => types::bounds([&bound], &SourceMap::new(text), &mut Vec::new()).remove(0),
+        Err(_) => ARSTypeParamBound::Verbatim(text.to_string()),
+    }
+}
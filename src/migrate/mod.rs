@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use syn::parse::Parser;
+use crate::ars_file::{from_ron, to_ron, ARSFile, FORMAT_VERSION};
+use crate::error::WaltError;
+use crate::syntax_elements::attributes;
+use tree::Value;
+
+mod items;
+mod leaves;
+mod tree;
+
+/// Parses .ars text of any supported format version, upgrading it to the current one.
+///
+/// Older files are read as a RON tree and brought up to date one change
+/// of shape at a time: items that were stored as source text are encoded
+/// again from that text by `items`, and strings that have since become
+/// structures are parsed into them by `leaves`. Nothing is read from the
+/// source the file was made from, which may have changed since.
+/// Returns the file together with the format version it was stored in.
+pub fn upgrade(ron_string: &str) -> Result<(ARSFile, u32), WaltError> {
+    let version = from_ron::<VersionProbe>(ron_string)?.header.format_version;
+    if version > FORMAT_VERSION {
+        return Err(WaltError::Version { path: None, found: version, supported: FORMAT_VERSION });
+    }
+    if version == FORMAT_VERSION {
+        return Ok((from_ron(ron_string)?, version));
+    }
+
+    let convert = || {
+        let mut file = Value::parse(ron_string)?;
+        if version == 0 {
+            upgrade_headerless(&mut file);
+        }
+        if version < items::STRUCTURED_ATTRIBUTES_VERSION {
+            upgrade_file_attributes(&mut file);
+        }
+        if let Some(Value::List(items)) = file.field_mut("items") {
+            items::upgrade_items(items, version)?;
+        }
+        leaves::upgrade(&mut file, version);
+        file.to::<ARSFile>()
+    };
+    let mut ars_file = convert().map_err(|reason| WaltError::Migration { path: None, found: version, reason })?;
+    ars_file.header.format_version = FORMAT_VERSION;
+    // The stored text matches the upgraded model as it matched the old one
+    if let Some(mut source_text) = ars_file.source_text.take() {
+        if !source_text.is_unchecked() {
+            source_text.record_models(&ars_file);
+        }
+        ars_file.source_text = Some(source_text);
+    }
+    Ok((ars_file, version))
+}
+
+/// Item kinds of the per-kind lists the first files kept, in the order they were decoded
+const HEADERLESS_LISTS: [(&str, &str); 11] = [
+    ("uses", "Use"),
+    ("constants", "Const"),
+    ("statics", "Static"),
+    ("type_aliases", "TypeAlias"),
+    ("macros", "Macro"),
+    ("structs", "Struct"),
+    ("enums", "Enum"),
+    ("traits", "Trait"),
+    ("impl_blocks", "Impl"),
+    ("modules", "Module"),
+    ("functions", "Function"),
+];
+
+/// Gives a file written before headers existed a header, and moves the
+/// items of its per-kind lists, if it has them, into the item list.
+fn upgrade_headerless(file: &mut Value) {
+    let mut items = match file.remove_field("items") {
+        Some(Value::List(items)) => items,
+        _ => Vec::new(),
+    };
+    for (list, kind) in HEADERLESS_LISTS {
+        if let Some(Value::List(old)) = file.remove_field(list) {
+            items.extend(old.into_iter().map(|item| Value::newtype(kind, item)));
+        }
+    }
+    file.set_field("items", Value::List(items));
+
+    let mode = match file.field("source_text") {
+        Some(source_text) if !source_text.is_none() => "Lossless",
+        _ => "Structured",
+    };
+    let header = format!("(format_version:0,mode:{})", mode);
+    file.set_field("header", Value::parse(&header).expect("valid header"));
+}
+
+/// Converts the file attributes older files stored as
+/// `(target:None,attr_type:"inner",content:"..")`.
+///
+/// Only inner attributes belong to the file; the first files also listed
+/// every outer attribute in it, which their items keep themselves.
+fn upgrade_file_attributes(file: &mut Value) {
+    let Some(Value::List(old)) = file.field_mut("attributes") else { return };
+    let inner = old.iter().filter(|attr| attr.field("attr_type").and_then(Value::as_str) == Some("inner"));
+    let converted = inner.filter_map(|attr| {
+        let text = format!("#![{}]", attr.field("content")?.as_str()?);
+        let parsed = syn::Attribute::parse_inner.parse_str(&text).ok()?;
+        Value::of(&attributes::from_syn(parsed.first()?)).ok()
+    });
+    *old = converted.collect();
+}
+
+/// Upgrades an .ars file in place.
+///
+/// Returns the version it was upgraded from, or `None` if it was already current.
+pub fn migrate_file(path: &Path) -> Result<Option<u32>, WaltError> {
+    let migrate = || {
+        let (ars_file, version) = upgrade(&fs::read_to_string(path)?)?;
+        if version == FORMAT_VERSION {
+            return Ok(None);
+        }
+        fs::write(path, to_ron(&ars_file)?)?;
+        Ok(Some(version))
+    };
+    migrate().map_err(|e: WaltError| e.with_path(path))
+}
+
+/// Upgrades every `.ars` file under a directory in place.
+pub fn migrate_project(dir: &Path) -> Result<Vec<(PathBuf, Option<u32>)>, WaltError> {
+    let mut migrated = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| WaltError::from(e).with_path(dir))?;
+    for entry in entries {
+        let path = entry.map_err(|e| WaltError::from(e).with_path(dir))?.path();
+
+        if path.is_dir() {
+            migrated.extend(migrate_project(&path)?);
+        } else if path.extension().is_some_and(|e| e == "ars") {
+            let from = migrate_file(&path)?;
+            migrated.push((path, from));
+        }
+    }
+    Ok(migrated)
+}
+
+/// Reads only the format version, ignoring everything else in the file
+#[derive(Deserialize)]
+#[serde(rename = "ARSFile")]
+struct VersionProbe {
+    #[serde(default)]
+    header: HeaderProbe,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename = "ARSHeader")]
+struct HeaderProbe {
+    #[serde(default)]
+    format_version: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ars_file::ARSItem;
+    use crate::decoder;
+
+    #[test]
+    fn test_upgrade_per_kind_lists() {
+        let old = r#"(attributes:[],uses:[(stmt_type:"use",path:"std::fmt",alias:None,is_glob:false),(stmt_type:"use",path:"std::io::Write as _",alias:Some("_"),is_glob:false)],constants:[],statics:[],type_aliases:[],macros:[],structs:[(name:"A",fields:[],is_tuple:false,is_unit:true,visibility:None,generics:None,attributes:[])],enums:[],traits:[],impl_blocks:[],modules:[],functions:[])"#;
+        let (ars_file, version) = upgrade(old).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(ars_file.header.format_version, FORMAT_VERSION);
+        assert_eq!(decoder::decode(&ars_file), "use std::fmt;\nuse std::io::Write as _;\nstruct A;\n");
+    }
+
+    #[test]
+    fn test_items_stored_as_text_are_encoded_again() {
+        let v1_body = r#"(header:(format_version:1,mode:Lossless),items:[Function((signature:"fn f()",body:[(stmt_type:"Expr",content:"g ()")]))],source_text:Some((items:["fn f() { g() }"],tail:"\n")))"#;
+        let (ars_file, version) = upgrade(v1_body).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(ars_file.header.format_version, FORMAT_VERSION);
+        assert!(matches!(&ars_file.items[0], ARSItem::Function(f) if f.body.len() == 1));
+
+        let impl_items = r#"(header:(format_version:1),items:[Impl((target:"S",trait_name:None,items:["fn f() {}"],generics:None,attributes:[]))])"#;
+        let (ars_file, _) = upgrade(impl_items).unwrap();
+        assert_eq!(decoder::decode(&ars_file), "impl S {\n    fn f() {}\n}\n");
+    }
+
+    #[test]
+    fn test_lifetimes_in_old_macro_bodies_are_joined() {
+        let v9 = r#"(header:(format_version:9),items:[Macro((name:"m",body:"() => { fn f() -> &\' static str { \"\'\" } const C: char = \' \'; };",attributes:[],visibility:None,macro_type:"declarative"))])"#;
+        let (ars_file, _) = upgrade(v9).unwrap();
+        assert!(matches!(&ars_file.items[0], ARSItem::Macro(_)));
+        let decoded = decoder::decode(&ars_file);
+        assert!(decoded.contains("&'static str { \"'\" } const C: char = ' ';"), "{}", decoded);
+    }
+
+    #[test]
+    fn test_generic_strings_are_structured() {
+        let v16 = r#"(header:(format_version:16),items:[Struct((name:"S",fields:[(name:None,ty:Path(leading_colon:false,segments:[(name:"T")]),visibility:None,attributes:[],docs:[])],is_tuple:true,is_unit:false,visibility:None,generics:(params:[Type(name:"T",bounds:["Copy"],default:Some("i32")),Const(name:"N",ty:"usize",default:Some("2"))],trailing_comma:false,where_clause:Some((predicates:[Type(for_lifetimes:None,ty:"T",bounds:["Default"])],trailing_comma:false))),attributes:[]))])"#;
+        let (ars_file, _) = upgrade(v16).unwrap();
+        assert_eq!(decoder::decode(&ars_file), "struct S<T: Copy = i32, const N: usize = 2>(T) where T: Default;\n");
+    }
+
+    #[test]
+    fn test_discriminant_strings_are_expressions() {
+        let v19 = r#"(header:(format_version:19),items:[Enum((name:"E",variants:[(name:"A",kind:Unit,fields:[],discriminant:Some("1 << 2"),attributes:[],docs:[]),(name:"B",kind:Unit,fields:[],discriminant:None,attributes:[],docs:[])],visibility:None,attributes:[]))])"#;
+        let (ars_file, _) = upgrade(v19).unwrap();
+        let ARSItem::Enum(e) = &ars_file.items[0] else { panic!() };
+        assert!(e.variants[0].discriminant.is_some() && e.variants[1].discriminant.is_none());
+        assert_eq!(decoder::decode(&ars_file), "enum E {\n    A = 1 << 2,\n    B\n}\n");
+    }
+
+    #[test]
+    fn test_old_functions_are_converted() {
+        let v1 = r#"(header:(format_version:1),items:[Function((signature:"pub fn f(x: u8) -> u8",body:[(stmt_type:"Local",content:"let y = x;"),(stmt_type:"Expr",content:"y",trivia:(leading:[Comment("// same")],trailing:None,closing:[]))]))])"#;
+        let (ars_file, version) = upgrade(v1).unwrap();
+        assert_eq!(version, 1);
+        assert!(matches!(&ars_file.items[0], ARSItem::Function(f) if f.name == "f" && f.params.len() == 1));
+        assert_eq!(decoder::decode(&ars_file), "pub fn f(x: u8) -> u8 {\n    let y = x;\n    // same\n    y\n}\n");
+
+        let v2 = r#"(header:(format_version:2),items:[Function((signature:"fn g()",body:[Expr(expr:(expression_type:Call(function:(expression_type:Path("h")),args:[])),semicolon:true)]))])"#;
+        let (ars_file, version) = upgrade(v2).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(decoder::decode(&ars_file), "fn g() {\n    h();\n}\n");
+    }
+
+    #[test]
+    fn test_baseline_files_are_converted() {
+        // Written by the first walt, which kept most items as source strings
+        let baseline = r##"(attributes:[(target:None,attr_type:"outer",content:"derive(Debug)")],
+            uses:[(stmt_type:"use",path:"std::fmt",alias:None,is_glob:false)],
+            constants:[(name:"ORIGIN",ty:"Point",value:"Point { x: 0, y: 0 }",visibility:Some("pub"),attributes:[])],
+            statics:[(name:"COUNT",ty:"u32",value:"0",mutable:false,visibility:None,attributes:[])],
+            type_aliases:[(name:"Pair",original_type:"(i32, i32)",visibility:None,attributes:[])],
+            macros:[(name:"square",body:"($x:expr) => {\n        $x * $x\n    };",attributes:[],visibility:None,macro_type:"declarative")],
+            structs:[(name:"Point",fields:["x: i32","y: i32"],is_tuple:false,is_unit:false,visibility:Some("pub"),generics:None,attributes:["#[derive(Debug)]"])],
+            enums:[(name:"Shape",variants:["Circle(u32)","Square { side: u32 }"],visibility:Some("pub"),generics:None,attributes:[])],
+            traits:[(name:"Area",items:["fn area(&self) -> u32;"],visibility:Some("pub"),generics:None,attributes:[])],
+            impl_blocks:[(target:"Shape",trait_name:Some("Area"),items:["fn area(&self) -> u32 {","match self {","Shape::Circle(r) => 3 * square!(r),","Shape::Square { side } => side * side,","}","}"],generics:None,attributes:[],visibility:None)],
+            modules:[(name:"inner",visibility:None,attributes:[],inline:true,body:Some("pub fn helper() -> u8 {\n        1\n    }"))],
+            functions:[(signature:"fn area(&self) -> u32",body:[(stmt_type:"Expr",content:"match self { Shape :: Circle (r) => 3 * square ! (r) , Shape :: Square { side } => side * side , }")]),
+                (signature:"fn area(&self) -> u32",body:[(stmt_type:"Expr",content:"match self { Shape :: Circle (r) => 3 * square ! (r) , Shape :: Square { side } => side * side , }")]),
+                (signature:"pub fn helper() -> u8",body:[(stmt_type:"Expr",content:"1")]),
+                (signature:"fn main()",body:[(stmt_type:"Macro",content:"println ! (\"{}\" , ORIGIN . x) ;")])])"##;
+        let (ars_file, version) = upgrade(baseline).unwrap();
+        assert_eq!(version, 0);
+        let kinds: Vec<_> = ars_file.items.iter().map(|item| match item {
+            ARSItem::Use(_) => "use",
+            ARSItem::Const(_) => "const",
+            ARSItem::Static(_) => "static",
+            ARSItem::TypeAlias(_) => "type",
+            ARSItem::Macro(_) => "macro",
+            ARSItem::Struct(s) if s.fields.len() == 2 => "struct",
+            ARSItem::Enum(e) if e.variants.len() == 2 => "enum",
+            ARSItem::Trait(t) if t.items.len() == 1 => "trait",
+            ARSItem::Impl(i) if i.items.len() == 1 => "impl",
+            ARSItem::Module(m) if m.items.len() == 1 => "mod",
+            ARSItem::Function(_) => "fn",
+            _ => "other",
+        }).collect();
+        // The first walt also listed the functions of impls and modules at the top level
+        assert_eq!(kinds, ["use", "const", "static", "type", "macro", "struct", "enum", "trait", "impl", "mod", "fn", "fn", "fn", "fn"]);
+
+        let decoded = decoder::decode(&ars_file);
+        assert!(syn::parse_file(&decoded).is_ok(), "{}", decoded);
+        assert!(decoded.contains("#[derive(Debug)]\npub struct Point { x: i32, y: i32, }\n"), "{}", decoded);
+        assert!(decoded.contains("impl Area for Shape {\n    fn area(&self) -> u32 {\n        match self {\n"), "{}", decoded);
+    }
+
+    #[test]
+    fn test_newer_versions_are_rejected() {
+        let newer = "(header:(format_version:999),items:[])";
+        assert!(matches!(upgrade(newer), Err(WaltError::Version { found: 999, .. })));
+    }
+}
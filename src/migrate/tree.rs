@@ -0,0 +1,467 @@
+use std::fmt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::ars_file::{from_ron, to_ron};
+use crate::syntax_elements::expressions::with_stack;
+
+/// RON text read as a tree, keeping the enum variant names.
+///
+/// Old .ars files are told apart from current ones by which variant or
+/// field holds a string and which a structure, which `ron::Value` cannot
+/// show: it drops variant names and reads `Some(x)` like `x`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),                 // Unescaped
+    Atom(String),                // A number or char, as written
+    Named(String, Option<Body>), // A variant, `None`, `Some(..)`, `true` or `false`
+    Anon(Body),                  // A struct, tuple or unit written without a name
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+/// What is between the parentheses of a struct, tuple or variant
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    Tuple(Vec<Value>),
+    Fields(Vec<(String, Value)>),
+}
+
+/// How the fields of a struct are held by their parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Owner<'a> {
+    Plain,              // A struct, or the root
+    Variant(&'a str),   // A struct variant, `Type(name:..)`
+    Newtype(&'a str),   // A struct in a newtype variant, `Struct((name:..))`
+}
+
+impl Value {
+    /// Parses RON text, or returns where and why it cannot
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text, position: 0 };
+        let value = parser.value()?;
+        parser.skip_space();
+        if parser.position < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// The tree a value serializes to
+    pub fn of<T: Serialize>(value: &T) -> Result<Value, String> {
+        Value::parse(&to_ron(value).map_err(|e| e.to_string())?)
+    }
+
+    /// Reads the tree as a typed value
+    pub fn to<T: DeserializeOwned>(&self) -> Result<T, String> {
+        from_ron(&self.to_string()).map_err(|e| e.code.to_string())
+    }
+
+    pub fn string(text: impl Into<String>) -> Value {
+        Value::Str(text.into())
+    }
+
+    /// `Some(value)`
+    pub fn some(value: Value) -> Value {
+        Value::Named("Some".to_string(), Some(Body::Tuple(vec![value])))
+    }
+
+    /// `Kind(value)`, a newtype variant
+    pub fn newtype(kind: &str, value: Value) -> Value {
+        Value::Named(kind.to_string(), Some(Body::Tuple(vec![value])))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The string in `Some("..")`
+    pub fn as_some_str(&self) -> Option<&str> {
+        self.as_newtype("Some").and_then(Value::as_str)
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, Value::Named(name, None) if name == "None")
+    }
+
+    /// The value held by a newtype variant of the given name
+    pub fn as_newtype(&self, kind: &str) -> Option<&Value> {
+        match self {
+            Value::Named(name, Some(Body::Tuple(values))) if name == kind && values.len() == 1 => values.first(),
+            _ => None,
+        }
+    }
+
+    pub fn as_newtype_mut(&mut self, kind: &str) -> Option<&mut Value> {
+        match self {
+            Value::Named(name, Some(Body::Tuple(values))) if name == kind && values.len() == 1 => values.first_mut(),
+            _ => None,
+        }
+    }
+
+    /// The variant name and value of a newtype variant
+    pub fn newtype_parts_mut(&mut self) -> Option<(&str, &mut Value)> {
+        match self {
+            Value::Named(name, Some(Body::Tuple(values))) if values.len() == 1 => Some((name, &mut values[0])),
+            _ => None,
+        }
+    }
+
+    pub fn fields(&self) -> Option<&Vec<(String, Value)>> {
+        match self {
+            Value::Anon(Body::Fields(fields)) | Value::Named(_, Some(Body::Fields(fields))) => Some(fields),
+            _ => None,
+        }
+    }
+
+    pub fn fields_mut(&mut self) -> Option<&mut Vec<(String, Value)>> {
+        match self {
+            Value::Anon(Body::Fields(fields)) | Value::Named(_, Some(Body::Fields(fields))) => Some(fields),
+            _ => None,
+        }
+    }
+
+    pub fn field(&self, key: &str) -> Option<&Value> {
+        self.fields()?.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    pub fn field_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.fields_mut()?.iter_mut().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    /// Sets a field, adding it at the end if missing
+    pub fn set_field(&mut self, key: &str, value: Value) {
+        let Some(fields) = self.fields_mut() else { return };
+        match fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) => *old = value,
+            None => fields.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn remove_field(&mut self, key: &str) -> Option<Value> {
+        let fields = self.fields_mut()?;
+        let index = fields.iter().position(|(name, _)| name == key)?;
+        Some(fields.remove(index).1)
+    }
+
+    /// Calls `f` on every value in the tree, parents first.
+    ///
+    /// Children of a value are not visited when `f` returns false.
+    pub fn visit(&mut self, f: &mut impl FnMut(&mut Value) -> bool) {
+        with_stack(|| {
+            if !f(self) {
+                return;
+            }
+            match self {
+                Value::Str(_) | Value::Atom(_) | Value::Named(_, None) => {}
+                Value::Named(_, Some(body)) | Value::Anon(body) => body.visit(f),
+                Value::List(values) => values.iter_mut().for_each(|value| value.visit(f)),
+                Value::Map(entries) => entries.iter_mut().for_each(|(key, value)| {
+                    key.visit(f);
+                    value.visit(f);
+                }),
+            }
+        })
+    }
+
+    /// Calls `f` on the fields of every struct in the tree, parents first,
+    /// along with how the struct is held
+    pub fn visit_fields(&mut self, f: &mut impl FnMut(Owner, &mut Vec<(String, Value)>)) {
+        self.visit_fields_as(Owner::Plain, f);
+    }
+
+    fn visit_fields_as(&mut self, owner: Owner, f: &mut impl FnMut(Owner, &mut Vec<(String, Value)>)) {
+        with_stack(|| match self {
+            Value::Str(_) | Value::Atom(_) | Value::Named(_, None) => {}
+            Value::Anon(Body::Fields(fields)) => {
+                f(owner, fields);
+                fields.iter_mut().for_each(|(_, value)| value.visit_fields_as(Owner::Plain, f));
+            }
+            Value::Named(name, Some(Body::Fields(fields))) => {
+                f(Owner::Variant(name), fields);
+                fields.iter_mut().for_each(|(_, value)| value.visit_fields_as(Owner::Plain, f));
+            }
+            Value::Named(name, Some(Body::Tuple(values))) if values.len() == 1 => {
+                values[0].visit_fields_as(Owner::Newtype(name), f);
+            }
+            Value::Named(_, Some(Body::Tuple(values))) | Value::Anon(Body::Tuple(values)) | Value::List(values) => {
+                values.iter_mut().for_each(|value| value.visit_fields_as(Owner::Plain, f));
+            }
+            Value::Map(entries) => entries.iter_mut().for_each(|(key, value)| {
+                key.visit_fields_as(Owner::Plain, f);
+                value.visit_fields_as(Owner::Plain, f);
+            }),
+        })
+    }
+}
+
+impl Body {
+    fn visit(&mut self, f: &mut impl FnMut(&mut Value) -> bool) {
+        match self {
+            Body::Tuple(values) => values.iter_mut().for_each(|value| value.visit(f)),
+            Body::Fields(fields) => fields.iter_mut().for_each(|(_, value)| value.visit(f)),
+        }
+    }
+}
+
+/// Writes the tree back as compact RON, as `to_ron` would
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        with_stack(|| match self {
+            Value::Str(text) => f.write_str(&to_ron(text).map_err(|_| fmt::Error)?),
+            Value::Atom(text) => f.write_str(text),
+            Value::Named(name, body) => {
+                f.write_str(name)?;
+                body.as_ref().map_or(Ok(()), |body| write!(f, "({})", body))
+            }
+            Value::Anon(body) => write!(f, "({})", body),
+            Value::List(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", key, value)?;
+                }
+                f.write_str("}")
+            }
+        })
+    }
+}
+
+impl fmt::Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Tuple(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+            }
+            Body::Fields(fields) => {
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", name, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn error(&self, what: &str) -> String {
+        let line = self.text[..self.position].matches('\n').count() + 1;
+        format!("{} at line {}, byte {}", what, line, self.position)
+    }
+
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.position += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(c) {
+            self.position += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", c)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        with_stack(|| {
+            self.skip_space();
+            let Some(c) = self.rest().chars().next() else {
+                return Err(self.error("unexpected end"));
+            };
+            match c {
+                '"' => self.string(0),
+                'r' if self.rest()[1..].starts_with(['"', '#']) => {
+                    let hashes = self.rest()[1..].chars().take_while(|&c| c == '#').count();
+                    self.string(1 + hashes)
+                }
+                '\'' => {
+                    let start = self.position;
+                    let mut escaped = false;
+                    for (i, c) in self.rest().char_indices().skip(1) {
+                        if c == '\'' && !escaped {
+                            self.position += i + 1;
+                            return Ok(Value::Atom(self.text[start..self.position].to_string()));
+                        }
+                        escaped = c == '\\' && !escaped;
+                    }
+                    Err(self.error("unterminated char"))
+                }
+                '(' => Ok(Value::Anon(self.body(')')?)),
+                '[' => {
+                    self.position += 1;
+                    let mut values = Vec::new();
+                    while !self.eat(']') {
+                        values.push(self.value()?);
+                        if !self.eat(',') {
+                            self.expect(']')?;
+                            break;
+                        }
+                    }
+                    Ok(Value::List(values))
+                }
+                '{' => {
+                    self.position += 1;
+                    let mut entries = Vec::new();
+                    while !self.eat('}') {
+                        let key = self.value()?;
+                        self.expect(':')?;
+                        entries.push((key, self.value()?));
+                        if !self.eat(',') {
+                            self.expect('}')?;
+                            break;
+                        }
+                    }
+                    Ok(Value::Map(entries))
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let name = self.ident();
+                    self.skip_space();
+                    let body = if self.rest().starts_with('(') { Some(self.body(')')?) } else { None };
+                    Ok(Value::Named(name, body))
+                }
+                _ => {
+                    let start = self.position;
+                    let len = self.rest()
+                        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '.' | '+' | '-' | '_')))
+                        .unwrap_or(self.rest().len());
+                    if len == 0 {
+                        return Err(self.error(&format!("unexpected `{}`", c)));
+                    }
+                    self.position += len;
+                    Ok(Value::Atom(self.text[start..self.position].to_string()))
+                }
+            }
+        })
+    }
+
+    fn ident(&mut self) -> String {
+        let len = self.rest()
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        let ident = self.rest()[..len].to_string();
+        self.position += len;
+        ident
+    }
+
+    /// Reads a string, raw if `prefix` is the length of its `r#..` start
+    fn string(&mut self, prefix: usize) -> Result<Value, String> {
+        let start = self.position;
+        let quoted = &self.rest()[prefix..];
+        let len = if prefix == 0 {
+            let mut escaped = false;
+            quoted.char_indices().skip(1).find_map(|(i, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end.then_some(i + 1)
+            })
+        } else {
+            let closing = format!("\"{}", "#".repeat(prefix - 1));
+            quoted[1..].find(&closing).map(|end| end + 1 + closing.len())
+        };
+        let len = len.ok_or_else(|| self.error("unterminated string"))?;
+        self.position += prefix + len;
+        let text: String = from_ron(&self.text[start..self.position]).map_err(|e| self.error(&e.code.to_string()))?;
+        Ok(Value::Str(text))
+    }
+
+    /// Reads the parenthesized fields or values after a name, or of an anonymous struct
+    fn body(&mut self, close: char) -> Result<Body, String> {
+        self.expect('(')?;
+        let mut values = Vec::new();
+        let mut fields = Vec::new();
+        while !self.eat(close) {
+            self.skip_space();
+            let start = self.position;
+            let is_field = self.rest().starts_with(|c: char| c.is_alphabetic() || c == '_') && {
+                self.ident();
+                self.skip_space();
+                self.rest().starts_with(':')
+            };
+            self.position = start;
+            if is_field {
+                let name = self.ident();
+                self.expect(':')?;
+                fields.push((name, self.value()?));
+            } else {
+                values.push(self.value()?);
+            }
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        if !values.is_empty() && !fields.is_empty() {
+            return Err(self.error("fields mixed with values"));
+        }
+        Ok(if fields.is_empty() { Body::Tuple(values) } else { Body::Fields(fields) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_keeps_variant_names() {
+        let text = r#"(items:[Struct((name:"A\"\n",generics:Some("<T>"))),Const(name:"N",ty:Path("u8"))],n:-1.5,c:'\'',unit:(),none:None)"#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        let items = value.field("items").unwrap();
+        let Value::List(items) = items else { panic!("not a list") };
+        let s = items[0].as_newtype("Struct").unwrap();
+        assert_eq!(s.field("name").and_then(Value::as_str), Some("A\"\n"));
+        assert_eq!(s.field("generics").and_then(Value::as_some_str), Some("<T>"));
+        assert!(value.field("none").unwrap().is_none());
+    }
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+use proc_macro2::Span;
+use syn::spanned::Spanned;
+
+/// Maps syn spans back to byte ranges of the original source text.
+///
+/// `syn::parse_file` strips a leading byte-order mark and `#!` shebang line
+/// before parsing, so every span it hands out is offset by what it stripped.
+pub struct SourceMap<'a> {
+    text: &'a str,
+    base: usize,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(text: &'a str) -> Self {
+        SourceMap {
+            text,
+            base: text.len() - strip_preamble(text).len(),
+        }
+    }
+
+    /// The full source text, including any BOM and shebang.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Byte offset at which syn started parsing.
+    pub fn content_start(&self) -> usize {
+        self.base
+    }
+
+    /// Byte range covered by a syn node, attributes included.
+    pub fn range(&self, node: &impl Spanned) -> Range<usize> {
+        self.span_range(node.span())
+    }
+
+    /// Byte range covered by a single span.
+    pub fn span_range(&self, span: Span) -> Range<usize> {
+        let range = span.byte_range();
+        range.start + self.base..range.end + self.base
+    }
+
+    /// The source text of a byte range.
+    pub fn slice(&self, range: Range<usize>) -> &'a str {
+        &self.text[range]
+    }
+}
+
+/// Mirrors the BOM and shebang handling of `syn::parse_file`.
+fn strip_preamble(mut content: &str) -> &str {
+    content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    if content.starts_with("#!") && !content[2..].trim_start().starts_with('[') {
+        let end = content.find('\n').unwrap_or(content.len());
+        content = &content[end..];
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_account_for_shebang() {
+        let source = "#!/usr/bin/env run-cargo-script\nfn main() {}\n";
+        let file = syn::parse_file(source).unwrap();
+        let map = SourceMap::new(source);
+        let range = map.range(&file.items[0]);
+        assert_eq!(map.slice(range), "fn main() {}");
+    }
+}
@@ -1,54 +1,369 @@
-use regex::Regex;
+use std::fmt;
+use std::ops::Range;
+use crate::ars_file::to_ron;
+use crate::error::WaltError;
 use serde::{Serialize, Deserialize};
+use syn::spanned::Spanned;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::ARSDelimiter;
+use crate::syntax_elements::tokens;
+use crate::syntax_elements::trivia::{self, ARSTrivia, ARSTriviaPiece, GapStart};
 
 pub fn extract(_source: &str) -> Vec<String> {
     // This is handled by each element's extractor
     vec![]
 }
 
-pub fn reconstruct(_ars_attributes: &[ARSAttribute]) -> String {
-    // Attributes are reconstructed as part of their parent elements (structs, fns, etc.)
-    String::new()
+/// Reconstructs attributes one per line, with their trivia: the inner
+/// attributes of a file, module or body, or the outer attributes of an element.
+pub fn reconstruct(ars_attributes: &[ARSAttribute]) -> String {
+    let mut output = String::new();
+    for attr in ars_attributes {
+        output.push_str(&trivia::render_lines(&attr.trivia.leading));
+        output.push_str(&trivia::with_trailing(&attr.to_string(), &attr.trivia));
+        output.push('\n');
+        output.push_str(&trivia::render_lines(&attr.trivia.closing));
+    }
+    output
 }
 
 /// Represents a Rust attribute in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSAttribute {
-    pub target: Option<String>, // e.g., "Fn", "Struct", "Macro", None for inner crate-level
-    pub attr_type: String,      // "outer" or "inner"
-    pub content: String,        // attribute body
+    pub style: ARSAttrStyle,
+    pub meta: ARSMeta,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia, // For an element's last outer attribute, `closing` holds the lines between it and the element
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ARSAttrStyle {
+    Outer, // `#[..]`
+    Inner, // `#![..]`
+}
+
+/// The contents of an attribute, mirroring `syn::Meta`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSMeta {
+    Path(String),                                                    // `test`
+    List { path: String, delimiter: ARSDelimiter, tokens: String }, // `derive(Debug, Clone)`
+    NameValue { path: String, value: String },                       // `path = "sys/unix.rs"`
+    DocComment(String),                                              // `/// text` or `//! text`, the text as syn stores it
 }
 
 impl ARSAttribute {
     /// Encode the attribute as a single .ars line
-    pub fn encode(&self) -> String {
-        ron::to_string(self).unwrap()
+    pub fn encode(&self) -> Result<String, WaltError> {
+        Ok(to_ron(self)?)
+    }
+
+    /// The path naming the attribute, e.g. `derive` or `serde`
+    pub fn path(&self) -> &str {
+        match &self.meta {
+            ARSMeta::Path(path) | ARSMeta::List { path, .. } | ARSMeta::NameValue { path, .. } => path,
+            ARSMeta::DocComment(_) => "doc",
+        }
+    }
+
+    pub fn is_inner(&self) -> bool {
+        self.style == ARSAttrStyle::Inner
+    }
+}
+
+impl fmt::Display for ARSAttribute {
+    /// Renders the attribute as source, `#[derive(Debug)]` or `#![allow(dead_code)]`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bang = if self.is_inner() { "!" } else { "" };
+        match &self.meta {
+            ARSMeta::DocComment(text) => {
+                write!(f, "{}", render_doc_lines(std::slice::from_ref(text), self.style).trim_end_matches('\n'))
+            }
+            ARSMeta::Path(path) => write!(f, "#{}[{}]", bang, path),
+            ARSMeta::List { path, delimiter, tokens } => match delimiter {
+                ARSDelimiter::Paren => write!(f, "#{}[{}({})]", bang, path, tokens),
+                ARSDelimiter::Bracket => write!(f, "#{}[{}[{}]]", bang, path, tokens),
+                ARSDelimiter::Brace => write!(f, "#{}[{} {{ {} }}]", bang, path, tokens),
+            },
+            ARSMeta::NameValue { path, value } => write!(f, "#{}[{} = {}]", bang, path, value),
+        }
+    }
+}
+
+/// Converts a syn attribute into an ARSAttribute
+pub fn from_syn(attr: &syn::Attribute) -> ARSAttribute {
+    let meta = match &attr.meta {
+        _ if is_doc_comment(attr) => ARSMeta::DocComment(doc_text(attr).unwrap_or_default()),
+        syn::Meta::Path(path) => ARSMeta::Path(tokens::to_source(path)),
+        syn::Meta::List(list) => ARSMeta::List {
+            path: tokens::to_source(&list.path),
+            delimiter: match list.delimiter {
+                syn::MacroDelimiter::Paren(_) => ARSDelimiter::Paren,
+                syn::MacroDelimiter::Bracket(_) => ARSDelimiter::Bracket,
+                syn::MacroDelimiter::Brace(_) => ARSDelimiter::Brace,
+            },
+            tokens: tokens::to_source(&list.tokens),
+        },
+        syn::Meta::NameValue(name_value) => ARSMeta::NameValue {
+            path: tokens::to_source(&name_value.path),
+            value: tokens::to_source(&name_value.value),
+        },
+    };
+    ARSAttribute {
+        style: match attr.style {
+            syn::AttrStyle::Outer => ARSAttrStyle::Outer,
+            syn::AttrStyle::Inner(_) => ARSAttrStyle::Inner,
+        },
+        meta,
+        trivia: ARSTrivia::default(),
+    }
+}
+
+/// Whether a doc attribute was written as a comment, `///` or `//!`, rather than `#[doc = ".."]`.
+///
+/// syn gives every token of a doc comment the span of the whole comment.
+fn is_doc_comment(attr: &syn::Attribute) -> bool {
+    doc_text(attr).is_some() && attr.pound_token.span.byte_range() == attr.path().span().byte_range()
+}
+
+/// Converts the inner attributes at the top of a braced body, e.g. `#![allow(..)]`
+/// in a function or impl, and splits the trivia of the body between them and
+/// the members that follow.
+///
+/// Returns the attributes, the trivia of each member and the closing trivia.
+pub fn inner_with_members(
+    attrs: &[syn::Attribute],
+    members: Vec<Range<usize>>,
+    brace: &syn::token::Brace,
+    source: &SourceMap,
+) -> (Vec<ARSAttribute>, Vec<ARSTrivia>, Vec<ARSTriviaPiece>) {
+    let inner: Vec<_> = attrs.iter().filter(|attr| matches!(attr.style, syn::AttrStyle::Inner(_))).collect();
+    let ranges: Vec<_> = inner.iter().map(|attr| source.range(*attr)).chain(members).collect();
+    let (mut attribute_trivia, closing) = trivia::for_siblings(
+        source,
+        source.span_range(brace.span.open()).end,
+        GapStart::AfterToken,
+        &ranges,
+        source.span_range(brace.span.close()).start,
+    );
+    let member_trivia = attribute_trivia.split_off(inner.len());
+    let attributes = inner.iter().zip(attribute_trivia).map(|(attr, trivia)| {
+        ARSAttribute { trivia, ..from_syn(attr) }
+    }).collect();
+    (attributes, member_trivia, closing)
+}
+
+/// Converts the outer attributes of an element, leaving out the doc comments `docs` takes.
+///
+/// Each keeps the comments between it and the attribute before it, and one
+/// after it on its line; the last also keeps the lines between it and the
+/// element. Those before the first attribute belong to the element.
+pub fn outer(attrs: &[syn::Attribute], source: &SourceMap) -> Vec<ARSAttribute> {
+    let outer: Vec<_> = attrs.iter().filter(|attr| matches!(attr.style, syn::AttrStyle::Outer)).collect();
+    let mut previous_end = None;
+    let mut converted = Vec::new();
+    for (i, (attr, is_doc)) in outer.iter().zip(movable_docs(&outer)).enumerate() {
+        let range = source.range(*attr);
+        if !is_doc {
+            let mut ars_attribute = from_syn(attr);
+            let next = match outer.get(i + 1) {
+                Some(next) => source.range(*next).start,
+                None => range.end + trivia::trivia_len(&source.text()[range.end..]),
+            };
+            if let Some(end) = previous_end.filter(|end| *end <= range.start) {
+                ars_attribute.trivia.leading = trivia::scan(source.slice(end..range.start), GapStart::AfterSibling);
+            }
+            if range.end <= next {
+                ars_attribute.trivia.trailing = trivia::trailing_comment(source.slice(range.end..next));
+                if i + 1 == outer.len() {
+                    ars_attribute.trivia.closing = trivia::scan(source.slice(range.end..next), GapStart::AfterSibling);
+                }
+            }
+            converted.push(ars_attribute);
+        }
+        previous_end = Some(range.end);
+    }
+    converted
+}
+
+/// The outer doc comment lines of an element, without the `///`
+pub fn docs(attrs: &[syn::Attribute]) -> Vec<String> {
+    let outer: Vec<_> = attrs.iter().filter(|attr| matches!(attr.style, syn::AttrStyle::Outer)).collect();
+    outer.iter().zip(movable_docs(&outer)).filter(|(_, is_doc)| *is_doc).filter_map(|(attr, _)| doc_text(attr)).collect()
+}
+
+/// Marks the doc comments among `attrs` that can move into a docs field.
+///
+/// Docs are rendered ahead of the attributes, so only those before the first
+/// other attribute move; the rest stay attributes and keep their place, which
+/// `#[must_use]` followed by `/// ...` or `#[cfg_attr(docsrs, doc = "...")]`
+/// between doc comments need. Those written as comments are rendered as
+/// comments again.
+pub fn movable_docs(attrs: &[&syn::Attribute]) -> Vec<bool> {
+    let mut leading = true;
+    attrs.iter().map(|attr| {
+        leading &= doc_text(attr).is_some();
+        leading
+    }).collect()
+}
+
+/// Splits the outer attributes of an element into its doc comment lines and the remaining attributes
+pub fn outer_and_docs(attrs: &[syn::Attribute], source: &SourceMap) -> (Vec<String>, Vec<ARSAttribute>) {
+    (docs(attrs), outer(attrs, source))
+}
+
+/// The text of a `///` or `//!` comment or `#[doc = "..."]` attribute, as syn stores it
+pub fn doc_text(attr: &syn::Attribute) -> Option<String> {
+    match &attr.meta {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            path,
+            value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(text), .. }),
+            ..
+        }) if path.is_ident("doc") => Some(text.value()),
+        _ => None,
+    }
+}
+
+/// Renders doc lines as outer doc comments, one per line.
+///
+/// Text from `/** */` comments spans several lines and is rendered as a block
+/// comment again. Text that would read back as a plain comment keeps the
+/// attribute form.
+pub fn render_docs(docs: &[String]) -> String {
+    render_doc_lines(docs, ARSAttrStyle::Outer)
+}
+
+/// Renders doc lines as inner doc comments, `//!` or `/*! */`, like `render_docs`
+pub fn render_inner_docs(docs: &[String]) -> String {
+    render_doc_lines(docs, ARSAttrStyle::Inner)
+}
+
+fn render_doc_lines(docs: &[String], style: ARSAttrStyle) -> String {
+    let (block, line, attribute) = match style {
+        ARSAttrStyle::Outer => ("/**", "///", "#"),
+        ARSAttrStyle::Inner => ("/*!", "//!", "#!"),
+    };
+    let mut output = String::new();
+    for text in docs {
+        let multiline = text.contains('\n');
+        if multiline && !text.starts_with('*') && !text.contains("*/") {
+            output.push_str(&format!("{}{}*/\n", block, text));
+        } else if !multiline && !text.starts_with('/') && !text.contains('\r') {
+            output.push_str(&format!("{}{}\n", line, text));
+        } else {
+            output.push_str(&format!("{}[doc = {:?}]\n", attribute, text));
+        }
     }
+    output
 }
 
-/// Encode all attributes in a Rust source file to Animated Rust (.ars)
+/// Encode the crate-level inner attributes (`#![...]`) of a Rust source file to Animated Rust (.ars)
 pub fn encode_rust(source: &str) -> Vec<ARSAttribute> {
-    let mut attributes = Vec::new();
+    match crate::encoder::encode_source(source) {
+        Ok(ars_file) => ars_file.attributes,
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+    use crate::ars_file::ARSItem;
+    use crate::{decoder, encoder};
+
+    #[test]
+    fn test_meta_kinds_and_inner_attributes() {
+        let source = "#[non_exhaustive] #[derive(Debug, Clone)] #[serde(rename = \"x\")] #[path = \"a.rs\"] struct S;";
+        let item: syn::ItemStruct = syn::parse_str(source).unwrap();
+        let attrs = outer(&item.attrs, &SourceMap::new(source));
+        assert_eq!(attrs[0].meta, ARSMeta::Path("non_exhaustive".to_string()));
+        assert!(matches!(&attrs[1].meta, ARSMeta::List { path, tokens, .. } if path == "derive" && tokens == "Debug, Clone"));
+        assert_eq!(attrs[2].path(), "serde");
+        assert!(matches!(&attrs[3].meta, ARSMeta::NameValue { value, .. } if value == "\"a.rs\""));
 
-    // Outer attributes #[...]
-    let outer_regex = Regex::new(r#"(?m)#\[(.*?)\]"#).unwrap();
-    for cap in outer_regex.captures_iter(source) {
-        attributes.push(ARSAttribute {
-            target: None,
-            attr_type: "outer".to_string(),
-            content: cap[1].trim().to_string(),
-        });
+        let source = "#![allow(dead_code)]\n\n// tools\n#![cfg_attr(test, feature(test))] // nightly\n\nmod inner {\n    #![allow(unused)]\n\n    fn f() {}\n}\n";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert_eq!(ars_file.attributes.len(), 2);
+        assert!(ars_file.attributes.iter().all(ARSAttribute::is_inner));
+        assert_eq!(decoder::decode(&ars_file), source);
     }
 
-    // Inner attributes #![...]
-    let inner_regex = Regex::new(r#"(?m)#!\[(.*?)\]"#).unwrap();
-    for cap in inner_regex.captures_iter(source) {
-        attributes.push(ARSAttribute {
-            target: None,
-            attr_type: "inner".to_string(),
-            content: cap[1].trim().to_string(),
-        });
+    #[test]
+    fn test_docs_roundtrip_as_comments() {
+        let source = "// Copyright notice
+
+//! Crate docs
+//!
+//! More detail.
+
+#![deny(missing_docs)]
+
+/// A point
+#[derive(Debug)]
+pub struct Point;
+
+/**
+ * Block docs
+ */
+pub mod shapes {
+    //! Shape docs
+
+    pub trait Area {
+        /// The area
+        fn area(&self) -> f64;
+    }
+}
+";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert_eq!(ars_file.docs, vec![" Crate docs", "", " More detail."]);
+        assert_eq!(ars_file.attributes.len(), 1);
+        assert!(matches!(&ars_file.items[0], ARSItem::Struct(s) if s.docs == [" A point"] && s.attributes.len() == 1));
+        assert!(matches!(&ars_file.items[1], ARSItem::Module(m) if m.inner_docs == [" Shape docs"]));
+        assert_eq!(decoder::decode(&ars_file), source);
+
+        // Docs added by another attribute keep their place between the doc comments
+        let source = "/// a\n#[cfg_attr(docsrs, doc = \"b\")]\n/// c\nfn f() {}";
+        let item: syn::ItemFn = syn::parse_str(source).unwrap();
+        assert_eq!(docs(&item.attrs), vec![" a"]);
+        assert_eq!(outer(&item.attrs, &SourceMap::new(source)).len(), 2);
+
+        // So do doc comments after any other attribute
+        let source = "#[must_use]\n/// doc\npub fn f() {}\n";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert!(matches!(&ars_file.items[0], ARSItem::Function(f) if f.docs.is_empty() && f.attributes.len() == 2));
+        assert_eq!(decoder::decode(&ars_file), source);
+
+        // Written as attributes, they stay attributes
+        let source = "#[must_use]\n#[doc = \" doc\"]\n/// more\npub fn f() {}\n";
+        let decoded = decoder::decode(&encoder::encode_source(source).unwrap());
+        assert_eq!(decoded, source);
+        assert_eq!(
+            syn::parse_file(&decoded).unwrap().into_token_stream().to_string(),
+            syn::parse_file(source).unwrap().into_token_stream().to_string()
+        );
     }
 
-    attributes
+    #[test]
+    fn test_comments_around_outer_attributes_are_kept() {
+        let source = "/// A point
+#[derive(Debug)] // why
+
+// between
+#[repr(C)]
+// note
+struct S {
+    #[serde(default)] // field
+    x: u8,
+}
+
+#[test]
+/* block */ fn f() {}
+";
+        let ars_file = encoder::encode_source(source).unwrap();
+        let ARSItem::Struct(s) = &ars_file.items[0] else { panic!() };
+        assert_eq!(s.attributes[0].trivia.trailing.as_deref(), Some("// why"));
+        assert_eq!(s.attributes[1].trivia.closing, vec![ARSTriviaPiece::Comment("// note".to_string())]);
+        assert!(ars_file.unsupported.is_empty());
+        assert_eq!(decoder::decode(&ars_file), source.replace("/* block */ fn", "/* block */\nfn"));
+    }
 }
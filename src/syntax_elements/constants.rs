@@ -1,59 +1,99 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::{self, ARSExpression};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType};
 
 /// Represents a Rust constant in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSConst {
-    pub name: String,
-    pub ty: String,
-    pub value: String,
+    pub name: String,            // `_` for an unnamed const
+    pub ty: ARSType,
+    pub value: ARSExpression,
     pub visibility: Option<String>,
-    pub attributes: Vec<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
 /// Generic extract function
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_consts(source)
         .iter()
-        .map(|c| ron::to_string(c).unwrap())
+        .map(|c| to_ron(c).map_err(WaltError::from))
         .collect()
 }
 
+/// Converts a syn const item into an ARSConst
+pub fn from_syn(item: &syn::ItemConst, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSConst {
+    ARSConst {
+        name: item.ident.to_string(),
+        ty: types::from_syn(&item.ty, source, unsupported),
+        value: expressions::from_syn(&item.expr, source, unsupported),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia::default(),
+    }
+}
+
 /// Encode all consts in a Rust source file to Animated Rust (.ars)
 pub fn extract_ars_consts(source: &str) -> Vec<ARSConst> {
-    let const_regex = Regex::new(r"(?m)((?:#\[.*?\]\s*)*)(pub(?:\(crate\))?\s+)?const\s+(\w+)\s*:\s*([^=]+)\s*=\s*(.+?);").unwrap();
-    let attr_regex = Regex::new(r"#\[(.*?)\]").unwrap();
-
-    const_regex.captures_iter(source).map(|cap| {
-        let attributes_str = &cap[1];
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap[3].to_string();
-        let ty = cap[4].trim().to_string();
-        let value = cap[5].trim().to_string();
-
-        let attributes = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-
-        ARSConst {
-            name,
-            ty,
-            value,
-            visibility,
-            attributes,
-        }
-    }).collect()
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Const(c) => Some(from_syn(c, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
 }
 
 /// Reconstructs Rust code from a slice of ARSConst structs
 pub fn reconstruct(ars_consts: &[ARSConst]) -> String {
     let mut output = String::new();
     for c in ars_consts {
-        for attr in &c.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
-        let vis = c.visibility.as_deref().unwrap_or("");
-        output.push_str(&format!("{}const {}: {} = {};\n\n", vis, c.name, c.ty, c.value));
+        output.push_str(&trivia::render_lines(&c.trivia.leading));
+        output.push_str(&attributes::render_docs(&c.docs));
+        output.push_str(&attributes::reconstruct(&c.attributes));
+        let vis = vis_prefix(&c.visibility);
+        let text = format!("{}const {}: {} = {};", vis, c.name, c.ty, expressions::reconstruct_expr(&c.value));
+        output.push_str(&format!("{}\n\n", trivia::with_trailing(&text, &c.trivia)));
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_elements::expressions::ARSExpressionType;
+
+    #[test]
+    fn test_values_are_expressions() {
+        let source = "pub(in crate::tables) const TABLE: [u8; 4] = [1, 2, 3, 4];
+pub(super) const PICK: fn(u8) -> u8 = |x| {
+    let y = x + 1;
+    y * 2
+};
+const _: () = assert!(TABLE.len() == 4);
+";
+        let result = extract_ars_consts(source);
+        assert_eq!(result[0].visibility.as_deref(), Some("pub(in crate::tables)"));
+        assert!(matches!(&result[0].ty, ARSType::Array { elem, .. } if elem.to_string() == "u8"));
+        assert!(matches!(&result[0].value.expression_type, ARSExpressionType::Array { elements, .. } if elements.len() == 4));
+        assert!(matches!(result[1].value.expression_type, ARSExpressionType::Closure(_)));
+        assert_eq!(result[2].name, "_");
+        assert_eq!(
+            reconstruct(&result),
+            "pub(in crate::tables) const TABLE: [u8; 4] = [1, 2, 3, 4];\n\n\
+             pub(super) const PICK: fn(u8) -> u8 = |x| {\n    let y = x + 1;\n    y * 2\n};\n\n\
+             const _: () = assert!(TABLE.len() == 4);\n\n"
+        );
+    }
+}
@@ -1,11 +1,19 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::structs::{self, ARSField};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::expressions::{self, ARSExpression};
+use crate::syntax_elements::{attributes, indent, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::trivia::{self, ARSTrivia, GapStart};
 
 // Generic extract function stub
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_enums(source)
         .iter()
-        .map(|e| ron::to_string(e).unwrap())
+        .map(|e| to_ron(e).map_err(WaltError::from))
         .collect()
 }
 
@@ -13,80 +21,203 @@ pub fn extract(source: &str) -> Vec<String> {
 pub fn reconstruct(ars_enums: &[ARSEnum]) -> String {
     let mut output = String::new();
     for e in ars_enums {
-        for attr in &e.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
+        output.push_str(&trivia::render_lines(&e.trivia.leading));
+        output.push_str(&attributes::render_docs(&e.docs));
+        output.push_str(&attributes::reconstruct(&e.attributes));
+
+        let vis = vis_prefix(&e.visibility);
+        let generics = generics::reconstruct_params(&e.generics);
+        let where_clause = generics::reconstruct_where(&e.generics);
 
-        let vis = e.visibility.as_deref().unwrap_or("");
-        let generics = e.generics.as_deref().unwrap_or("");
-        
-        output.push_str(&format!("{}enum {}{} {{ ", vis, e.name, generics));
-        output.push_str(&e.variants.join(", "));
-        output.push_str(" }\n\n");
+        let mut text = format!("{}enum {}{}{} {{", vis, e.name, generics, where_clause);
+        if e.variants.is_empty() && e.trivia.closing.is_empty() {
+            text.push('}');
+        } else {
+            text.push_str(&format!("\n{}\n}}", indent(&variants_source(e))));
+        }
+        output.push_str(&trivia::with_trailing(&text, &e.trivia));
+        output.push_str("\n\n");
     }
     output
 }
 
+/// Renders the variants one per line, each followed by a comma and its trailing comment
+fn variants_source(e: &ARSEnum) -> String {
+    let mut lines = String::new();
+    for (i, variant) in e.variants.iter().enumerate() {
+        let comma = if i + 1 < e.variants.len() || e.trailing_comma { "," } else { "" };
+        lines.push_str(&trivia::render_lines(&variant.trivia.leading));
+        lines.push_str(&attributes::render_docs(&variant.docs));
+        lines.push_str(&attributes::reconstruct(&variant.attributes));
+        let mut text = variant.name.clone();
+        match variant.kind {
+            ARSVariantKind::Unit => {}
+            ARSVariantKind::Tuple | ARSVariantKind::Struct => text.push_str(&structs::reconstruct_fields(
+                &variant.fields,
+                variant.kind == ARSVariantKind::Struct,
+                variant.trailing_comma,
+                &variant.trivia.closing,
+            )),
+        }
+        if let Some(discriminant) = &variant.discriminant {
+            text.push_str(&format!(" = {}", expressions::reconstruct_expr(discriminant)));
+        }
+        text.push_str(comma);
+        lines.push_str(&trivia::with_trailing(&text, &variant.trivia));
+        lines.push('\n');
+    }
+    lines.push_str(&trivia::render_lines(&e.trivia.closing));
+    lines.trim_end_matches('\n').to_string()
+}
+
 /// Represents a Rust enum in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSEnum {
     pub name: String,
-    pub variants: Vec<String>,       // Each variant encoded as "Variant" | "Variant(type1,type2)" | "Variant(field:type,...)"
+    pub variants: Vec<ARSVariant>,
+    #[serde(default)]
+    pub trailing_comma: bool,         // After the last variant
     pub visibility: Option<String>,
-    pub generics: Option<String>,
-    pub attributes: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
-/// Encode all enums in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_enums(source: &str) -> Vec<ARSEnum> {
-    let mut enums = Vec::new();
-    let enum_regex = Regex::new(
-        r"(?s)((?:#\[.*?\]\s*)*)(pub(?:\(\w+\))?\s*)?enum\s+(\w+)\s*(<[^>]*>)?\s*\{"
-    ).unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
+/// A single enum variant
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSVariant {
+    pub name: String,
+    pub kind: ARSVariantKind,
+    pub fields: Vec<ARSField>,        // Empty for unit variants
+    #[serde(default)]
+    pub trailing_comma: bool,         // After the last field
+    pub discriminant: Option<ARSExpression>, // Expression after `=`
+    pub attributes: Vec<ARSAttribute>,
+    pub docs: Vec<String>,            // Doc comment lines, without the `///`
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,            // `closing` holds the lines before the end of the field list
+}
 
-    for cap in enum_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap.get(3).unwrap().as_str().to_string();
-        let generics = cap.get(4).map(|m| m.as_str().to_string());
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ARSVariantKind {
+    Unit,   // `A`
+    Tuple,  // `A(u8, String)`
+    Struct, // `A { x: i32 }`
+}
 
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-        
-        // Manually find the end of the enum body by balancing braces
-        let body_start = full_match.end();
-        let mut brace_count = 1;
-        let mut body_end = body_start;
-        for (i, c) in source[body_start..].char_indices() {
-            if c == '{' { brace_count += 1; }
-            if c == '}' { brace_count -= 1; }
-            if brace_count == 0 {
-                body_end = body_start + i;
-                break;
-            }
-        }
+/// Converts a syn enum item into an ARSEnum
+pub fn from_syn(item: &syn::ItemEnum, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSEnum {
+    let ranges: Vec<_> = item.variants.iter().map(|variant| source.range(variant)).collect();
+    let (mut variant_trivia, closing) = trivia::for_siblings(
+        source,
+        source.span_range(item.brace_token.span.open()).end,
+        GapStart::AfterToken,
+        &ranges,
+        source.span_range(item.brace_token.span.close()).start,
+    );
 
-        let variants = if body_end > body_start {
-            let body = &source[body_start..body_end];
-            body.split(',')
-                .map(|l| l.trim().to_string())
-                .filter(|l| !l.is_empty())
-                .collect()
-        } else {
-            Vec::new()
-        };
+    ARSEnum {
+        name: item.ident.to_string(),
+        variants: item.variants.iter().zip(variant_trivia.iter_mut()).map(|(variant, variant_trivia)| {
+            let mut ars_variant = variant_from_syn(variant, source, unsupported);
+            ars_variant.trivia.leading = std::mem::take(&mut variant_trivia.leading);
+            ars_variant.trivia.trailing = variant_trivia.trailing.take();
+            ars_variant
+        }).collect(),
+        trailing_comma: item.variants.trailing_punct(),
+        visibility: visibility(&item.vis),
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
+    }
+}
 
-        enums.push(ARSEnum {
-            name,
-            variants,
-            visibility,
-            generics,
-            attributes,
-        });
+/// Converts a syn variant into an ARSVariant, without its own leading and trailing trivia
+pub fn variant_from_syn(variant: &syn::Variant, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSVariant {
+    let (docs, attributes) = attributes::outer_and_docs(&variant.attrs, source);
+    let (fields, closing) = structs::fields_from_syn(&variant.fields, source, unsupported);
+    let (kind, trailing_comma) = match &variant.fields {
+        syn::Fields::Unit => (ARSVariantKind::Unit, false),
+        syn::Fields::Unnamed(unnamed) => (ARSVariantKind::Tuple, unnamed.unnamed.trailing_punct()),
+        syn::Fields::Named(named) => (ARSVariantKind::Struct, named.named.trailing_punct()),
+    };
+    ARSVariant {
+        name: variant.ident.to_string(),
+        kind,
+        fields,
+        trailing_comma,
+        discriminant: variant.discriminant.as_ref().map(|(_, expr)| expressions::from_syn(expr, source, unsupported)),
+        attributes,
+        docs,
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
     }
+}
+
+/// Encode all enums in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_enums(source: &str) -> Vec<ARSEnum> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Enum(e) => Some(from_syn(e, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_elements::expressions::ARSExpressionType;
 
-    enums
+    #[test]
+    fn test_variants_are_structured() {
+        let source = r#"
+pub enum Message {
+    /// Nothing to do
+    Quit,
+    #[serde(rename = "mv")]
+    Move { x: i32, y: i32 },
+    Error(String, HashMap<K, V>),
+    Code = 4 + 1,
+    Write {
+        // The text
+        text: String,
+    }
+}
+"#;
+        let result = extract_ars_enums(source);
+        let variants = &result[0].variants;
+        assert_eq!(variants.len(), 5);
+        assert!(!result[0].trailing_comma);
+        assert_eq!(variants[0].docs, vec![" Nothing to do"]);
+        assert_eq!(variants[1].attributes[0].to_string(), "#[serde(rename = \"mv\")]");
+        assert_eq!(variants[1].kind, ARSVariantKind::Struct);
+        assert_eq!(variants[1].fields[1].name.as_deref(), Some("y"));
+        assert_eq!(variants[2].fields[1].ty.to_string(), "HashMap<K, V>");
+        assert!(matches!(
+            variants[3].discriminant.as_ref().map(|d| &d.expression_type),
+            Some(ARSExpressionType::Binary { op, .. }) if op == "+"
+        ));
+        assert_eq!(
+            reconstruct(&result),
+            "pub enum Message {\n    \
+             /// Nothing to do\n    \
+             Quit,\n    \
+             #[serde(rename = \"mv\")]\n    \
+             Move { x: i32, y: i32 },\n    \
+             Error(String, HashMap<K, V>),\n    \
+             Code = 4 + 1,\n    \
+             Write {\n        \
+             // The text\n        \
+             text: String,\n    \
+             }\n\
+             }\n\n"
+        );
+    }
 }
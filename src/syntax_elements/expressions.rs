@@ -1,25 +1,203 @@
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use syn::Expr;
+use proc_macro2::extra::DelimSpan;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::attributes::{self, ARSAttribute};
+use crate::syntax_elements::{indent, macros, statements};
+use crate::syntax_elements::statements::ARSStatement;
+use crate::syntax_elements::tokens::to_source;
+use crate::syntax_elements::trivia::{self, ARSTrivia, ARSTriviaPiece, GapStart};
+use crate::syntax_elements::types::{self, ARSType};
 
+/// An expression node, mirroring `syn::Expr`
+///
+/// Serde goes through `with_stack`, see the impls below.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(remote = "Self")]
+pub struct ARSExpression {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<ARSAttribute>, // Outer `#[..]`, and inner `#![..]` of block-like expressions
+    pub expression_type: ARSExpressionType,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia, // Only kept for the elements of a list and the `..base` of a struct literal
+}
+
+/// The kind of an expression, with its children.
+///
+/// Patterns and paths are kept as source text. `trailing_comma`
+/// records a comma after the last element of a list, which `(a,)` needs,
+/// and `closing` the comments and blank lines before its closing delimiter.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ARSExpressionType {
-    Block,
-    // ... other expression types
+    Array {
+        elements: Vec<ARSExpression>,
+        #[serde(default)]
+        trailing_comma: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    Assign { left: Box<ARSExpression>, right: Box<ARSExpression> },
+    Async { is_move: bool, block: ARSBlock },
+    Await { base: Box<ARSExpression> },
+    Binary { left: Box<ARSExpression>, op: String, right: Box<ARSExpression> },
+    Block { label: Option<String>, block: ARSBlock },
+    Break { label: Option<String>, value: Option<Box<ARSExpression>> },
+    Call {
+        function: Box<ARSExpression>,
+        args: Vec<ARSExpression>,
+        #[serde(default)]
+        trailing_comma: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    Cast { expr: Box<ARSExpression>, ty: ARSType },
+    Closure(ARSClosure),
+    Const { block: ARSBlock },
+    Continue { label: Option<String> },
+    Field { base: Box<ARSExpression>, member: String },
+    ForLoop { label: Option<String>, pattern: String, iterable: Box<ARSExpression>, body: ARSBlock },
+    Group { expr: Box<ARSExpression> }, // Invisible delimiters from macro expansion
+    If { condition: Box<ARSExpression>, then_branch: ARSBlock, else_branch: Option<Box<ARSExpression>> },
+    Index { expr: Box<ARSExpression>, index: Box<ARSExpression> },
+    Infer,
+    Let { pattern: String, expr: Box<ARSExpression> },
+    Lit(String),
+    Loop { label: Option<String>, body: ARSBlock },
+    Macro { path: String, delimiter: ARSDelimiter, tokens: String },
+    Match {
+        expr: Box<ARSExpression>,
+        arms: Vec<ARSMatchArm>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    MethodCall {
+        receiver: Box<ARSExpression>,
+        method: String,
+        turbofish: Option<String>, // e.g. `::<T>`
+        args: Vec<ARSExpression>,
+        #[serde(default)]
+        trailing_comma: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    Paren { expr: Box<ARSExpression> },
+    Path(String),
+    Range { start: Option<Box<ARSExpression>>, limits: String, end: Option<Box<ARSExpression>> },
+    RawAddr { mutability: String, expr: Box<ARSExpression> }, // `&raw const` or `&raw mut`
+    Reference { mutable: bool, expr: Box<ARSExpression> },
+    Repeat { expr: Box<ARSExpression>, len: Box<ARSExpression> },
+    Return { value: Option<Box<ARSExpression>> },
+    Struct {
+        path: String,
+        fields: Vec<ARSFieldValue>,
+        has_rest: bool, // `..` present
+        rest: Option<Box<ARSExpression>>,
+        #[serde(default)]
+        trailing_comma: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    Try { expr: Box<ARSExpression> },
+    TryBlock { block: ARSBlock },
+    Tuple {
+        elements: Vec<ARSExpression>,
+        #[serde(default)]
+        trailing_comma: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        closing: Vec<ARSTriviaPiece>,
+    },
+    Unary { op: String, expr: Box<ARSExpression> },
+    Unsafe { block: ARSBlock },
+    Verbatim(String), // Anything syn itself could not structure
+    While { label: Option<String>, condition: Box<ARSExpression>, body: ARSBlock },
+    Yield { value: Option<Box<ARSExpression>> },
+}
+
+/// A braced block of statements
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ARSBlock {
+    pub statements: Vec<ARSStatement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closing: Vec<ARSTriviaPiece>, // Comments and blank lines before the closing brace
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-pub struct ARSExpression {
-    pub expression_type: ARSExpressionType,
-    pub content: String,
+pub struct ARSClosure {
+    pub lifetimes: Option<String>, // `for<'a>`
+    pub is_const: bool,
+    pub is_static: bool,
+    pub is_async: bool,
+    pub is_move: bool,
+    pub inputs: Vec<String>,      // Patterns, with their type if annotated
+    #[serde(default)]
+    pub trailing_comma: bool,
+    pub output: Option<ARSType>,  // Return type
+    pub body: Box<ARSExpression>,
 }
 
-impl ARSExpression {
-    pub fn encode(&self) -> String {
-        ron::to_string(self).unwrap()
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSMatchArm {
+    pub attributes: Vec<ARSAttribute>,
+    pub pattern: String,
+    pub guard: Option<ARSExpression>,
+    pub body: ARSExpression,
+    pub comma: bool,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSFieldValue {
+    pub attributes: Vec<ARSAttribute>,
+    pub member: String,
+    pub shorthand: bool, // `Point { x }` rather than `Point { x: x }`
+    pub expr: ARSExpression,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ARSDelimiter {
+    Paren,
+    Bracket,
+    Brace,
+}
+
+/// Stack left when an expression level moves to a fresh stack segment
+const STACK_RED_ZONE: usize = 256 * 1024;
+/// Size of each fresh stack segment
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Runs one level of expression recursion, growing the stack if it runs low.
+///
+/// Expressions nest as deep as the source does: every call of a 200-call
+/// builder chain is one level, so converting, rendering and (de)serializing
+/// them would otherwise overflow the stack.
+pub(crate) fn with_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, f)
+}
+
+impl Serialize for ARSExpression {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        with_stack(|| ARSExpression::serialize(self, serializer))
     }
 }
 
+impl<'de> Deserialize<'de> for ARSExpression {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        with_stack(|| ARSExpression::deserialize(deserializer))
+    }
+}
+
+impl ARSExpression {
+    pub fn encode(&self) -> Result<String, WaltError> {
+        Ok(to_ron(self)?)
+    }
+}
 
-pub fn extract(input: &str) -> Vec<String> {
+pub fn extract(input: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_expressions(input)
         .iter()
         .map(|e| e.encode())
@@ -27,32 +205,561 @@ pub fn extract(input: &str) -> Vec<String> {
 }
 
 pub fn reconstruct(expressions: &[ARSExpression]) -> String {
-    expressions.iter().map(|e| e.content.clone()).collect::<Vec<String>>().join("\n")
+    expressions.iter().map(reconstruct_expr).collect::<Vec<String>>().join("\n")
 }
 
 pub fn extract_ars_expressions(input: &str) -> Vec<ARSExpression> {
-    // This is a placeholder implementation.
-    // A real implementation would parse various kinds of expressions.
-    // For now, we'll just capture everything as a block.
-    if !input.trim().is_empty() {
-        vec![ARSExpression {
-            expression_type: ARSExpressionType::Block,
-            content: input.to_string(),
-        }]
+    if input.trim().is_empty() {
+        return Vec::new();
+    }
+    match syn::parse_str::<Expr>(input) {
+        // Only the expression is kept, not what its blocks could not structure
+        Ok(expr) => vec![from_syn(&expr, &SourceMap::new(input), &mut Vec::new())],
+        // Keep what could not be parsed as a single verbatim node
+        Err(_) => vec![ARSExpression {
+            attributes: Vec::new(),
+            expression_type: ARSExpressionType::Verbatim(input.to_string()),
+            trivia: ARSTrivia::default(),
+        }],
+    }
+}
+
+/// Converts a syn expression into an ARSExpression tree.
+///
+/// `source` is the text the expression was parsed from, used to keep the
+/// comments and blank lines between the statements of nested blocks. Items
+/// in those blocks that the model has no structure for are recorded in
+/// `unsupported`.
+pub fn from_syn(expr: &Expr, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSExpression {
+    with_stack(|| convert(expr, source, unsupported))
+}
+
+fn convert(expr: &Expr, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSExpression {
+    use ARSExpressionType as T;
+
+    let boxed = |e: &Expr, unsupported: &mut Vec<ARSUnsupported>| Box::new(from_syn(e, source, unsupported));
+    let optional = |e: &Option<Box<Expr>>, unsupported: &mut Vec<ARSUnsupported>| {
+        e.as_ref().map(|e| Box::new(from_syn(e, source, unsupported)))
+    };
+    let label = |l: &Option<syn::Label>| l.as_ref().map(|l| to_source(&l.name));
+    let lifetime = |l: &Option<syn::Lifetime>| l.as_ref().map(to_source);
+    let list = |items: &syn::punctuated::Punctuated<Expr, syn::Token![,]>,
+                delimiters: &DelimSpan,
+                unsupported: &mut Vec<ARSUnsupported>| {
+        let ranges: Vec<_> = items.iter().map(|e| source.range(e)).collect();
+        let (item_trivia, closing) = siblings(&ranges, delimiters, source);
+        let elements = items.iter().zip(item_trivia).map(|(e, trivia)| ARSExpression {
+            trivia,
+            ..from_syn(e, source, unsupported)
+        }).collect();
+        (elements, items.trailing_punct(), closing)
+    };
+
+    let (attrs, expression_type) = match expr {
+        Expr::Array(e) => {
+            let (elements, trailing_comma, closing) = list(&e.elems, &e.bracket_token.span, unsupported);
+            (&e.attrs, T::Array { elements, trailing_comma, closing })
+        }
+        Expr::Assign(e) => (&e.attrs, T::Assign { left: boxed(&e.left, unsupported), right: boxed(&e.right, unsupported) }),
+        Expr::Async(e) => (&e.attrs, T::Async { is_move: e.capture.is_some(), block: block(&e.block, source, unsupported) }),
+        Expr::Await(e) => (&e.attrs, T::Await { base: boxed(&e.base, unsupported) }),
+        Expr::Binary(e) => (
+            &e.attrs,
+            T::Binary { left: boxed(&e.left, unsupported), op: to_source(&e.op), right: boxed(&e.right, unsupported) },
+        ),
+        Expr::Block(e) => (&e.attrs, T::Block { label: label(&e.label), block: block(&e.block, source, unsupported) }),
+        Expr::Break(e) => (&e.attrs, T::Break { label: lifetime(&e.label), value: optional(&e.expr, unsupported) }),
+        Expr::Call(e) => {
+            let (args, trailing_comma, closing) = list(&e.args, &e.paren_token.span, unsupported);
+            (&e.attrs, T::Call { function: boxed(&e.func, unsupported), args, trailing_comma, closing })
+        }
+        Expr::Cast(e) => (
+            &e.attrs,
+            T::Cast { expr: boxed(&e.expr, unsupported), ty: types::from_syn(&e.ty, source, unsupported) },
+        ),
+        Expr::Closure(e) => (
+            &e.attrs,
+            T::Closure(ARSClosure {
+                lifetimes: e.lifetimes.as_ref().map(to_source),
+                is_const: e.constness.is_some(),
+                is_static: e.movability.is_some(),
+                is_async: e.asyncness.is_some(),
+                is_move: e.capture.is_some(),
+                inputs: e.inputs.iter().map(to_source).collect(),
+                trailing_comma: e.inputs.trailing_punct(),
+                output: types::return_type(&e.output, source, unsupported),
+                body: boxed(&e.body, unsupported),
+            }),
+        ),
+        Expr::Const(e) => (&e.attrs, T::Const { block: block(&e.block, source, unsupported) }),
+        Expr::Continue(e) => (&e.attrs, T::Continue { label: lifetime(&e.label) }),
+        Expr::Field(e) => (&e.attrs, T::Field { base: boxed(&e.base, unsupported), member: to_source(&e.member) }),
+        Expr::ForLoop(e) => (
+            &e.attrs,
+            T::ForLoop {
+                label: label(&e.label),
+                pattern: to_source(&e.pat),
+                iterable: boxed(&e.expr, unsupported),
+                body: block(&e.body, source, unsupported),
+            },
+        ),
+        Expr::Group(e) => (&e.attrs, T::Group { expr: boxed(&e.expr, unsupported) }),
+        Expr::If(e) => (
+            &e.attrs,
+            T::If {
+                condition: boxed(&e.cond, unsupported),
+                then_branch: block(&e.then_branch, source, unsupported),
+                else_branch: e.else_branch.as_ref().map(|(_, e)| boxed(e, unsupported)),
+            },
+        ),
+        Expr::Index(e) => (&e.attrs, T::Index { expr: boxed(&e.expr, unsupported), index: boxed(&e.index, unsupported) }),
+        Expr::Infer(e) => (&e.attrs, T::Infer),
+        Expr::Let(e) => (&e.attrs, T::Let { pattern: to_source(&e.pat), expr: boxed(&e.expr, unsupported) }),
+        Expr::Lit(e) => (&e.attrs, T::Lit(to_source(&e.lit))),
+        Expr::Loop(e) => (&e.attrs, T::Loop { label: label(&e.label), body: block(&e.body, source, unsupported) }),
+        Expr::Macro(e) => (
+            &e.attrs,
+            T::Macro {
+                path: to_source(&e.mac.path),
+                delimiter: match e.mac.delimiter {
+                    syn::MacroDelimiter::Paren(_) => ARSDelimiter::Paren,
+                    syn::MacroDelimiter::Bracket(_) => ARSDelimiter::Bracket,
+                    syn::MacroDelimiter::Brace(_) => ARSDelimiter::Brace,
+                },
+                tokens: macros::group_text(&e.mac.tokens, e.mac.delimiter.span(), source),
+            },
+        ),
+        Expr::Match(e) => {
+            let ranges: Vec<_> = e.arms.iter().map(|arm| source.range(arm)).collect();
+            let (arm_trivia, closing) = siblings(&ranges, &e.brace_token.span, source);
+            (
+                &e.attrs,
+                T::Match {
+                    expr: boxed(&e.expr, unsupported),
+                    arms: e.arms.iter().zip(arm_trivia).map(|(arm, trivia)| ARSMatchArm {
+                        attributes: arm.attrs.iter().map(attributes::from_syn).collect(),
+                        pattern: to_source(&arm.pat),
+                        guard: arm.guard.as_ref().map(|(_, guard)| from_syn(guard, source, unsupported)),
+                        body: from_syn(&arm.body, source, unsupported),
+                        comma: arm.comma.is_some(),
+                        trivia,
+                    }).collect(),
+                    closing,
+                },
+            )
+        }
+        Expr::MethodCall(e) => {
+            let (args, trailing_comma, closing) = list(&e.args, &e.paren_token.span, unsupported);
+            (
+                &e.attrs,
+                T::MethodCall {
+                    receiver: boxed(&e.receiver, unsupported),
+                    method: e.method.to_string(),
+                    turbofish: e.turbofish.as_ref().map(to_source),
+                    args,
+                    trailing_comma,
+                    closing,
+                },
+            )
+        }
+        Expr::Paren(e) => (&e.attrs, T::Paren { expr: boxed(&e.expr, unsupported) }),
+        Expr::Path(e) => (&e.attrs, T::Path(to_source(&syn::ExprPath { attrs: Vec::new(), ..e.clone() }))),
+        Expr::Range(e) => (
+            &e.attrs,
+            T::Range {
+                start: optional(&e.start, unsupported),
+                limits: to_source(&e.limits),
+                end: optional(&e.end, unsupported),
+            },
+        ),
+        Expr::RawAddr(e) => (
+            &e.attrs,
+            T::RawAddr { mutability: to_source(&e.mutability), expr: boxed(&e.expr, unsupported) },
+        ),
+        Expr::Reference(e) => (
+            &e.attrs,
+            T::Reference { mutable: e.mutability.is_some(), expr: boxed(&e.expr, unsupported) },
+        ),
+        Expr::Repeat(e) => (&e.attrs, T::Repeat { expr: boxed(&e.expr, unsupported), len: boxed(&e.len, unsupported) }),
+        Expr::Return(e) => (&e.attrs, T::Return { value: optional(&e.expr, unsupported) }),
+        Expr::Struct(e) => {
+            // `..base` is the last sibling, its trivia kept on the base expression
+            let mut ranges: Vec<_> = e.fields.iter().map(|field| source.range(field)).collect();
+            if let Some(dot2) = &e.dot2_token {
+                let start = source.range(dot2).start;
+                ranges.push(start..e.rest.as_ref().map_or(source.range(dot2).end, |rest| source.range(rest).end));
+            }
+            let (mut field_trivia, closing) = siblings(&ranges, &e.brace_token.span, source);
+            let rest_trivia = if e.dot2_token.is_some() { field_trivia.pop() } else { None };
+            (
+                &e.attrs,
+                T::Struct {
+                    path: match &e.qself {
+                        Some(_) => to_source(&syn::ExprPath { attrs: Vec::new(), qself: e.qself.clone(), path: e.path.clone() }),
+                        None => to_source(&e.path),
+                    },
+                    fields: e.fields.iter().zip(field_trivia).map(|(field, trivia)| ARSFieldValue {
+                        attributes: field.attrs.iter().map(attributes::from_syn).collect(),
+                        member: to_source(&field.member),
+                        shorthand: field.colon_token.is_none(),
+                        expr: from_syn(&field.expr, source, unsupported),
+                        trivia,
+                    }).collect(),
+                    has_rest: e.dot2_token.is_some(),
+                    rest: e.rest.as_ref().map(|rest| Box::new(ARSExpression {
+                        trivia: rest_trivia.unwrap_or_default(),
+                        ..from_syn(rest, source, unsupported)
+                    })),
+                    // The comma before `..` separates, it does not trail
+                    trailing_comma: e.fields.trailing_punct() && e.dot2_token.is_none(),
+                    closing,
+                },
+            )
+        }
+        Expr::Try(e) => (&e.attrs, T::Try { expr: boxed(&e.expr, unsupported) }),
+        Expr::TryBlock(e) => (&e.attrs, T::TryBlock { block: block(&e.block, source, unsupported) }),
+        Expr::Tuple(e) => {
+            let (elements, trailing_comma, closing) = list(&e.elems, &e.paren_token.span, unsupported);
+            (&e.attrs, T::Tuple { elements, trailing_comma, closing })
+        }
+        Expr::Unary(e) => (&e.attrs, T::Unary { op: to_source(&e.op), expr: boxed(&e.expr, unsupported) }),
+        Expr::Unsafe(e) => (&e.attrs, T::Unsafe { block: block(&e.block, source, unsupported) }),
+        Expr::While(e) => (
+            &e.attrs,
+            T::While {
+                label: label(&e.label),
+                condition: boxed(&e.cond, unsupported),
+                body: block(&e.body, source, unsupported),
+            },
+        ),
+        Expr::Yield(e) => (&e.attrs, T::Yield { value: optional(&e.expr, unsupported) }),
+        other => return ARSExpression {
+            attributes: Vec::new(),
+            expression_type: T::Verbatim(to_source(other)),
+            trivia: ARSTrivia::default(),
+        },
+    };
+
+    ARSExpression {
+        attributes: attrs.iter().map(attributes::from_syn).collect(),
+        expression_type,
+        trivia: ARSTrivia::default(),
+    }
+}
+
+/// The trivia of the elements of a delimited list, and before its closing delimiter
+fn siblings(
+    ranges: &[std::ops::Range<usize>],
+    delimiters: &DelimSpan,
+    source: &SourceMap,
+) -> (Vec<ARSTrivia>, Vec<ARSTriviaPiece>) {
+    trivia::for_siblings(
+        source,
+        source.span_range(delimiters.open()).end,
+        GapStart::AfterToken,
+        ranges,
+        source.span_range(delimiters.close()).start,
+    )
+}
+
+fn block(block: &syn::Block, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSBlock {
+    let (statements, closing) = statements::from_block(block, source, unsupported);
+    ARSBlock { statements, closing }
+}
+
+/// Reconstructs the source of a single expression
+pub fn reconstruct_expr(expr: &ARSExpression) -> String {
+    with_stack(|| render(expr))
+}
+
+fn render(expr: &ARSExpression) -> String {
+    use ARSExpressionType as T;
+
+    let (inner, outer): (Vec<&ARSAttribute>, Vec<&ARSAttribute>) =
+        expr.attributes.iter().partition(|attr| attr.is_inner());
+    let with_label = |label: &Option<String>, text: String| match label {
+        Some(label) => format!("{}: {}", label, text),
+        None => text,
+    };
+    let braced = |b: &ARSBlock| reconstruct_block(b, &inner);
+    let optional = |prefix: &str, value: &Option<Box<ARSExpression>>| match value {
+        Some(value) => format!("{} {}", prefix, reconstruct_expr(value)),
+        None => prefix.to_string(),
+    };
+
+    let text = match &expr.expression_type {
+        T::Array { elements, trailing_comma, closing } => format!("[{}]", join(elements, *trailing_comma, closing)),
+        T::Assign { left, right } => format!("{} = {}", reconstruct_expr(left), reconstruct_expr(right)),
+        T::Async { is_move, block } => format!("async {}{}", if *is_move { "move " } else { "" }, braced(block)),
+        T::Await { base } => format!("{}.await", reconstruct_expr(base)),
+        T::Binary { left, op, right } => format!("{} {} {}", reconstruct_expr(left), op, reconstruct_expr(right)),
+        T::Block { label, block } => with_label(label, braced(block)),
+        T::Break { label, value } => {
+            let keyword = label.as_ref().map_or("break".to_string(), |label| format!("break {}", label));
+            optional(&keyword, value)
+        }
+        T::Call { function, args, trailing_comma, closing } => {
+            format!("{}({})", reconstruct_expr(function), join(args, *trailing_comma, closing))
+        }
+        T::Cast { expr, ty } => format!("{} as {}", reconstruct_expr(expr), ty),
+        T::Closure(closure) => reconstruct_closure(closure),
+        T::Const { block } => format!("const {}", braced(block)),
+        T::Continue { label } => label.as_ref().map_or("continue".to_string(), |label| format!("continue {}", label)),
+        T::Field { base, member } => format!("{}.{}", reconstruct_expr(base), member),
+        T::ForLoop { label, pattern, iterable, body } => with_label(
+            label,
+            format!("for {} in {} {}", pattern, reconstruct_expr(iterable), braced(body)),
+        ),
+        T::Group { expr } => reconstruct_expr(expr),
+        T::If { condition, then_branch, else_branch } => {
+            let mut text = format!("if {} {}", reconstruct_expr(condition), reconstruct_block(then_branch, &[]));
+            if let Some(else_branch) = else_branch {
+                text.push_str(&format!(" else {}", reconstruct_expr(else_branch)));
+            }
+            text
+        }
+        T::Index { expr, index } => format!("{}[{}]", reconstruct_expr(expr), reconstruct_expr(index)),
+        T::Infer => "_".to_string(),
+        T::Let { pattern, expr } => format!("let {} = {}", pattern, reconstruct_expr(expr)),
+        T::Lit(lit) => lit.clone(),
+        T::Loop { label, body } => with_label(label, format!("loop {}", braced(body))),
+        T::Macro { path, delimiter, tokens } => macros::reconstruct_call(path, *delimiter, tokens),
+        T::Match { expr, arms, closing } => {
+            let mut lines: Vec<String> = inner.iter().map(|attr| attr.to_string()).collect();
+            lines.extend(arms.iter().map(|arm| {
+                format!("{}{}", trivia::render_lines(&arm.trivia.leading), trivia::with_trailing(&reconstruct_arm(arm), &arm.trivia))
+            }));
+            if !closing.is_empty() {
+                lines.push(trivia::render_lines(closing).trim_end_matches('\n').to_string());
+            }
+            if lines.is_empty() {
+                format!("match {} {{}}", reconstruct_expr(expr))
+            } else {
+                format!("match {} {{\n{}\n}}", reconstruct_expr(expr), indent(&lines.join("\n")))
+            }
+        }
+        T::MethodCall { receiver, method, turbofish, args, trailing_comma, closing } => format!(
+            "{}.{}{}({})",
+            reconstruct_expr(receiver),
+            method,
+            turbofish.as_deref().unwrap_or(""),
+            join(args, *trailing_comma, closing),
+        ),
+        T::Paren { expr } => format!("({})", reconstruct_expr(expr)),
+        T::Path(path) => path.clone(),
+        T::Range { start, limits, end } => format!(
+            "{}{}{}",
+            start.as_ref().map_or(String::new(), |start| reconstruct_expr(start)),
+            limits,
+            end.as_ref().map_or(String::new(), |end| reconstruct_expr(end)),
+        ),
+        T::RawAddr { mutability, expr } => format!("&raw {} {}", mutability, reconstruct_expr(expr)),
+        T::Reference { mutable, expr } => format!("&{}{}", if *mutable { "mut " } else { "" }, reconstruct_expr(expr)),
+        T::Repeat { expr, len } => format!("[{}; {}]", reconstruct_expr(expr), reconstruct_expr(len)),
+        T::Return { value } => optional("return", value),
+        T::Struct { path, fields, has_rest, rest, trailing_comma, closing } => {
+            let no_trivia = ARSTrivia::default();
+            let mut parts: Vec<(String, &ARSTrivia)> = fields.iter().map(|field| {
+                let mut part: String = field.attributes.iter().map(|attr| format!("{} ", attr)).collect();
+                if field.shorthand {
+                    part.push_str(&field.member);
+                } else {
+                    part.push_str(&format!("{}: {}", field.member, reconstruct_expr(&field.expr)));
+                }
+                (part, &field.trivia)
+            }).collect();
+            if *has_rest {
+                parts.push(match rest {
+                    Some(rest) => (format!("..{}", reconstruct_expr(rest)), &rest.trivia),
+                    None => ("..".to_string(), &no_trivia),
+                });
+            }
+            if parts.iter().any(|(_, part_trivia)| !part_trivia.is_empty()) || !closing.is_empty() {
+                format!("{} {{{}}}", path, lines(&parts, *trailing_comma, closing))
+            } else {
+                let mut body = parts.into_iter().map(|(part, _)| part).collect::<Vec<_>>().join(", ");
+                if *trailing_comma {
+                    body.push(',');
+                }
+                if body.is_empty() {
+                    format!("{} {{}}", path)
+                } else {
+                    format!("{} {{ {} }}", path, body)
+                }
+            }
+        }
+        T::Try { expr } => format!("{}?", reconstruct_expr(expr)),
+        T::TryBlock { block } => format!("try {}", braced(block)),
+        T::Tuple { elements, trailing_comma, closing } => format!("({})", join(elements, *trailing_comma, closing)),
+        T::Unary { op, expr } => format!("{}{}", op, reconstruct_expr(expr)),
+        T::Unsafe { block } => format!("unsafe {}", braced(block)),
+        T::Verbatim(text) => text.clone(),
+        T::While { label, condition, body } => with_label(
+            label,
+            format!("while {} {}", reconstruct_expr(condition), braced(body)),
+        ),
+        T::Yield { value } => optional("yield", value),
+    };
+
+    let mut output: String = outer.iter().map(|attr| format!("{} ", attr)).collect();
+    output.push_str(&text);
+    output
+}
+
+/// Reconstructs a braced block, with any inner attributes at its top
+pub fn reconstruct_block(block: &ARSBlock, inner_attributes: &[&ARSAttribute]) -> String {
+    let mut lines: Vec<String> = inner_attributes.iter().map(|attr| attr.to_string()).collect();
+    if !block.statements.is_empty() {
+        lines.push(statements::reconstruct(&block.statements));
+    }
+    if !block.closing.is_empty() {
+        lines.push(trivia::render_lines(&block.closing).trim_end_matches('\n').to_string());
+    }
+    if lines.is_empty() {
+        "{}".to_string()
     } else {
-        Vec::new()
+        format!("{{\n{}\n}}", indent(&lines.join("\n")))
+    }
+}
+
+fn reconstruct_closure(closure: &ARSClosure) -> String {
+    let mut output = String::new();
+    if let Some(lifetimes) = &closure.lifetimes {
+        output.push_str(&format!("{} ", lifetimes));
+    }
+    for (set, keyword) in [
+        (closure.is_const, "const "),
+        (closure.is_static, "static "),
+        (closure.is_async, "async "),
+        (closure.is_move, "move "),
+    ] {
+        if set {
+            output.push_str(keyword);
+        }
+    }
+    let mut inputs = closure.inputs.join(", ");
+    if closure.trailing_comma {
+        inputs.push(',');
+    }
+    output.push_str(&format!("|{}| ", inputs));
+    if let Some(ty) = &closure.output {
+        output.push_str(&format!("-> {} ", ty));
+    }
+    output.push_str(&reconstruct_expr(&closure.body));
+    output
+}
+
+fn reconstruct_arm(arm: &ARSMatchArm) -> String {
+    let mut output: String = arm.attributes.iter().map(|attr| format!("{}\n", attr)).collect();
+    output.push_str(&arm.pattern);
+    if let Some(guard) = &arm.guard {
+        output.push_str(&format!(" if {}", reconstruct_expr(guard)));
+    }
+    output.push_str(&format!(" => {}", reconstruct_expr(&arm.body)));
+    if arm.comma {
+        output.push(',');
+    }
+    output
+}
+
+/// Renders the elements of a list, on one line unless any carries trivia
+fn join(expressions: &[ARSExpression], trailing_comma: bool, closing: &[ARSTriviaPiece]) -> String {
+    if expressions.iter().any(|e| !e.trivia.is_empty()) || !closing.is_empty() {
+        let parts: Vec<_> = expressions.iter().map(|e| (reconstruct_expr(e), &e.trivia)).collect();
+        return lines(&parts, trailing_comma, closing);
     }
+    let mut output = expressions.iter().map(reconstruct_expr).collect::<Vec<String>>().join(", ");
+    if trailing_comma {
+        output.push(',');
+    }
+    output
+}
+
+/// Renders list elements one per line with their trivia, between delimiters
+fn lines(parts: &[(String, &ARSTrivia)], trailing_comma: bool, closing: &[ARSTriviaPiece]) -> String {
+    let mut output = String::new();
+    for (i, (part, part_trivia)) in parts.iter().enumerate() {
+        let comma = if i + 1 < parts.len() || trailing_comma { "," } else { "" };
+        output.push_str(&trivia::render_lines(&part_trivia.leading));
+        output.push_str(&trivia::with_trailing(&format!("{}{}", part, comma), part_trivia));
+        output.push('\n');
+    }
+    output.push_str(&trivia::render_lines(closing));
+    format!("\n{}\n", indent(output.trim_end()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quote::ToTokens;
+
+    fn roundtrip(source: &str) {
+        let expr: Expr = syn::parse_str(source).unwrap();
+        let output = reconstruct_expr(&from_syn(&expr, &SourceMap::new(source), &mut Vec::new()));
+        let reparsed: Expr = syn::parse_str(&output)
+            .unwrap_or_else(|e| panic!("{}: {}", e, output));
+        assert_eq!(
+            expr.to_token_stream().to_string(),
+            reparsed.to_token_stream().to_string(),
+            "{}",
+            output
+        );
+    }
 
     #[test]
     fn test_extract_simple_expression() {
+        let result = extract_ars_expressions("a + 5");
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0].expression_type, ARSExpressionType::Binary { ref op, .. } if op == "+"));
+
         let source = r#"let a = 5;"#;
         let result = extract_ars_expressions(source);
-        assert_eq!(result.len(), 1);
-        assert_eq!(result[0].content, source);
+        assert_eq!(result[0].expression_type, ARSExpressionType::Verbatim(source.to_string()));
+        assert_eq!(reconstruct(&result), source);
+    }
+
+    #[test]
+    fn test_expressions_roundtrip() {
+        for source in [
+            "-x.field.0 * (a + b) as u64",
+            "values.iter().map(|v| v * 2).collect::<Vec<_>>()",
+            "async move { fetch(url).await? }",
+            "'outer: for (i, x) in xs.iter().enumerate() { if i > 2 { break 'outer; } else if x == 1 { continue; } }",
+            "match status { Status::Ok => (), Status::Error(e) if e.is_empty() => { log(e) } _ => unreachable!() }",
+            "Point { x, y: 1, ..Default::default() }",
+            "[0u8; 4][1..=2].len() + (1,).0 + [1, 2,].len()",
+            "while let Some(v) = stack.pop() { *total += v; }",
+            "unsafe { &mut *ptr } ?.ok()",
+            "move |a: i32, b| -> i32 { a + b }",
+            "#[allow(unused)] { #![allow(dead_code)] x = vec![1, 2] }",
+            "loop { return; }",
+        ] {
+            roundtrip(source);
+        }
+    }
+
+    #[test]
+    fn test_comments_in_arms_and_lists_are_kept() {
+        let source = "match (first(a, /* unused */ b), [
+    // one
+    1,
+    2, // two
+]) {
+    // zero
+    (0, _) => Point {
+        x: 0, // origin
+        // rest
+        ..Default::default()
+    },
+    _ => call(
+        x,
+        // nothing else
+    ),
+    // unreachable
+}";
+        let expr: Expr = syn::parse_str(source).unwrap();
+        let output = reconstruct_expr(&from_syn(&expr, &SourceMap::new(source), &mut Vec::new()));
+        assert_eq!(output, source.replace("a, /* unused */ b", "\n    a, /* unused */\n    b\n"));
+        roundtrip(source);
     }
 }
@@ -0,0 +1,255 @@
+use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::functions::{self, ARSFunction};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, parse_items, tokens, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType};
+
+// Generic extract function stub
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
+    extract_ars_foreign_mods(source)
+        .iter()
+        .map(|f| to_ron(f).map_err(WaltError::from))
+        .collect()
+}
+
+pub fn reconstruct(ars_foreign_mods: &[ARSForeignMod]) -> String {
+    let mut output = String::new();
+    for f in ars_foreign_mods {
+        output.push_str(&trivia::render_lines(&f.trivia.leading));
+        output.push_str(&attributes::render_docs(&f.docs));
+        output.push_str(&attributes::reconstruct(&f.attributes));
+
+        if f.is_unsafe {
+            output.push_str("unsafe ");
+        }
+        output.push_str("extern");
+        if let Some(abi) = &f.abi {
+            output.push_str(&format!(" \"{}\"", abi));
+        }
+
+        let mut body = attributes::reconstruct(&f.inner_attributes);
+        for item in &f.items {
+            body.push_str(&format!("{}\n", reconstruct_item(item)));
+        }
+        body.push_str(&trivia::render_lines(&f.trivia.closing));
+        if body.trim().is_empty() {
+            output.push_str(&trivia::with_trailing(" {}", &f.trivia));
+        } else {
+            output.push_str(&format!(" {{\n{}\n", indent(body.trim_end())));
+            output.push_str(&trivia::with_trailing("}", &f.trivia));
+        }
+        output.push_str("\n\n");
+    }
+    output
+}
+
+/// Reconstructs a single foreign item with its trivia, unindented
+fn reconstruct_item(item: &ARSForeignItem) -> String {
+    let text = match item {
+        ARSForeignItem::Fn(func) => format!("{};", functions::reconstruct_signature(func)),
+        ARSForeignItem::Static(s) => {
+            let mut text = attributes::render_docs(&s.docs);
+            text.push_str(&attributes::reconstruct(&s.attributes));
+            let mut_str = if s.mutable { "mut " } else { "" };
+            text.push_str(&format!("{}static {}{}: {};", vis_prefix(&s.visibility), mut_str, s.name, s.ty));
+            text
+        }
+        ARSForeignItem::Type(t) => {
+            let mut text = attributes::render_docs(&t.docs);
+            text.push_str(&attributes::reconstruct(&t.attributes));
+            text.push_str(&format!(
+                "{}type {}{}{};",
+                vis_prefix(&t.visibility),
+                t.name,
+                generics::reconstruct_params(&t.generics),
+                generics::reconstruct_where(&t.generics),
+            ));
+            text
+        }
+        ARSForeignItem::Verbatim { content, .. } => content.clone(),
+    };
+    format!("{}{}", trivia::render_lines(&item.trivia().leading), trivia::with_trailing(&text, item.trivia()))
+}
+
+/// Represents an `extern "ABI" { ... }` block in Animated Rust (.ars) format
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSForeignMod {
+    pub abi: Option<String>,        // The ABI name, e.g. "C", or None for a bare `extern`
+    pub is_unsafe: bool,            // `unsafe extern`
+    pub items: Vec<ARSForeignItem>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_attributes: Vec<ARSAttribute>, // `#![...]` at the top of the block
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,          // `closing` holds the lines after the last foreign item
+}
+
+/// An item declared in an `extern` block
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSForeignItem {
+    Fn(Box<ARSFunction>), // Signature only, its body is empty
+    Static(ARSForeignStatic),
+    Type(ARSForeignType),
+    /// Macro invocations, and anything else syn does not structure
+    Verbatim {
+        content: String,
+        #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+        trivia: ARSTrivia,
+    },
+}
+
+/// A foreign static, `static mut errno: c_int;`, which has no value
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSForeignStatic {
+    pub name: String,
+    pub ty: ARSType,
+    pub mutable: bool,
+    pub visibility: Option<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// An opaque foreign type, `type FILE;`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSForeignType {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub visibility: Option<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+impl ARSForeignItem {
+    /// The comments and blank lines attached to the item
+    pub fn trivia(&self) -> &ARSTrivia {
+        match self {
+            ARSForeignItem::Fn(f) => &f.trivia,
+            ARSForeignItem::Static(s) => &s.trivia,
+            ARSForeignItem::Type(t) => &t.trivia,
+            ARSForeignItem::Verbatim { trivia, .. } => trivia,
+        }
+    }
+
+    pub fn trivia_mut(&mut self) -> &mut ARSTrivia {
+        match self {
+            ARSForeignItem::Fn(f) => &mut f.trivia,
+            ARSForeignItem::Static(s) => &mut s.trivia,
+            ARSForeignItem::Type(t) => &mut t.trivia,
+            ARSForeignItem::Verbatim { trivia, .. } => trivia,
+        }
+    }
+}
+
+/// Converts a syn extern block into an ARSForeignMod
+pub fn from_syn(item: &syn::ItemForeignMod, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSForeignMod {
+    let ranges = item.items.iter().map(|member| source.range(member)).collect();
+    let (inner_attributes, item_trivia, closing) =
+        attributes::inner_with_members(&item.attrs, ranges, &item.brace_token, source);
+
+    let items = item.items.iter().zip(item_trivia).map(|(member, mut member_trivia)| {
+        let mut ars_item = item_from_syn(member, source, unsupported);
+        let target = ars_item.trivia_mut();
+        target.leading = std::mem::take(&mut member_trivia.leading);
+        target.trailing = member_trivia.trailing.take();
+        ars_item
+    }).collect();
+
+    ARSForeignMod {
+        abi: item.abi.name.as_ref().map(|name| name.value()),
+        is_unsafe: item.unsafety.is_some(),
+        items,
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        inner_attributes,
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
+    }
+}
+
+/// Converts a single foreign item; functions go through the function model
+fn item_from_syn(item: &syn::ForeignItem, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSForeignItem {
+    match item {
+        syn::ForeignItem::Fn(f) => ARSForeignItem::Fn(Box::new(functions::from_syn_foreign(f, source, unsupported))),
+        syn::ForeignItem::Static(s) => ARSForeignItem::Static(ARSForeignStatic {
+            name: s.ident.to_string(),
+            ty: types::from_syn(&s.ty, source, unsupported),
+            mutable: matches!(s.mutability, syn::StaticMutability::Mut(_)),
+            visibility: visibility(&s.vis),
+            attributes: attributes::outer(&s.attrs, source),
+            docs: attributes::docs(&s.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        syn::ForeignItem::Type(t) => ARSForeignItem::Type(ARSForeignType {
+            name: t.ident.to_string(),
+            generics: generics::from_syn(&t.generics, source, unsupported),
+            visibility: visibility(&t.vis),
+            attributes: attributes::outer(&t.attrs, source),
+            docs: attributes::docs(&t.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        other => ARSForeignItem::Verbatim { content: tokens::to_source(other), trivia: ARSTrivia::default() },
+    }
+}
+
+/// Encode all extern blocks in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_foreign_mods(source: &str) -> Vec<ARSForeignMod> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::ForeignMod(f) => Some(from_syn(f, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreign_items_are_structured() {
+        let source = "#[link(name = \"c\")]
+unsafe extern \"C\" {
+    // libc
+    pub fn printf(format: *const c_char, ...) -> c_int;
+    static mut errno: c_int;
+    type FILE;
+}
+
+extern {}
+";
+        let result = extract_ars_foreign_mods(source);
+        let libc = &result[0];
+        assert_eq!(libc.abi.as_deref(), Some("C"));
+        assert!(libc.is_unsafe);
+        assert!(matches!(&libc.items[0], ARSForeignItem::Fn(f) if f.name == "printf" && f.variadic.is_some()));
+        assert!(matches!(&libc.items[1], ARSForeignItem::Static(s) if s.mutable && s.ty.to_string() == "c_int"));
+        assert!(matches!(&libc.items[2], ARSForeignItem::Type(t) if t.name == "FILE"));
+        assert!(result[1].abi.is_none());
+        assert_eq!(
+            reconstruct(&result),
+            "#[link(name = \"c\")]\n\
+             unsafe extern \"C\" {\n    \
+             // libc\n    \
+             pub fn printf(format: *const c_char, ...) -> c_int;\n    \
+             static mut errno: c_int;\n    \
+             type FILE;\n\
+             }\n\n\
+             extern {}\n\n"
+        );
+    }
+}
@@ -1,74 +1,272 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
-use crate::syntax_elements::statements;
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::macros::{self, ARSProcMacro};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, parse_items, statements, tokens, vis_prefix, visibility};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
 
 use crate::syntax_elements::statements::ARSStatement;
+use crate::syntax_elements::types::{self, ARSType};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSFunction {
-    pub signature: String,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    pub visibility: Option<String>,
+    #[serde(default)]
+    pub is_default: bool,             // `default fn` in a specializing impl
+    pub is_const: bool,
+    pub is_async: bool,
+    pub is_unsafe: bool,
+    pub abi: Option<String>,          // `extern` qualifier: the ABI name, e.g. "C", or empty for a bare `extern`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proc_macro: Option<ARSProcMacro>, // Set by a `#[proc_macro*]` attribute, which stays in `attributes`
+    pub name: String,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub params: Vec<ARSParam>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variadic: Option<String>,     // Trailing `...` of a C-variadic function
+    #[serde(default)]
+    pub trailing_comma: bool,
+    pub output: Option<ARSType>,      // Return type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_attributes: Vec<ARSAttribute>, // `#![...]` at the top of the body
     pub body: Vec<ARSStatement>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// A function parameter
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSParam {
+    /// `self`, `mut self`, `&'a mut self` or `self: Box<Self>`
+    Receiver {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attributes: Vec<ARSAttribute>,
+        by_reference: bool,
+        lifetime: Option<String>,
+        mutable: bool,       // `&mut self`, or `mut self` when taken by value
+        ty: Option<ARSType>, // Explicit type after `self:`
+    },
+    Typed {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attributes: Vec<ARSAttribute>,
+        pattern: String,
+        ty: ARSType,
+    },
 }
 
 pub fn reconstruct(ars_functions: &[ARSFunction]) -> String {
     let mut output = String::new();
     for func in ars_functions {
-        let body_content = statements::reconstruct(&func.body);
-        let indented_body = body_content.lines().map(|line| format!("    {}", line)).collect::<Vec<String>>().join("\n");
-        output.push_str(&format!("{} {{\n{}\n}}\n\n", func.signature, indented_body));
+        output.push_str(&trivia::render_lines(&func.trivia.leading));
+
+        let mut body_content = attributes::reconstruct(&func.inner_attributes);
+        body_content.push_str(&statements::reconstruct(&func.body));
+        body_content.truncate(body_content.trim_end_matches('\n').len());
+        if !func.trivia.closing.is_empty() {
+            if !body_content.is_empty() {
+                body_content.push('\n');
+            }
+            body_content.push_str(trivia::render_lines(&func.trivia.closing).trim_end_matches('\n'));
+        }
+
+        let signature = reconstruct_signature(func);
+        let text = if body_content.is_empty() {
+            format!("{} {{}}", signature)
+        } else {
+            format!("{} {{\n{}\n}}", signature, indent(&body_content))
+        };
+        output.push_str(&trivia::with_trailing(&text, &func.trivia));
+        output.push_str("\n\n");
     }
     output
 }
 
-pub fn extract(source: &str) -> Vec<String> {
-    extract_ars_functions(source)
-        .iter()
-        .map(|f| ron::to_string(f).unwrap())
-        .collect()
+/// Rebuilds the signature of a function: attributes, qualifiers, name, generics, parameters, return type and where clause
+pub fn reconstruct_signature(func: &ARSFunction) -> String {
+    let mut output = attributes::render_docs(&func.docs);
+    output.push_str(&attributes::reconstruct(&func.attributes));
+    output.push_str(&vis_prefix(&func.visibility));
+    for (set, keyword) in [
+        (func.is_default, "default "),
+        (func.is_const, "const "),
+        (func.is_async, "async "),
+        (func.is_unsafe, "unsafe "),
+    ] {
+        if set {
+            output.push_str(keyword);
+        }
+    }
+    match func.abi.as_deref() {
+        Some("") => output.push_str("extern "),
+        Some(abi) => output.push_str(&format!("extern \"{}\" ", abi)),
+        None => {}
+    }
+
+    let mut params: Vec<String> = func.params.iter().map(reconstruct_param).collect();
+    params.extend(func.variadic.clone());
+    let mut params = params.join(", ");
+    if func.trailing_comma {
+        params.push(',');
+    }
+    output.push_str(&format!("fn {}{}({})", func.name, generics::reconstruct_params(&func.generics), params));
+    if let Some(ty) = &func.output {
+        output.push_str(&format!(" -> {}", ty));
+    }
+    output.push_str(&generics::reconstruct_where(&func.generics));
+    output
 }
 
-pub fn extract_ars_functions(source: &str) -> Vec<ARSFunction> {
-    let mut functions = Vec::new();
-    // This regex finds the start of a function signature.
-    let re = Regex::new(r"(?m)(?:(#\[.*?\]\s*)*)?((?:pub(?:\(crate\))?\s*)?(?:unsafe\s+)?(?:async\s+)?(?:const\s+)?fn\s+[\w\d_]+\s*(?:<.*?>)?\s*\(.*?\)\s*(?:->\s*[\w\d_<>&\s]+)?)").unwrap();
-
-    for cap in re.captures_iter(source) {
-        let _signature_match = cap.get(2).unwrap();
-        let signature_with_attrs = cap.get(0).unwrap().as_str();
-
-        let start_of_body = cap.get(0).unwrap().end();
-        let mut brace_level = 0;
-        let mut body_start_index = 0;
-        let mut body_end_index = 0;
-
-        for (i, c) in source[start_of_body..].char_indices() {
-            if c == '{' {
-                if brace_level == 0 {
-                    body_start_index = start_of_body + i + 1;
-                }
-                brace_level += 1;
-            } else if c == '}' {
-                brace_level -= 1;
-                if brace_level == 0 {
-                    body_end_index = start_of_body + i;
-                    break;
+fn reconstruct_param(param: &ARSParam) -> String {
+    let (attributes, text) = match param {
+        ARSParam::Receiver { attributes, by_reference, lifetime, mutable, ty } => {
+            let mut text = String::new();
+            if *by_reference {
+                text.push('&');
+                if let Some(lifetime) = lifetime {
+                    text.push_str(&format!("{} ", lifetime));
                 }
             }
+            if *mutable {
+                text.push_str("mut ");
+            }
+            text.push_str("self");
+            if let Some(ty) = ty {
+                text.push_str(&format!(": {}", ty));
+            }
+            (attributes, text)
         }
+        ARSParam::Typed { attributes, pattern, ty } => (attributes, format!("{}: {}", pattern, ty)),
+    };
+    let mut output: String = attributes.iter().map(|attr| format!("{} ", attr)).collect();
+    output.push_str(&text);
+    output
+}
 
-        if body_end_index > body_start_index {
-            let body_content = &source[body_start_index..body_end_index].trim();
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
+    extract_ars_functions(source)
+        .iter()
+        .map(|f| to_ron(f).map_err(WaltError::from))
+        .collect()
+}
 
-            let statements = statements::extract_ars_statements(body_content);
+/// Converts a syn free function into an ARSFunction
+pub fn from_syn(item: &syn::ItemFn, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSFunction {
+    let func = from_signature(&item.attrs, &item.vis, None, &item.sig, source, unsupported);
+    with_body(func, &item.attrs, &item.block, source, unsupported)
+}
 
-            functions.push(ARSFunction {
-                signature: signature_with_attrs.trim().to_string(),
-                body: statements,
-            });
-        }
+/// Converts a method inside an impl block into an ARSFunction
+pub fn from_syn_impl(item: &syn::ImplItemFn, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSFunction {
+    with_body(
+        from_signature(&item.attrs, &item.vis, item.defaultness.as_ref(), &item.sig, source, unsupported),
+        &item.attrs,
+        &item.block,
+        source,
+        unsupported,
+    )
+}
+
+/// Converts a trait method into an ARSFunction; required methods get an empty body
+pub fn from_syn_trait(item: &syn::TraitItemFn, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSFunction {
+    let func = from_signature(&item.attrs, &syn::Visibility::Inherited, None, &item.sig, source, unsupported);
+    match &item.default {
+        Some(block) => with_body(func, &item.attrs, block, source, unsupported),
+        None => func,
     }
+}
+
+/// Converts a function declared in an `extern` block; it has no body
+pub fn from_syn_foreign(
+    item: &syn::ForeignItemFn,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> ARSFunction {
+    from_signature(&item.attrs, &item.vis, None, &item.sig, source, unsupported)
+}
 
-    functions
+fn with_body(
+    mut func: ARSFunction,
+    attrs: &[syn::Attribute],
+    block: &syn::Block,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> ARSFunction {
+    let (inner_attributes, body, closing) = statements::from_body(attrs, block, source, unsupported);
+    func.inner_attributes = inner_attributes;
+    func.body = body;
+    func.trivia.closing = closing;
+    func
+}
+
+/// Splits attributes, visibility and a syn signature into an ARSFunction with an empty body
+fn from_signature(
+    attrs: &[syn::Attribute],
+    vis: &syn::Visibility,
+    defaultness: Option<&syn::token::Default>,
+    sig: &syn::Signature,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> ARSFunction {
+    ARSFunction {
+        attributes: attributes::outer(attrs, source),
+        docs: attributes::docs(attrs),
+        visibility: visibility(vis),
+        is_default: defaultness.is_some(),
+        is_const: sig.constness.is_some(),
+        is_async: sig.asyncness.is_some(),
+        is_unsafe: sig.unsafety.is_some(),
+        abi: sig.abi.as_ref().map(|abi| abi.name.as_ref().map_or(String::new(), |name| name.value())),
+        proc_macro: macros::proc_macro_kind(attrs),
+        name: sig.ident.to_string(),
+        generics: generics::from_syn(&sig.generics, source, unsupported),
+        params: sig.inputs.iter().map(|arg| param(arg, source, unsupported)).collect(),
+        variadic: sig.variadic.as_ref().map(|variadic| {
+            tokens::to_source(&syn::Variadic { comma: None, ..variadic.clone() })
+        }),
+        trailing_comma: match &sig.variadic {
+            Some(variadic) => variadic.comma.is_some(),
+            None => sig.inputs.trailing_punct(),
+        },
+        output: types::return_type(&sig.output, source, unsupported),
+        inner_attributes: Vec::new(),
+        body: Vec::new(),
+        trivia: ARSTrivia::default(),
+    }
+}
+
+fn param(arg: &syn::FnArg, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSParam {
+    match arg {
+        syn::FnArg::Receiver(receiver) => ARSParam::Receiver {
+            attributes: receiver.attrs.iter().map(attributes::from_syn).collect(),
+            by_reference: receiver.reference.is_some(),
+            lifetime: receiver.reference.as_ref().and_then(|(_, lifetime)| lifetime.as_ref().map(tokens::to_source)),
+            mutable: receiver.mutability.is_some(),
+            ty: receiver.colon_token.map(|_| types::from_syn(&receiver.ty, source, unsupported)),
+        },
+        syn::FnArg::Typed(typed) => ARSParam::Typed {
+            attributes: typed.attrs.iter().map(attributes::from_syn).collect(),
+            pattern: tokens::to_source(&typed.pat),
+            ty: types::from_syn(&typed.ty, source, unsupported),
+        },
+    }
+}
+
+pub fn extract_ars_functions(source: &str) -> Vec<ARSFunction> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Fn(f) => Some(from_syn(f, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -88,13 +286,46 @@ fn my_func() -> i32 {
         "#;
         let result = extract_ars_functions(source);
         assert_eq!(result.len(), 1);
-        assert_eq!(result[0].signature, "fn my_func() -> i32");
+        assert_eq!(result[0].name, "my_func");
+        assert_eq!(result[0].output.as_ref().map(ARSType::to_string).as_deref(), Some("i32"));
+        assert_eq!(reconstruct_signature(&result[0]), "fn my_func() -> i32");
         assert_eq!(result[0].body.len(), 3);
-        assert_eq!(result[0].body[0].stmt_type, "Local");
-        assert_eq!(result[0].body[0].content, "let x = 5 ;");
-        assert_eq!(result[0].body[1].stmt_type, "Expr");
-        assert_eq!(result[0].body[1].content, "if x > 0 { return 1 ; }");
-        assert_eq!(result[0].body[2].stmt_type, "Expr");
-        assert_eq!(result[0].body[2].content, "x + 1");
+        assert!(matches!(&result[0].body[0], ARSStatement::Local(local) if local.pattern == "x"));
+        assert!(matches!(&result[0].body[1], ARSStatement::Expr { semicolon: false, .. }));
+        assert!(matches!(&result[0].body[2], ARSStatement::Expr { semicolon: false, .. }));
+        assert_eq!(
+            statements::reconstruct(&result[0].body),
+            "let x = 5;\nif x > 0 {\n    return 1;\n}\nx + 1"
+        );
+    }
+
+    #[test]
+    fn test_signature_parts_roundtrip() {
+        let source = r#"
+#[inline]
+pub(crate) const async unsafe extern "C" fn run<'a, T: Clone>(#[cfg(x)] mut a: &'a T, (b, c): (u8, u8),) -> Result<Vec<(A, B)>, E> where T: 'a {}
+"#;
+        let result = extract_ars_functions(source);
+        let func = &result[0];
+        assert_eq!(func.attributes[0].to_string(), "#[inline]");
+        assert_eq!(func.visibility.as_deref(), Some("pub(crate)"));
+        assert!(func.is_const && func.is_async && func.is_unsafe);
+        assert_eq!(func.abi.as_deref(), Some("C"));
+        assert_eq!(generics::reconstruct_where(&func.generics), " where T: 'a");
+        assert!(matches!(&func.output, Some(ARSType::Path { segments, .. }) if segments[0].name == "Result"));
+        assert_eq!(func.output.as_ref().map(ARSType::to_string).as_deref(), Some("Result<Vec<(A, B)>, E>"));
+        assert!(func.trailing_comma);
+        assert_eq!(
+            reconstruct_signature(func),
+            "#[inline]\npub(crate) const async unsafe extern \"C\" fn run<'a, T: Clone>(#[cfg(x)] mut a: &'a T, (b, c): (u8, u8),) -> Result<Vec<(A, B)>, E> where T: 'a"
+        );
+
+        for method in ["fn f(self)", "fn f(mut self)", "fn f(&'a mut self, x: impl Fn())", "fn f(self: Box<Self>)"] {
+            let text = format!("{} {{}}", method);
+            let item: syn::ImplItemFn = syn::parse_str(&text).unwrap();
+            let func = from_syn_impl(&item, &SourceMap::new(&text), &mut Vec::new());
+            assert!(matches!(func.params[0], ARSParam::Receiver { .. }));
+            assert_eq!(reconstruct_signature(&func), method);
+        }
     }
 }
@@ -0,0 +1,218 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::ars_file::ARSUnsupported;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::attributes::{self, ARSAttribute};
+use crate::syntax_elements::tokens;
+use crate::syntax_elements::types::{self, ARSType, ARSTypeParamBound};
+
+/// The generic parameters and where clause of an item, shared by every item kind
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ARSGenerics {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<ARSGenericParam>,       // In source order
+    #[serde(default)]
+    pub trailing_comma: bool,               // After the last parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub where_clause: Option<ARSWhereClause>,
+}
+
+/// A single generic parameter
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSGenericParam {
+    /// `'a: 'b`
+    Lifetime {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attributes: Vec<ARSAttribute>,
+        name: String,                       // Including the `'`
+        bounds: Vec<String>,
+    },
+    /// `T: Clone + Send = u8`
+    Type {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attributes: Vec<ARSAttribute>,
+        name: String,
+        bounds: Vec<ARSTypeParamBound>,
+        default: Option<ARSType>,
+    },
+    /// `const N: usize = 4`
+    Const {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attributes: Vec<ARSAttribute>,
+        name: String,
+        ty: ARSType,
+        default: Option<String>,
+    },
+}
+
+/// `where T: Clone, 'a: 'b`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ARSWhereClause {
+    pub predicates: Vec<ARSWherePredicate>,
+    #[serde(default)]
+    pub trailing_comma: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSWherePredicate {
+    /// `'a: 'b + 'c`
+    Lifetime { lifetime: String, bounds: Vec<String> },
+    /// `for<'a> F: Fn(&'a T)`
+    Type { for_lifetimes: Option<String>, ty: ARSType, bounds: Vec<ARSTypeParamBound> },
+}
+
+impl ARSGenerics {
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty() && self.where_clause.is_none()
+    }
+}
+
+/// Converts syn generics into ARSGenerics
+pub fn from_syn(generics: &syn::Generics, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSGenerics {
+    ARSGenerics {
+        params: generics.params.iter().map(|p| param(p, source, unsupported)).collect(),
+        trailing_comma: generics.params.trailing_punct(),
+        where_clause: generics.where_clause.as_ref().map(|where_clause| ARSWhereClause {
+            predicates: where_clause.predicates.iter().map(|p| predicate(p, source, unsupported)).collect(),
+            trailing_comma: where_clause.predicates.trailing_punct(),
+        }),
+    }
+}
+
+fn param(param: &syn::GenericParam, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSGenericParam {
+    match param {
+        syn::GenericParam::Lifetime(l) => ARSGenericParam::Lifetime {
+            attributes: l.attrs.iter().map(attributes::from_syn).collect(),
+            name: tokens::to_source(&l.lifetime),
+            bounds: l.bounds.iter().map(tokens::to_source).collect(),
+        },
+        syn::GenericParam::Type(t) => ARSGenericParam::Type {
+            attributes: t.attrs.iter().map(attributes::from_syn).collect(),
+            name: t.ident.to_string(),
+            bounds: types::bounds(&t.bounds, source, unsupported),
+            default: t.default.as_ref().map(|ty| types::from_syn(ty, source, unsupported)),
+        },
+        syn::GenericParam::Const(c) => ARSGenericParam::Const {
+            attributes: c.attrs.iter().map(attributes::from_syn).collect(),
+            name: c.ident.to_string(),
+            ty: types::from_syn(&c.ty, source, unsupported),
+            default: c.default.as_ref().map(tokens::to_source),
+        },
+    }
+}
+
+fn predicate(
+    predicate: &syn::WherePredicate,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> ARSWherePredicate {
+    match predicate {
+        syn::WherePredicate::Lifetime(l) => ARSWherePredicate::Lifetime {
+            lifetime: tokens::to_source(&l.lifetime),
+            bounds: l.bounds.iter().map(tokens::to_source).collect(),
+        },
+        syn::WherePredicate::Type(t) => ARSWherePredicate::Type {
+            for_lifetimes: t.lifetimes.as_ref().map(tokens::to_source),
+            ty: types::from_syn(&t.bounded_ty, source, unsupported),
+            bounds: types::bounds(&t.bounds, source, unsupported),
+        },
+        // syn's predicate enum is non-exhaustive; keep anything new as a bound-less type
+        other => ARSWherePredicate::Type {
+            for_lifetimes: None,
+            ty: ARSType::Verbatim(tokens::to_source(other)),
+            bounds: Vec::new(),
+        },
+    }
+}
+
+/// Renders the parameter list, `<'a, T: Clone = u8, const N: usize>`, or nothing without parameters
+pub fn reconstruct_params(generics: &ARSGenerics) -> String {
+    if generics.params.is_empty() {
+        return String::new();
+    }
+    let mut params = generics.params.iter().map(reconstruct_param).collect::<Vec<_>>().join(", ");
+    if generics.trailing_comma {
+        params.push(',');
+    }
+    format!("<{}>", params)
+}
+
+/// Renders the where clause with a leading space, ` where T: Clone`, or nothing without one
+pub fn reconstruct_where(generics: &ARSGenerics) -> String {
+    let Some(where_clause) = &generics.where_clause else {
+        return String::new();
+    };
+    let mut predicates = where_clause.predicates.iter().map(|predicate| match predicate {
+        ARSWherePredicate::Lifetime { lifetime, bounds } => with_bounds(lifetime.clone(), bounds),
+        ARSWherePredicate::Type { for_lifetimes, ty, bounds } => match for_lifetimes {
+            Some(for_lifetimes) => with_bounds(format!("{} {}", for_lifetimes, ty), bounds),
+            None => with_bounds(ty.to_string(), bounds),
+        },
+    }).collect::<Vec<_>>().join(", ");
+    if where_clause.trailing_comma {
+        predicates.push(',');
+    }
+    if predicates.is_empty() {
+        " where".to_string()
+    } else {
+        format!(" where {}", predicates)
+    }
+}
+
+fn reconstruct_param(param: &ARSGenericParam) -> String {
+    let (attributes, text) = match param {
+        ARSGenericParam::Lifetime { attributes, name, bounds } => (attributes, with_bounds(name.clone(), bounds)),
+        ARSGenericParam::Type { attributes, name, bounds, default } => {
+            let mut text = with_bounds(name.clone(), bounds);
+            if let Some(default) = default {
+                text.push_str(&format!(" = {}", default));
+            }
+            (attributes, text)
+        }
+        ARSGenericParam::Const { attributes, name, ty, default } => {
+            let mut text = format!("const {}: {}", name, ty);
+            if let Some(default) = default {
+                text.push_str(&format!(" = {}", default));
+            }
+            (attributes, text)
+        }
+    };
+    attributes.iter().map(|attr| format!("{} ", attr)).collect::<String>() + &text
+}
+
+/// `T: A + B`, or just `T` without bounds
+fn with_bounds<B: fmt::Display>(subject: String, bounds: &[B]) -> String {
+    if bounds.is_empty() {
+        subject
+    } else {
+        format!("{}: {}", subject, bounds.iter().map(B::to_string).collect::<Vec<_>>().join(" + "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_and_predicates_are_structured() {
+        let source = "fn f<'a: 'b, T: Into<Vec<U>> + ?Sized = u8, const N: usize = 4>() where for<'c> F: Fn(&'c T), 'b: 'a, {}";
+        let item: syn::ItemFn = syn::parse_str(source).unwrap();
+        let generics = from_syn(&item.sig.generics, &SourceMap::new(source), &mut Vec::new());
+        assert!(matches!(&generics.params[0], ARSGenericParam::Lifetime { name, bounds, .. } if name == "'a" && bounds == &["'b"]));
+        assert!(matches!(
+            &generics.params[1],
+            ARSGenericParam::Type { bounds, default: Some(ARSType::Path { .. }), .. }
+                if matches!(&bounds[..], [ARSTypeParamBound::Trait(into), ARSTypeParamBound::Trait(sized)]
+                    if into.segments[0].name == "Into" && !into.maybe && sized.maybe)
+        ));
+        assert!(matches!(&generics.params[2], ARSGenericParam::Const { ty, .. } if ty.to_string() == "usize"));
+        let where_clause = generics.where_clause.as_ref().unwrap();
+        assert!(where_clause.trailing_comma);
+        assert!(matches!(
+            &where_clause.predicates[0],
+            ARSWherePredicate::Type { for_lifetimes: Some(for_lifetimes), ty, .. } if for_lifetimes == "for<'c>" && ty.to_string() == "F"
+        ));
+        assert_eq!(reconstruct_params(&generics), "<'a: 'b, T: Into<Vec<U>> + ?Sized = u8, const N: usize = 4>");
+        assert_eq!(reconstruct_where(&generics), " where for<'c> F: Fn(&'c T), 'b: 'a,");
+    }
+}
@@ -1,11 +1,20 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::{self, ARSExpression};
+use crate::syntax_elements::functions::{self, ARSFunction};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, parse_items, tokens, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType};
 
 // Generic extract function stub
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_impls(source)
         .iter()
-        .map(|i| ron::to_string(i).unwrap())
+        .map(|i| to_ron(i).map_err(WaltError::from))
         .collect()
 }
 
@@ -13,128 +22,294 @@ pub fn extract(source: &str) -> Vec<String> {
 pub fn reconstruct(ars_impls: &[ARSImpl]) -> String {
     let mut output = String::new();
     for i in ars_impls {
-        for attr in &i.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
+        output.push_str(&trivia::render_lines(&i.trivia.leading));
+        output.push_str(&attributes::render_docs(&i.docs));
+        output.push_str(&attributes::reconstruct(&i.attributes));
 
-        let generics = i.generics.as_deref().unwrap_or("");
-        
-        output.push_str(&format!("impl{}", generics));
+        if i.is_default {
+            output.push_str("default ");
+        }
+        if i.is_unsafe {
+            output.push_str("unsafe ");
+        }
+        output.push_str(&format!("impl{}", generics::reconstruct_params(&i.generics)));
 
         if let Some(trait_name) = &i.trait_name {
-            output.push_str(&format!(" {} for", trait_name));
+            let negation = if i.is_negative { "!" } else { "" };
+            output.push_str(&format!(" {}{} for", negation, trait_name));
         }
-        
-        output.push_str(&format!(" {} {{\n", i.target));
-
-        for item in &i.items {
-            output.push_str(&format!("    {}\n", item));
+        output.push_str(&format!(" {}", i.target));
+        output.push_str(&generics::reconstruct_where(&i.generics));
+        if i.items.is_empty() && i.inner_attributes.is_empty() && i.trivia.closing.is_empty() {
+            output.push_str(&trivia::with_trailing(" {}", &i.trivia));
+        } else {
+            output.push_str(" {\n");
+            output.push_str(&indent(&attributes::reconstruct(&i.inner_attributes)));
+            for item in &i.items {
+                output.push_str(&format!("{}\n", indent(&reconstruct_item(item))));
+            }
+            output.push_str(&indent(&trivia::render_lines(&i.trivia.closing)));
+            output.push_str(&trivia::with_trailing("}", &i.trivia));
         }
-        output.push_str("}\n\n");
+        output.push_str("\n\n");
     }
     output
 }
 
+/// Reconstructs a single impl member with its trivia, unindented
+fn reconstruct_item(item: &ARSImplItem) -> String {
+    let text = match item {
+        // Methods render their own trivia
+        ARSImplItem::Method(method) => return functions::reconstruct(std::slice::from_ref(method)).trim_end().to_string(),
+        ARSImplItem::Const(c) => {
+            let mut text = attributes::render_docs(&c.docs);
+            text.push_str(&attributes::reconstruct(&c.attributes));
+            text.push_str(&vis_prefix(&c.visibility));
+            if c.is_default {
+                text.push_str("default ");
+            }
+            text.push_str(&format!(
+                "const {}{}: {} = {}",
+                c.name,
+                generics::reconstruct_params(&c.generics),
+                c.ty,
+                expressions::reconstruct_expr(&c.value),
+            ));
+            text.push_str(&generics::reconstruct_where(&c.generics));
+            text.push(';');
+            text
+        }
+        ARSImplItem::Type(t) => {
+            let mut text = attributes::render_docs(&t.docs);
+            text.push_str(&attributes::reconstruct(&t.attributes));
+            text.push_str(&vis_prefix(&t.visibility));
+            if t.is_default {
+                text.push_str("default ");
+            }
+            text.push_str(&format!("type {}{} = {}", t.name, generics::reconstruct_params(&t.generics), t.ty));
+            text.push_str(&generics::reconstruct_where(&t.generics));
+            text.push(';');
+            text
+        }
+        ARSImplItem::Verbatim { content, .. } => content.clone(),
+    };
+    format!("{}{}", trivia::render_lines(&item.trivia().leading), trivia::with_trailing(&text, item.trivia()))
+}
+
 /// Represents a Rust impl block in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSImpl {
-    pub target: String,              // Type being implemented
+    pub target: ARSType,             // Type being implemented
     pub trait_name: Option<String>,  // Trait name if `impl Trait for Type`
-    pub items: Vec<String>,          // Methods, constants, associated types
-    pub generics: Option<String>,
-    pub attributes: Vec<String>,
-    pub visibility: Option<String>,  // Currently impl blocks don't have visibility in Rust, optional
+    pub is_negative: bool,           // `impl !Trait for Type`
+    pub items: Vec<ARSImplItem>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_attributes: Vec<ARSAttribute>, // `#![...]` at the top of the impl block
+    pub is_unsafe: bool,
+    pub is_default: bool,            // `default impl` under specialization
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
-/// Encode all impl blocks in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_impls(source: &str) -> Vec<ARSImpl> {
-    let mut impls = Vec::new();
-
-    // This regex is complex. It tries to capture generics, the type/trait being implemented, and the target type.
-    let impl_regex = Regex::new(
-        r"(?s)((?:#\[.*?\]\s*)*)impl\s*(<[^>]*>)?\s*(.*?)\s*for\s*(.*?)\s*\{"
-    ).unwrap();
-    let simple_impl_regex = Regex::new(
-        r"(?s)((?:#\[.*?\]\s*)*)impl\s*(<[^>]*>)?\s*(.*?)\s*\{"
-    ).unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
-
-    // First pass for `impl Trait for Type`
-    for cap in impl_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let generics = cap.get(2).map(|m| m.as_str().to_string());
-        let trait_name = cap.get(3).map(|m| m.as_str().trim().to_string());
-        let target = cap.get(4).map(|m| m.as_str().trim().to_string()).unwrap_or_default();
-
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-
-        let body_start = full_match.end();
-        let (items, _) = parse_body(source, body_start);
-
-        impls.push(ARSImpl {
-            target,
-            trait_name,
-            items,
-            generics,
-            attributes,
-            visibility: None,
-        });
+/// A member of an impl block
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSImplItem {
+    Method(ARSFunction),
+    Const(ARSImplConst),
+    Type(ARSImplType),
+    /// Macro invocations, and anything else syn does not structure
+    Verbatim {
+        content: String,
+        #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+        trivia: ARSTrivia,
+    },
+}
+
+/// An associated constant, `const N: usize = 4;`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSImplConst {
+    pub name: String,
+    pub ty: ARSType,
+    pub value: ARSExpression,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub visibility: Option<String>,
+    pub is_default: bool,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// An associated type, `type Item<'a> = &'a T where Self: 'a;`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSImplType {
+    pub name: String,
+    pub ty: ARSType,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub visibility: Option<String>,
+    pub is_default: bool,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+impl ARSImplItem {
+    /// The comments and blank lines attached to the member
+    pub fn trivia(&self) -> &ARSTrivia {
+        match self {
+            ARSImplItem::Method(m) => &m.trivia,
+            ARSImplItem::Const(c) => &c.trivia,
+            ARSImplItem::Type(t) => &t.trivia,
+            ARSImplItem::Verbatim { trivia, .. } => trivia,
+        }
     }
-    
-    // Second pass for `impl Type`
-    for cap in simple_impl_regex.captures_iter(source) {
-        // Avoid double-matching `impl Trait for Type`
-        if cap.get(0).unwrap().as_str().contains(" for ") {
-            continue;
+
+    pub fn trivia_mut(&mut self) -> &mut ARSTrivia {
+        match self {
+            ARSImplItem::Method(m) => &mut m.trivia,
+            ARSImplItem::Const(c) => &mut c.trivia,
+            ARSImplItem::Type(t) => &mut t.trivia,
+            ARSImplItem::Verbatim { trivia, .. } => trivia,
         }
+    }
+}
 
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let generics = cap.get(2).map(|m| m.as_str().to_string());
-        let target = cap.get(3).map(|m| m.as_str().trim().to_string()).unwrap_or_default();
-        
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-        
-        let body_start = full_match.end();
-        let (items, _) = parse_body(source, body_start);
-
-        impls.push(ARSImpl {
-            target,
-            trait_name: None,
-            items,
-            generics,
-            attributes,
-            visibility: None,
-        });
+/// Converts a syn impl block into an ARSImpl
+pub fn from_syn(item: &syn::ItemImpl, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSImpl {
+    let ranges = item.items.iter().map(|member| source.range(member)).collect();
+    let (inner_attributes, mut item_trivia, closing) =
+        attributes::inner_with_members(&item.attrs, ranges, &item.brace_token, source);
+
+    let items = item.items.iter().zip(item_trivia.iter_mut()).map(|(member, member_trivia)| {
+        let mut ars_item = item_from_syn(member, source, unsupported);
+        let target = ars_item.trivia_mut();
+        target.leading = std::mem::take(&mut member_trivia.leading);
+        target.trailing = member_trivia.trailing.take();
+        ars_item
+    }).collect();
+
+    ARSImpl {
+        target: types::from_syn(&item.self_ty, source, unsupported),
+        trait_name: item.trait_.as_ref().map(|(_, path, _)| tokens::to_source(path)),
+        is_negative: item.trait_.as_ref().is_some_and(|(bang, _, _)| bang.is_some()),
+        items,
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        inner_attributes,
+        is_unsafe: item.unsafety.is_some(),
+        is_default: item.defaultness.is_some(),
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
     }
+}
 
-    impls
+/// Converts a single impl member; methods go through the function model so their bodies stay structured
+fn item_from_syn(item: &syn::ImplItem, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSImplItem {
+    match item {
+        syn::ImplItem::Fn(method) => ARSImplItem::Method(functions::from_syn_impl(method, source, unsupported)),
+        syn::ImplItem::Const(c) => ARSImplItem::Const(ARSImplConst {
+            name: c.ident.to_string(),
+            ty: types::from_syn(&c.ty, source, unsupported),
+            value: expressions::from_syn(&c.expr, source, unsupported),
+            generics: generics::from_syn(&c.generics, source, unsupported),
+            visibility: visibility(&c.vis),
+            is_default: c.defaultness.is_some(),
+            attributes: attributes::outer(&c.attrs, source),
+            docs: attributes::docs(&c.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        syn::ImplItem::Type(t) => ARSImplItem::Type(ARSImplType {
+            name: t.ident.to_string(),
+            ty: types::from_syn(&t.ty, source, unsupported),
+            generics: generics::from_syn(&t.generics, source, unsupported),
+            visibility: visibility(&t.vis),
+            is_default: t.defaultness.is_some(),
+            attributes: attributes::outer(&t.attrs, source),
+            docs: attributes::docs(&t.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        other => ARSImplItem::Verbatim { content: tokens::to_source(other), trivia: ARSTrivia::default() },
+    }
 }
 
-fn parse_body(source: &str, start: usize) -> (Vec<String>, usize) {
-    let mut items = Vec::new();
-    let mut brace_count = 1;
-    let mut body_end = start;
-    for (i, c) in source[start..].char_indices() {
-        if c == '{' { brace_count += 1; }
-        if c == '}' { brace_count -= 1; }
-        if brace_count == 0 {
-            body_end = start + i;
-            break;
-        }
+/// Encode all impl blocks in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_impls(source: &str) -> Vec<ARSImpl> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Impl(i) => Some(from_syn(i, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impl_members_are_structured() {
+        let source = "
+fn f() { for x in xs {} }
+
+unsafe impl<T> !Send for Wrapper<T> where T: Copy {}
+
+impl<T: Clone> Iterator for Items<T> {
+    // The yielded type
+    type Item<'a> = &'a T where Self: 'a;
+    const LIMIT: usize = 4;
+
+    default fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next();
+        item
     }
-    if body_end > start {
-        let body = &source[start..body_end];
-        items = body
-            .lines()
-            .map(|l| l.trim().to_string())
-            .filter(|l| !l.is_empty())
-            .collect();
+    forward! {}
+}
+";
+        let result = extract_ars_impls(source);
+        assert_eq!(result.len(), 2);
+        assert!(result[0].is_unsafe && result[0].is_negative);
+        assert_eq!(generics::reconstruct_where(&result[0].generics), " where T: Copy");
+        assert!(matches!(&result[1].items[0], ARSImplItem::Type(t) if t.generics.where_clause.is_some()));
+        assert!(matches!(&result[1].items[1], ARSImplItem::Const(c) if expressions::reconstruct_expr(&c.value) == "4"));
+        assert!(matches!(&result[1].items[2], ARSImplItem::Method(m) if m.is_default && m.body.len() == 2));
+        assert!(matches!(&result[1].items[3], ARSImplItem::Verbatim { .. }));
+        assert_eq!(
+            reconstruct(&result),
+            "unsafe impl<T> !Send for Wrapper<T> where T: Copy {}\n\n\
+             impl<T: Clone> Iterator for Items<T> {\n    \
+             // The yielded type\n    \
+             type Item<'a> = &'a T where Self: 'a;\n    \
+             const LIMIT: usize = 4;\n\n    \
+             default fn next(&mut self) -> Option<Self::Item> {\n        \
+             let item = self.inner.next();\n        \
+             item\n    \
+             }\n    \
+             forward!{}\n\
+             }\n\n"
+        );
+    }
+
+    #[test]
+    fn test_inner_attributes_of_impls_and_methods_are_kept() {
+        let source = "impl Parser {\n    #![allow(clippy::all)]\n\n    // First method\n    fn is_new_arg(&self) -> bool {\n        #![allow(clippy::needless_bool)] // why\n        true\n    }\n\n    fn empty() {\n        #![inline]\n    }\n}\n\n";
+        let result = extract_ars_impls(source);
+        assert_eq!(result[0].inner_attributes[0].to_string(), "#![allow(clippy::all)]");
+        assert!(result[0].attributes.is_empty());
+        assert!(matches!(&result[0].items[0], ARSImplItem::Method(m)
+            if m.inner_attributes[0].trivia.trailing.as_deref() == Some("// why") && m.attributes.is_empty()));
+        assert_eq!(reconstruct(&result), source);
     }
-    (items, body_end)
 }
+
@@ -1,81 +1,447 @@
-use regex::Regex;
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use proc_macro2::extra::DelimSpan;
+use crate::ars_file::to_ron;
+use crate::error::WaltError;
 use serde::{Serialize, Deserialize};
+use syn::punctuated::Punctuated;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::ARSDelimiter;
+use crate::syntax_elements::trivia::{self, ARSTrivia, GapStart};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, dedent, indent, parse_items, tokens};
 
-/// Represents a Rust macro in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+/// Represents a `macro_rules!` definition in Animated Rust (.ars) format
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSMacro {
     pub name: String,
-    pub body: String,
-    pub attributes: Vec<String>,
-    pub visibility: Option<String>,
-    pub macro_type: String, // "declarative" or "procedural"
+    pub rules: Vec<ARSMacroRule>,
+    #[serde(default)]
+    pub trailing_semicolon: bool,   // After the last rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,       // Body tokens, only when they could not be split into rules
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default)]
+    pub is_exported: bool,          // Has `#[macro_export]`, which stays in `attributes`
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,          // `closing` holds the lines after the last rule
 }
 
-pub fn extract(source: &str) -> Vec<String> {
+/// One `(matcher) => { transcriber }` arm of a `macro_rules!`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSMacroRule {
+    pub matcher: String,             // Tokens inside the matcher's delimiters, see `group_text`
+    pub matcher_delimiter: ARSDelimiter,
+    pub transcriber: String,         // Tokens inside the transcriber's delimiters
+    pub transcriber_delimiter: ARSDelimiter,
+    pub fragments: Vec<ARSFragment>, // Metavariables the matcher binds, including inside repetitions
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// A metavariable such as `$name:ident`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSFragment {
+    pub name: String,      // Without the `$`
+    pub specifier: String, // `expr`, `ident`, `tt`, ...
+}
+
+/// A macro invoked in item position, e.g. `thread_local! { .. }` or `lazy_static! { .. }`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSMacroInvocation {
+    pub path: String,
+    pub delimiter: ARSDelimiter,
+    pub tokens: String,              // See `group_text`
+    pub semicolon: bool,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// The kind of procedural macro a function defines, from its attribute
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSProcMacro {
+    FunctionLike,                                  // `#[proc_macro]`
+    Derive { name: String, helpers: Vec<String> }, // `#[proc_macro_derive(Name, attributes(helper))]`
+    Attribute,                                     // `#[proc_macro_attribute]`
+}
+
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_macros(source)
         .iter()
-        .map(|m| ron::to_string(m).unwrap())
+        .map(|m| to_ron(m).map_err(WaltError::from))
         .collect()
 }
 
-/// Encode all macros in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_macros(source: &str) -> Vec<ARSMacro> {
-    let mut macros = Vec::new();
-    let macro_regex = Regex::new(r"(?m)((?:#\[.*?\]\s*)*)(pub(?:\(\w+\))?\s*)?macro_rules!\s*(\w+)\s*\{").unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
-    
-    for cap in macro_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap.get(3).unwrap().as_str().to_string();
-
-        let attributes = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-        
-        // Manually find the end of the macro body by balancing braces
-        let body_start = full_match.end();
-        let mut brace_count = 1;
-        let mut body_end = body_start;
-        for (i, c) in source[body_start..].char_indices() {
-            if c == '{' {
-                brace_count += 1;
-            } else if c == '}' {
-                brace_count -= 1;
+/// Converts a `macro_rules!` definition into an ARSMacro.
+///
+/// Returns `None` for item-position macro invocations, see `invocation_from_syn`.
+pub fn from_syn(item: &syn::ItemMacro, source: &SourceMap) -> Option<ARSMacro> {
+    let name = item.ident.as_ref()?;
+    if !item.mac.path.is_ident("macro_rules") {
+        return None;
+    }
+
+    let mut ars_macro = ARSMacro {
+        name: name.to_string(),
+        rules: Vec::new(),
+        trailing_semicolon: false,
+        body: None,
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        is_exported: item.attrs.iter().any(|attr| attr.path().is_ident("macro_export")),
+        trivia: ARSTrivia::default(),
+    };
+    match split_rules(item.mac.tokens.clone()) {
+        Some((spans, trailing_semicolon)) => {
+            let ranges: Vec<_> = spans.iter().map(|(matcher, transcriber)| {
+                source.span_range(matcher.span()).start..source.span_range(transcriber.span()).end
+            }).collect();
+            let delimiters = item.mac.delimiter.span();
+            let (mut rule_trivia, closing) = trivia::for_siblings(
+                source,
+                source.span_range(delimiters.open()).end,
+                GapStart::AfterToken,
+                &ranges,
+                source.span_range(delimiters.close()).start,
+            );
+            ars_macro.rules = spans.iter().zip(rule_trivia.iter_mut()).map(|((matcher, transcriber), rule_trivia)| {
+                ARSMacroRule {
+                    matcher: group_text(&matcher.stream(), &matcher.delim_span(), source),
+                    matcher_delimiter: delimiter(matcher.delimiter()),
+                    transcriber: group_text(&transcriber.stream(), &transcriber.delim_span(), source),
+                    transcriber_delimiter: delimiter(transcriber.delimiter()),
+                    fragments: fragments(matcher.stream()),
+                    trivia: std::mem::take(rule_trivia),
+                }
+            }).collect();
+            ars_macro.trailing_semicolon = trailing_semicolon;
+            ars_macro.trivia.closing = closing;
+        }
+        None => ars_macro.body = Some(group_text(&item.mac.tokens, item.mac.delimiter.span(), source)),
+    }
+    Some(ars_macro)
+}
+
+/// Converts an item-position macro invocation; `macro_rules!` definitions go through `from_syn`
+pub fn invocation_from_syn(item: &syn::ItemMacro, source: &SourceMap) -> ARSMacroInvocation {
+    ARSMacroInvocation {
+        path: tokens::to_source(&item.mac.path),
+        delimiter: match item.mac.delimiter {
+            syn::MacroDelimiter::Paren(_) => ARSDelimiter::Paren,
+            syn::MacroDelimiter::Bracket(_) => ARSDelimiter::Bracket,
+            syn::MacroDelimiter::Brace(_) => ARSDelimiter::Brace,
+        },
+        tokens: group_text(&item.mac.tokens, item.mac.delimiter.span(), source),
+        semicolon: item.semi_token.is_some(),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia::default(),
+    }
+}
+
+/// The source of the tokens between a group's delimiters.
+///
+/// Macro tokens have no structure to print them from, so their text is kept
+/// as written, dedented, with its comments and line breaks. That text starts
+/// and ends with a newline if it starts on a line of its own, which
+/// `delimited` renders on lines of their own too. Tokens without source
+/// positions are printed.
+pub fn group_text(stream: &TokenStream, delimiters: &DelimSpan, source: &SourceMap) -> String {
+    let start = source.span_range(delimiters.open()).end;
+    let end = source.span_range(delimiters.close()).start;
+    if start > end {
+        return tokens::to_source(stream);
+    }
+    let interior = source.slice(start..end);
+    let text = dedent(interior);
+    if interior.trim_start_matches([' ', '\t']).starts_with(['\r', '\n']) {
+        format!("\n{}\n", text.trim())
+    } else {
+        text.trim().to_string()
+    }
+}
+
+/// Detects `#[proc_macro]`, `#[proc_macro_derive]` and `#[proc_macro_attribute]` on a function
+pub fn proc_macro_kind(attrs: &[syn::Attribute]) -> Option<ARSProcMacro> {
+    attrs.iter().find_map(|attr| {
+        let path = attr.path();
+        if path.is_ident("proc_macro") {
+            Some(ARSProcMacro::FunctionLike)
+        } else if path.is_ident("proc_macro_attribute") {
+            Some(ARSProcMacro::Attribute)
+        } else if path.is_ident("proc_macro_derive") {
+            let args = attr.parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated).ok()?;
+            let mut args = args.iter();
+            let name = tokens::to_source(args.next()?.path());
+            let helpers = args.find_map(|arg| match arg {
+                syn::Meta::List(list) if list.path.is_ident("attributes") => list
+                    .parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated)
+                    .ok()
+                    .map(|helpers| helpers.iter().map(tokens::to_source).collect()),
+                _ => None,
+            });
+            Some(ARSProcMacro::Derive { name, helpers: helpers.unwrap_or_default() })
+        } else {
+            None
+        }
+    })
+}
+
+/// Splits a `macro_rules!` body into its matcher and transcriber groups.
+///
+/// Returns `None` if the body is not a sequence of `(..) => {..}` arms
+/// separated by semicolons; otherwise also whether the last arm has one.
+fn split_rules(body: TokenStream) -> Option<(Vec<(proc_macro2::Group, proc_macro2::Group)>, bool)> {
+    let tokens: Vec<TokenTree> = body.into_iter().collect();
+    let mut rules = Vec::new();
+    let mut i = 0;
+    let mut trailing_semicolon = false;
+    while i < tokens.len() {
+        match &tokens[i..] {
+            [TokenTree::Group(matcher), TokenTree::Punct(eq), TokenTree::Punct(gt), TokenTree::Group(transcriber), ..]
+                if eq.as_char() == '=' && eq.spacing() == Spacing::Joint && gt.as_char() == '>' =>
+            {
+                rules.push((matcher.clone(), transcriber.clone()));
+                i += 4;
             }
-            if brace_count == 0 {
-                body_end = body_start + i;
-                break;
+            _ => return None,
+        }
+        trailing_semicolon = false;
+        match tokens.get(i) {
+            Some(TokenTree::Punct(semi)) if semi.as_char() == ';' => {
+                trailing_semicolon = true;
+                i += 1;
             }
+            Some(_) => return None,
+            None => {}
         }
+    }
+    Some((rules, trailing_semicolon))
+}
 
-        if body_end > body_start {
-            let body = source[body_start..body_end].trim().to_string();
-            macros.push(ARSMacro {
-                name,
-                body,
-                attributes,
-                visibility,
-                macro_type: "declarative".to_string(),
-            });
+/// Collects the `$name:specifier` metavariables of a matcher, in order
+fn fragments(matcher: TokenStream) -> Vec<ARSFragment> {
+    let tokens: Vec<TokenTree> = matcher.into_iter().collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i..] {
+            [TokenTree::Punct(dollar), TokenTree::Ident(name), TokenTree::Punct(colon), TokenTree::Ident(specifier), ..]
+                if dollar.as_char() == '$' && colon.as_char() == ':' =>
+            {
+                found.push(ARSFragment { name: name.to_string(), specifier: specifier.to_string() });
+                i += 4;
+            }
+            [TokenTree::Group(group), ..] => {
+                found.extend(fragments(group.stream()));
+                i += 1;
+            }
+            _ => i += 1,
         }
     }
+    found
+}
+
+fn delimiter(delimiter: Delimiter) -> ARSDelimiter {
+    match delimiter {
+        Delimiter::Parenthesis => ARSDelimiter::Paren,
+        Delimiter::Bracket => ARSDelimiter::Bracket,
+        Delimiter::Brace | Delimiter::None => ARSDelimiter::Brace,
+    }
+}
 
-    macros
+/// Renders a macro call, `path!(tokens)`, `path![tokens]` or `path! { tokens }`
+pub fn reconstruct_call(path: &str, delimiter: ARSDelimiter, tokens: &str) -> String {
+    let space = if delimiter == ARSDelimiter::Brace { " " } else { "" };
+    format!("{}!{}{}", path, space, delimited(delimiter, tokens))
+}
+
+/// Encode all macros in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_macros(source: &str) -> Vec<ARSMacro> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Macro(m) => from_syn(m, &source_map),
+            _ => None,
+        })
+        .collect()
 }
 
 pub fn reconstruct(macros: &[ARSMacro]) -> String {
     macros.iter().map(|m| {
-        let mut result = String::new();
-        for attr in &m.attributes {
-            result.push_str(&format!("{}\n", attr));
-        }
-        if let Some(vis) = &m.visibility {
-            result.push_str(&format!("{} ", vis));
+        let mut result = trivia::render_lines(&m.trivia.leading);
+        result.push_str(&attributes::render_docs(&m.docs));
+        result.push_str(&attributes::reconstruct(&m.attributes));
+        let text = match &m.body {
+            Some(body) => format!("macro_rules! {} {}", m.name, delimited(ARSDelimiter::Brace, body)),
+            None if m.rules.is_empty() && m.trivia.closing.is_empty() => format!("macro_rules! {} {{}}", m.name),
+            None => format!("macro_rules! {} {{\n{}\n}}", m.name, indent(&rules_source(m))),
+        };
+        result.push_str(&format!("{}\n", trivia::with_trailing(&text, &m.trivia)));
+        result.push('\n');
+        result
+    }).collect::<Vec<String>>().concat()
+}
+
+/// Renders the rules one per line, each followed by a semicolon and its trailing comment
+fn rules_source(m: &ARSMacro) -> String {
+    let mut lines = String::new();
+    for (i, rule) in m.rules.iter().enumerate() {
+        let semicolon = if i + 1 < m.rules.len() || m.trailing_semicolon { ";" } else { "" };
+        let text = format!(
+            "{} => {}{}",
+            delimited(rule.matcher_delimiter, &rule.matcher),
+            delimited(rule.transcriber_delimiter, &rule.transcriber),
+            semicolon,
+        );
+        lines.push_str(&trivia::render_lines(&rule.trivia.leading));
+        lines.push_str(&trivia::with_trailing(&text, &rule.trivia));
+        lines.push('\n');
+    }
+    lines.push_str(&trivia::render_lines(&m.trivia.closing));
+    lines.trim_end_matches('\n').to_string()
+}
+
+/// Renders tokens between delimiters, on lines of their own if `group_text` kept several
+fn delimited(delimiter: ARSDelimiter, tokens: &str) -> String {
+    let (open, close) = match delimiter {
+        ARSDelimiter::Paren => ("(", ")"),
+        ARSDelimiter::Bracket => ("[", "]"),
+        ARSDelimiter::Brace => ("{", "}"),
+    };
+    if tokens.starts_with('\n') {
+        format!("{}\n{}\n{}", open, indent(tokens.trim_matches('\n')), close)
+    } else if delimiter == ARSDelimiter::Brace && !tokens.is_empty() {
+        format!("{{ {} }}", tokens)
+    } else {
+        format!("{}{}{}", open, tokens, close)
+    }
+}
+
+pub fn reconstruct_invocations(invocations: &[ARSMacroInvocation]) -> String {
+    invocations.iter().map(|m| {
+        let mut result = trivia::render_lines(&m.trivia.leading);
+        result.push_str(&attributes::render_docs(&m.docs));
+        result.push_str(&attributes::reconstruct(&m.attributes));
+        let mut text = reconstruct_call(&m.path, m.delimiter, &m.tokens);
+        if m.semicolon {
+            text.push(';');
         }
-        result.push_str(&format!("macro_rules! {} {{ {} }}\n", m.name, m.body.trim()));
+        result.push_str(&format!("{}\n\n", trivia::with_trailing(&text, &m.trivia)));
         result
-    }).collect::<Vec<String>>().join("\n")
+    }).collect::<Vec<String>>().concat()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ars_file::ARSItem;
+    use crate::{decoder, encoder};
+
+    #[test]
+    fn test_rules_are_split_into_arms() {
+        let source = "
+#[macro_export]
+macro_rules! square {
+    // A single value
+    ($x:expr) => { $x * $x };
+    ($($x:expr),+ $(,)?) => [$(square!($x)),+]
+}
+";
+        let macros = extract_ars_macros(source);
+        let square = &macros[0];
+        assert!(square.is_exported && square.body.is_none());
+        assert_eq!(square.rules.len(), 2);
+        assert_eq!(square.rules[0].fragments, vec![ARSFragment { name: "x".to_string(), specifier: "expr".to_string() }]);
+        assert_eq!(square.rules[1].transcriber_delimiter, ARSDelimiter::Bracket);
+        assert!(!square.trailing_semicolon);
+        assert_eq!(
+            reconstruct(&macros),
+            "#[macro_export]\n\
+             macro_rules! square {\n    \
+             // A single value\n    \
+             ($x:expr) => { $x * $x };\n    \
+             ($($x:expr),+ $(,)?) => [$(square!($x)),+]\n\
+             }\n\n"
+        );
+    }
+
+    #[test]
+    fn test_layout_of_arms_and_invocations_is_kept() {
+        let source = "macro_rules! make {
+    ($name:ident {
+        $($field:ident: $ty:ty),* $(,)?
+    }) => {
+        pub struct $name {
+            $(pub $field: $ty,)*
+        }
+    };
+}
+
+thread_local! {
+    static A: u8 = 1;
+    static B: u8 = 2;
+}
+";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert_eq!(decoder::decode(&ars_file), source);
+    }
+
+    #[test]
+    fn test_invocations_and_proc_macros() {
+        let source = "
+thread_local! { static COUNT: Cell<u32> = Cell::new(0); }
+#[proc_macro_derive(Builder, attributes(builder))]
+pub fn derive(input: TokenStream) -> TokenStream { input }
+";
+        let file: syn::File = syn::parse_str(source).unwrap();
+        let syn::Item::Macro(item) = &file.items[0] else { panic!() };
+        assert!(from_syn(item, &SourceMap::new(source)).is_none());
+        let invocation = invocation_from_syn(item, &SourceMap::new(source));
+        assert_eq!(invocation.path, "thread_local");
+        assert_eq!(reconstruct_invocations(&[invocation]), "thread_local! { static COUNT: Cell<u32> = Cell::new(0); }\n\n");
+
+        let syn::Item::Fn(func) = &file.items[1] else { panic!() };
+        assert_eq!(
+            proc_macro_kind(&func.attrs),
+            Some(ARSProcMacro::Derive { name: "Builder".to_string(), helpers: vec!["builder".to_string()] })
+        );
+    }
+
+    #[test]
+    fn test_comments_in_macro_bodies_are_kept() {
+        let source = "lazy_static! {
+    // The cache
+    static ref CACHE: Mutex<Vec<u8>> = Mutex::new(vec![
+        1, // one
+        2,
+    ]);
+}
+
+macro_rules! twice {
+    ($x:expr /* any */) => {
+        // evaluated twice
+        $x + $x
+    };
+}
+
+fn main() {
+    let (a, /* ignored */ _) = f!(a, b /* why */);
+}
+";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert!(matches!(&ars_file.items[0], ARSItem::MacroInvocation(m) if m.tokens.starts_with("\n// The cache\n")));
+        let decoded = decoder::decode(&ars_file);
+        assert_eq!(decoded, source.replace("(a, /* ignored */ _)", "(a, _)"));
+
+        // What is still dropped is reported
+        assert_eq!(ars_file.unsupported.len(), 1);
+        assert_eq!((ars_file.unsupported[0].line, ars_file.unsupported[0].column), (17, 12));
+        assert_eq!(ars_file.unsupported[0].detail, "dropped: /* ignored */");
+    }
+}
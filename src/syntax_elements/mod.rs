@@ -1,7 +1,9 @@
 pub mod attributes;
 pub mod constants;
 pub mod expressions;
+pub mod foreign_mods;
 pub mod functions;
+pub mod generics;
 pub mod impl_blocks;
 pub mod macros;
 pub mod modules;
@@ -10,8 +12,13 @@ pub mod structs;
 pub mod enums;
 pub mod traits;
 pub mod statics;
+pub mod tokens;
+pub mod trivia;
 pub mod type_aliases;
+pub mod types;
+pub mod unions;
 pub mod use_statements;
+pub mod verbatim;
 // Generic extract function stub
 pub fn extract(source: &str) -> Vec<String> {
     source
@@ -24,3 +31,182 @@ pub fn extract(source: &str) -> Vec<String> {
 pub fn reconstruct(lines: &[String]) -> String {
     lines.join("\n")
 }
+
+/// Parses a source file into its top-level syn items, or nothing if it does not parse.
+pub fn parse_items(source: &str) -> Vec<syn::Item> {
+    syn::parse_file(source).map(|file| file.items).unwrap_or_default()
+}
+
+/// Converts a syn visibility into the .ars form: `None` for inherited, otherwise `pub`, `pub(crate)`, ...
+pub fn visibility(vis: &syn::Visibility) -> Option<String> {
+    match vis {
+        syn::Visibility::Inherited => None,
+        _ => Some(tokens::to_source(vis)),
+    }
+}
+
+/// Renders an optional visibility as a prefix ready to be followed by a keyword.
+pub fn vis_prefix(visibility: &Option<String>) -> String {
+    match visibility {
+        Some(vis) if !vis.is_empty() => format!("{} ", vis),
+        _ => String::new(),
+    }
+}
+
+/// Indents every non-empty line of `text` by four spaces.
+///
+/// Lines that continue a multi-line string literal or block comment are left
+/// alone, since indenting them would change the literal's value.
+pub fn indent(text: &str) -> String {
+    let mut output = String::new();
+    let mut scanner = LiteralScanner::default();
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            output.push('\n');
+        }
+        if !line.is_empty() && !scanner.inside() {
+            output.push_str("    ");
+        }
+        output.push_str(line);
+        scanner.feed(line);
+        scanner.feed("\n");
+    }
+    output
+}
+
+/// Removes the indentation shared by the lines after the first, undoing `indent`.
+///
+/// Lines that start inside a string literal or block comment are left as they are.
+pub fn dedent(text: &str) -> String {
+    let mut scanner = LiteralScanner::default();
+    let lines: Vec<(&str, bool)> = text.split('\n').enumerate().map(|(i, line)| {
+        let code = i > 0 && !scanner.inside();
+        scanner.feed(line);
+        scanner.feed("\n");
+        (line, code)
+    }).collect();
+
+    let margin = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let width = lines.iter()
+        .filter(|(line, code)| *code && !line.trim().is_empty())
+        .map(|(line, _)| margin(line))
+        .min()
+        .unwrap_or(0);
+    lines.iter().map(|(line, code)| match code {
+        true if line.trim().is_empty() => "",
+        true => &line[width.min(margin(line))..],
+        false => line,
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// Tracks whether a position in Rust source lies inside a string literal or block comment.
+#[derive(Default)]
+struct LiteralScanner {
+    state: LiteralState,
+    prev: Option<char>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum LiteralState {
+    #[default]
+    Code,
+    Str { escaped: bool },
+    RawStr { hashes: usize },
+    BlockComment { depth: usize },
+    LineComment,
+}
+
+impl LiteralScanner {
+    fn inside(&self) -> bool {
+        matches!(
+            self.state,
+            LiteralState::Str { .. } | LiteralState::RawStr { .. } | LiteralState::BlockComment { .. }
+        )
+    }
+
+    fn feed(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            match self.state {
+                LiteralState::Code => match c {
+                    '"' => self.state = LiteralState::Str { escaped: false },
+                    '/' if next == Some('/') => self.state = LiteralState::LineComment,
+                    '/' if next == Some('*') => {
+                        self.state = LiteralState::BlockComment { depth: 1 };
+                        i += 1;
+                    }
+                    'r' if !self.prev.is_some_and(|p| p.is_alphanumeric() || p == '_') || self.prev == Some('b') => {
+                        let hashes = chars[i + 1..].iter().take_while(|&&h| h == '#').count();
+                        if chars.get(i + 1 + hashes) == Some(&'"') {
+                            self.state = LiteralState::RawStr { hashes };
+                            i += 1 + hashes;
+                        }
+                    }
+                    '\'' => {
+                        // Skip char literals so `'"'` does not open a string; lifetimes fall through.
+                        if next == Some('\\') {
+                            if let Some(close) = chars[i + 2..].iter().position(|&q| q == '\'') {
+                                i += close + 2;
+                            }
+                        } else if chars.get(i + 2) == Some(&'\'') {
+                            i += 2;
+                        }
+                    }
+                    _ => {}
+                },
+                LiteralState::Str { escaped } => {
+                    if escaped {
+                        self.state = LiteralState::Str { escaped: false };
+                    } else if c == '\\' {
+                        self.state = LiteralState::Str { escaped: true };
+                    } else if c == '"' {
+                        self.state = LiteralState::Code;
+                    }
+                }
+                LiteralState::RawStr { hashes } => {
+                    if c == '"' && chars[i + 1..].iter().take_while(|&&h| h == '#').count() >= hashes {
+                        self.state = LiteralState::Code;
+                        i += hashes;
+                    }
+                }
+                LiteralState::BlockComment { depth } => {
+                    if c == '/' && next == Some('*') {
+                        self.state = LiteralState::BlockComment { depth: depth + 1 };
+                        i += 1;
+                    } else if c == '*' && next == Some('/') {
+                        self.state = if depth == 1 {
+                            LiteralState::Code
+                        } else {
+                            LiteralState::BlockComment { depth: depth - 1 }
+                        };
+                        i += 1;
+                    }
+                }
+                LiteralState::LineComment => {
+                    if c == '\n' {
+                        self.state = LiteralState::Code;
+                    }
+                }
+            }
+            self.prev = chars.get(i).copied();
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indent_leaves_multiline_literals_alone() {
+        let text = "let s = r#\"\nfirst\n\"#;\nlet t = \"a\n  b\";\ncall();";
+        assert_eq!(
+            indent(text),
+            "    let s = r#\"\nfirst\n\"#;\n    let t = \"a\n  b\";\n    call();"
+        );
+    }
+}
@@ -1,90 +1,151 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::to_ron;
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::trivia::{self, ARSTrivia, ARSTriviaPiece};
+use crate::ars_file::{ARSFile, ARSItem};
+use crate::decoder;
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, vis_prefix, visibility};
 
 // Generic extract function stub
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_modules(source)
         .iter()
-        .map(|m| ron::to_string(m).unwrap())
+        .map(|m| to_ron(m).map_err(WaltError::from))
         .collect()
 }
 
 pub fn reconstruct(modules: &[ARSModule]) -> String {
     modules.iter().map(|m| {
-        let mut result = String::new();
-        for attr in &m.attributes {
-            result.push_str(&format!("{}\n", attr));
-        }
-        if let Some(vis) = &m.visibility {
-            result.push_str(&format!("{} ", vis));
-        }
-        result.push_str(&format!("mod {}", m.name));
+        let mut result = trivia::render_lines(&m.trivia.leading);
+        result.push_str(&attributes::render_docs(&m.docs));
+        result.push_str(&attributes::reconstruct(&m.attributes));
+        let mut text = format!("{}mod {}", vis_prefix(&m.visibility), m.name);
         if m.inline {
-            if let Some(body) = &m.body {
-                result.push_str(&format!(" {{\n{}\n}}\n", body));
+            let mut body = trivia::render_lines(&m.inner_docs_trivia);
+            body.push_str(&attributes::render_inner_docs(&m.inner_docs));
+            body.push_str(&attributes::reconstruct(&m.inner_attributes));
+            body.push_str(&decoder::reconstruct_items(&m.items));
+            body.push_str(&trivia::render_lines(&m.trivia.closing));
+            if body.trim().is_empty() {
+                text.push_str(" {}");
             } else {
-                result.push_str(" {}\n");
+                text.push_str(&format!(" {{\n{}\n}}", indent(body.trim_end())));
             }
         } else {
-            result.push_str(";\n");
+            text.push(';');
         }
+        result.push_str(&trivia::with_trailing(&text, &m.trivia));
+        result.push_str("\n\n");
         result
-    }).collect::<Vec<String>>().join("\n")
+    }).collect::<Vec<String>>().concat()
 }
 
 /// Represents a Rust module in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSModule {
     pub name: String,
     pub visibility: Option<String>,
-    pub attributes: Vec<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
     pub inline: bool,                // true if inline module `{ ... }`, false if file module `mod name;`
-    pub body: Option<String>,        // Only for inline modules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_docs: Vec<String>,     // `//!` lines at the top of an inline module
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_docs_trivia: Vec<ARSTriviaPiece>, // Comments and blank lines before the inner docs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_attributes: Vec<ARSAttribute>, // `#![...]` at the top of an inline module
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<ARSItem>,         // Only for inline modules, in source order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,        // Value of a `#[path = "..."]` attribute, which stays in `attributes`
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,           // `closing` holds the lines after the last nested item
+}
+
+/// Converts a syn module into an ARSModule.
+///
+/// The contents of an inline module are encoded by the caller, which walks
+/// them like a file; `nested` is empty for `mod name;`.
+pub fn from_syn(item: &syn::ItemMod, nested: ARSFile, source: &SourceMap) -> ARSModule {
+    ARSModule {
+        name: item.ident.to_string(),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        inline: item.content.is_some(),
+        inner_docs: nested.docs,
+        inner_docs_trivia: nested.docs_trivia,
+        inner_attributes: nested.attributes,
+        items: nested.items,
+        path: item.attrs.iter().find_map(path_attribute),
+        trivia: ARSTrivia { closing: nested.closing_trivia, ..ARSTrivia::default() },
+    }
+}
+
+/// The file named by a `#[path = "..."]` attribute
+fn path_attribute(attr: &syn::Attribute) -> Option<String> {
+    match &attr.meta {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            path,
+            value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(file), .. }),
+            ..
+        }) if path.is_ident("path") => Some(file.value()),
+        _ => None,
+    }
 }
 
 /// Encode all modules in a Rust source file to Animated Rust (.ars)
 pub fn extract_ars_modules(source: &str) -> Vec<ARSModule> {
-    let mut modules = Vec::new();
-    let mod_regex = Regex::new(r"(?s)((?:#\[.*?\]\s*)*)(pub(?:\(\w+\))?\s*)?mod\s+(\w+)\s*(\{)?").unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
+    match crate::encoder::encode_source(source) {
+        Ok(ars_file) => ars_file
+            .items
+            .into_iter()
+            .filter_map(|item| match item {
+                ARSItem::Module(m) => Some(m),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
 
-    for cap in mod_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap.get(3).unwrap().as_str().to_string();
-        let inline = cap.get(4).is_some();
-        
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut body = None;
-        if inline {
-            let body_start = full_match.end();
-            let mut brace_count = 1;
-            let mut body_end = body_start;
-            for (i, c) in source[body_start..].char_indices() {
-                if c == '{' { brace_count += 1; }
-                if c == '}' { brace_count -= 1; }
-                if brace_count == 0 {
-                    body_end = body_start + i;
-                    break;
-                }
-            }
-            if body_end > body_start {
-                body = Some(source[body_start..body_end].trim().to_string());
-            }
-        }
+    #[test]
+    fn test_inline_modules_nest_their_items() {
+        let source = "pub mod network {
+    pub struct Request;
 
-        modules.push(ARSModule {
-            name,
-            visibility,
-            attributes,
-            inline,
-            body,
-        });
+    mod inner {
+        fn send() {}
+        // pending
+    }
+}
+#[path = \"sys/unix.rs\"]
+mod platform;
+";
+        let modules = extract_ars_modules(source);
+        assert_eq!(modules.len(), 2);
+        let network = &modules[0];
+        assert!(matches!(&network.items[0], ARSItem::Struct(s) if s.name == "Request"));
+        assert!(matches!(&network.items[1], ARSItem::Module(m) if m.items.len() == 1 && !m.trivia.closing.is_empty()));
+        assert_eq!(modules[1].path.as_deref(), Some("sys/unix.rs"));
+        assert_eq!(
+            reconstruct(&modules),
+            "pub mod network {\n    \
+             pub struct Request;\n\n    \
+             mod inner {\n        \
+             fn send() {}\n        \
+             // pending\n    \
+             }\n\
+             }\n\n\
+             #[path = \"sys/unix.rs\"]\n\
+             mod platform;\n\n"
+        );
     }
-
-    modules
 }
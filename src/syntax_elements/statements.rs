@@ -1,30 +1,111 @@
 use serde::{Serialize, Deserialize};
-use syn::{File, Item, Stmt};
-use quote::ToTokens;
-
-/// Represents a Rust statement in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ARSStatement {
-    pub stmt_type: String,
-    pub content: String,
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use syn::{Block, File, Item, Pat, Stmt};
+use crate::ars_file::ARSItem;
+use crate::decoder;
+use crate::encoder;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::attributes::{self, ARSAttribute};
+use crate::syntax_elements::expressions::{self, ARSBlock, ARSExpression, ARSExpressionType};
+use crate::syntax_elements::tokens::to_source;
+use crate::syntax_elements::trivia::{self, ARSTrivia, ARSTriviaPiece};
+use crate::syntax_elements::types::{self, ARSType};
+
+/// Represents a Rust statement in Animated Rust (.ars) format.
+///
+/// Macros in statement position, like `println!(..);`, are `Expr`s holding
+/// an `ARSExpressionType::Macro`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSStatement {
+    Local(ARSLocal),
+    Item(Box<ARSItem>), // Keeps its trivia in the item itself
+    Expr {
+        expr: ARSExpression,
+        semicolon: bool,
+        #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+        trivia: ARSTrivia,
+    },
+    /// Source text that could not be parsed, or an item the item model cannot hold in full
+    Verbatim {
+        content: String,
+        #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+        trivia: ARSTrivia,
+    },
+}
+
+/// A `let` binding
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSLocal {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<ARSAttribute>,
+    pub pattern: String,
+    pub ty: Option<ARSType>,             // `let x: u8`
+    pub init: Option<ARSExpression>,
+    pub else_block: Option<ARSBlock>,    // `let Some(x) = y else { .. };`
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
 impl ARSStatement {
-    /// Encode the statement as a single .ars line
-    pub fn encode(&self) -> String {
-        format!("{},[{}]", self.stmt_type, self.content.replace('\n', "\\n"))
+    /// The comments and blank lines attached to the statement
+    pub fn trivia(&self) -> &ARSTrivia {
+        match self {
+            ARSStatement::Local(local) => &local.trivia,
+            ARSStatement::Item(item) => item.trivia(),
+            ARSStatement::Expr { trivia, .. } | ARSStatement::Verbatim { trivia, .. } => trivia,
+        }
+    }
+
+    pub fn trivia_mut(&mut self) -> &mut ARSTrivia {
+        match self {
+            ARSStatement::Local(local) => &mut local.trivia,
+            ARSStatement::Item(item) => item.trivia_mut(),
+            ARSStatement::Expr { trivia, .. } | ARSStatement::Verbatim { trivia, .. } => trivia,
+        }
     }
 }
 
-pub fn extract(input: &str) -> Vec<String> {
+pub fn extract(input: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_statements(input)
         .iter()
-        .map(|s| ron::to_string(s).unwrap())
+        .map(|s| to_ron(s).map_err(WaltError::from))
         .collect()
 }
 
 pub fn reconstruct(statements: &[ARSStatement]) -> String {
-    statements.iter().map(|s| s.content.clone()).collect::<Vec<String>>().join("\n")
+    statements.iter().map(|s| {
+        let text = match s {
+            // Items render their own trivia
+            ARSStatement::Item(item) => return decoder::reconstruct_item(item).trim_end().to_string(),
+            ARSStatement::Local(local) => reconstruct_local(local),
+            ARSStatement::Expr { expr, semicolon, .. } => {
+                let mut text = expressions::reconstruct_expr(expr);
+                if *semicolon {
+                    text.push(';');
+                }
+                text
+            }
+            ARSStatement::Verbatim { content, .. } => content.clone(),
+        };
+        format!("{}{}", trivia::render_lines(&s.trivia().leading), trivia::with_trailing(&text, s.trivia()))
+    }).collect::<Vec<String>>().join("\n")
+}
+
+fn reconstruct_local(local: &ARSLocal) -> String {
+    let mut output: String = local.attributes.iter().map(|attr| format!("{}\n", attr)).collect();
+    output.push_str(&format!("let {}", local.pattern));
+    if let Some(ty) = &local.ty {
+        output.push_str(&format!(": {}", ty));
+    }
+    if let Some(init) = &local.init {
+        output.push_str(&format!(" = {}", expressions::reconstruct_expr(init)));
+    }
+    if let Some(else_block) = &local.else_block {
+        output.push_str(&format!(" else {}", expressions::reconstruct_block(else_block, &[])));
+    }
+    output.push(';');
+    output
 }
 
 pub fn extract_ars_statements(input: &str) -> Vec<ARSStatement> {
@@ -35,11 +116,11 @@ pub fn extract_ars_statements(input: &str) -> Vec<ARSStatement> {
     let ast: File = match syn::parse_file(&wrapped_code) {
         Ok(tree) => tree,
         Err(_) => {
-            // If parsing fails, fall back to the generic block
+            // If parsing fails, fall back to the verbatim text
             if !input.trim().is_empty() {
-                return vec![ARSStatement {
-                    stmt_type: "Generic".to_string(),
+                return vec![ARSStatement::Verbatim {
                     content: input.to_string(),
+                    trivia: ARSTrivia::default(),
                 }];
             } else {
                 return Vec::new();
@@ -48,26 +129,91 @@ pub fn extract_ars_statements(input: &str) -> Vec<ARSStatement> {
     };
 
     // Extract statements from the dummy function's block
-    let mut statements = Vec::new();
-    if let Some(Item::Fn(func)) = ast.items.first() {
-        for stmt in &func.block.stmts {
-            let content = stmt.to_token_stream().to_string();
-            let stmt_type = match stmt {
-                Stmt::Local(_) => "Local",
-                Stmt::Item(_) => "Item",
-                Stmt::Expr(_, _) => "Expr",
-                Stmt::Macro(_) => "Macro",
-            }
-            .to_string();
+    match ast.items.first() {
+        Some(Item::Fn(func)) => from_block(&func.block, &SourceMap::new(&wrapped_code), &mut Vec::new()).0,
+        _ => Vec::new(),
+    }
+}
+
+/// Converts every statement of a syn block into ARSStatements, with their trivia.
+///
+/// Also returns the trivia between the last statement and the closing brace.
+pub fn from_block(
+    block: &Block,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> (Vec<ARSStatement>, Vec<ARSTriviaPiece>) {
+    let (_, statements, closing) = from_body(&[], block, source, unsupported);
+    (statements, closing)
+}
+
+/// Converts the block of a function, whose inner attributes syn hands to the
+/// function; they come back first, with their trivia.
+///
+/// Items declared in the block that the model has no structure for are
+/// recorded in `unsupported`, as they are at the top level.
+pub fn from_body(
+    attrs: &[syn::Attribute],
+    block: &Block,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> (Vec<ARSAttribute>, Vec<ARSStatement>, Vec<ARSTriviaPiece>) {
+    let ranges = block.stmts.iter().map(|stmt| source.range(stmt)).collect();
+    let (inner_attributes, mut trivia, closing) =
+        attributes::inner_with_members(attrs, ranges, &block.brace_token, source);
+
+    let statements = block.stmts.iter().zip(trivia.iter_mut()).map(|(stmt, trivia)| {
+        let mut statement = from_syn(stmt, source, unsupported);
+        let target = statement.trivia_mut();
+        target.leading = std::mem::take(&mut trivia.leading);
+        target.trailing = trivia.trailing.take();
+        statement
+    }).collect();
+    (inner_attributes, statements, closing)
+}
 
-            statements.push(ARSStatement {
-                stmt_type,
-                content,
-            });
+/// Converts a syn statement into an ARSStatement
+pub fn from_syn(stmt: &Stmt, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSStatement {
+    match stmt {
+        Stmt::Local(local) => {
+            let (pattern, ty) = match &local.pat {
+                Pat::Type(typed) if typed.attrs.is_empty() => {
+                    (to_source(&typed.pat), Some(types::from_syn(&typed.ty, source, unsupported)))
+                }
+                pat => (to_source(pat), None),
+            };
+            ARSStatement::Local(ARSLocal {
+                attributes: local.attrs.iter().map(attributes::from_syn).collect(),
+                pattern,
+                ty,
+                init: local.init.as_ref().map(|init| expressions::from_syn(&init.expr, source, unsupported)),
+                else_block: local.init.as_ref().and_then(|init| init.diverge.as_ref()).map(|(_, diverge)| {
+                    match expressions::from_syn(diverge, source, unsupported).expression_type {
+                        ARSExpressionType::Block { block, .. } => block,
+                        // syn only ever parses a block here
+                        _ => ARSBlock::default(),
+                    }
+                }),
+                trivia: ARSTrivia::default(),
+            })
         }
+        // Items the model has no structure for come back as verbatim items
+        Stmt::Item(item) => ARSStatement::Item(Box::new(encoder::encode_item(item, source, unsupported))),
+        Stmt::Expr(expr, semi) => ARSStatement::Expr {
+            expr: expressions::from_syn(expr, source, unsupported),
+            semicolon: semi.is_some(),
+            trivia: ARSTrivia::default(),
+        },
+        Stmt::Macro(mac) => ARSStatement::Expr {
+            expr: expressions::from_syn(
+                &syn::Expr::Macro(syn::ExprMacro { attrs: mac.attrs.clone(), mac: mac.mac.clone() }),
+                source,
+                unsupported,
+            ),
+            semicolon: mac.semi_token.is_some(),
+            trivia: ARSTrivia::default(),
+        },
     }
-
-    statements
 }
 
 #[cfg(test)]
@@ -79,8 +225,8 @@ mod tests {
         let source = "let x = 5;";
         let result = extract_ars_statements(source);
         assert_eq!(result.len(), 1);
-        assert_eq!(result[0].content, "let x = 5 ;");
-        assert_eq!(result[0].stmt_type, "Local");
+        assert!(matches!(&result[0], ARSStatement::Local(local) if local.pattern == "x" && local.ty.is_none()));
+        assert_eq!(reconstruct(&result), "let x = 5;");
     }
 
     #[test]
@@ -92,12 +238,80 @@ mod tests {
         "#;
         let result = extract_ars_statements(source);
         assert_eq!(result.len(), 3);
-        assert_eq!(result[0].stmt_type, "Local");
-        assert_eq!(result[0].content, "let a = 1 ;");
-        assert_eq!(result[1].stmt_type, "Macro");
-        assert_eq!(result[1].content, "println ! (\"Hello\") ;");
-        assert_eq!(result[2].stmt_type, "Local");
-        assert_eq!(result[2].content, "let b = a + 2 ;");
+        assert!(matches!(&result[0], ARSStatement::Local(local) if local.pattern == "a"));
+        assert_eq!(reconstruct(&result[..1]), "let a = 1;");
+        assert!(matches!(
+            &result[1],
+            ARSStatement::Expr { expr, semicolon: true, .. } if matches!(&expr.expression_type, ARSExpressionType::Macro { path, .. } if path == "println")
+        ));
+        assert_eq!(reconstruct(&result[1..2]), "println!(\"Hello\");");
+        assert!(matches!(&result[2], ARSStatement::Local(local) if local.pattern == "b"));
+        assert_eq!(reconstruct(&result[2..]), "let b = a + 2;");
+    }
+
+    #[test]
+    fn test_typed_let_else_and_tail_statements() {
+        let source = r#"
+            let a: u8 = 1;
+            println!("Hello");
+            let Some(b) = a.checked_add(2) else { return };
+            a + b
+        "#;
+        let result = extract_ars_statements(source);
+        assert_eq!(result.len(), 4);
+        assert!(matches!(&result[0], ARSStatement::Local(local) if local.ty.as_ref().is_some_and(|ty| ty.to_string() == "u8")));
+        assert!(matches!(&result[1], ARSStatement::Expr { semicolon: true, .. }));
+        assert!(matches!(&result[2], ARSStatement::Local(local) if local.else_block.is_some()));
+        assert!(matches!(&result[3], ARSStatement::Expr { semicolon: false, .. }));
+        assert_eq!(
+            reconstruct(&result),
+            "let a: u8 = 1;\nprintln!(\"Hello\");\nlet Some(b) = a.checked_add(2) else {\n    return\n};\na + b"
+        );
+    }
+
+    #[test]
+    fn test_nested_items_use_the_item_model() {
+        let source = "
+            struct Point { x: i32 }
+            fn helper() -> i32 { 1 }
+            union Bits { int: u32, float: f32 }
+            trait Shape = Clone + Debug;
+        ";
+        let result = extract_ars_statements(source);
+        assert!(matches!(&result[0], ARSStatement::Item(item) if matches!(**item, ARSItem::Struct(_))));
+        assert!(matches!(&result[1], ARSStatement::Item(item) if matches!(**item, ARSItem::Function(_))));
+        assert!(matches!(&result[2], ARSStatement::Item(item) if matches!(**item, ARSItem::Union(_))));
+        // Not structured yet, so kept as verbatim source
+        assert!(matches!(&result[3], ARSStatement::Item(item) if matches!(&**item, ARSItem::Verbatim(v) if v.kind == "trait alias")));
+    }
+
+    #[test]
+    fn test_nested_unsupported_items_are_reported() {
+        // What `encode --strict` checks, so a nested trait alias fails it like a top-level one
+        let source = "fn f() {\n    let shape = || {\n        trait Shape = Clone;\n    };\n}\n";
+        let ars_file = encoder::encode_source(source).unwrap();
+        assert_eq!(ars_file.unsupported.len(), 1);
+        assert_eq!(ars_file.unsupported[0].kind, "trait alias");
+        assert_eq!((ars_file.unsupported[0].line, ars_file.unsupported[0].column), (3, 8));
+    }
+
+    #[test]
+    fn test_comments_attach_to_statements() {
+        let source = "
+            // set up
+            let a = 1; // one
+
+            if a > 0 {
+                // nested
+                call(a);
+            }
+            // done
+        ";
+        let result = extract_ars_statements(source);
+        assert_eq!(result[0].trivia().leading, vec![ARSTriviaPiece::Comment("// set up".to_string())]);
+        assert_eq!(result[0].trivia().trailing.as_deref(), Some("// one"));
+        assert_eq!(result[1].trivia().leading, vec![ARSTriviaPiece::BlankLine]);
+        assert_eq!(reconstruct(&result), "// set up\nlet a = 1; // one\n\nif a > 0 {\n    // nested\n    call(a);\n}");
     }
 
     #[test]
@@ -112,7 +326,6 @@ mod tests {
         let source = "let x = ;"; // Invalid syntax
         let result = extract_ars_statements(source);
         assert_eq!(result.len(), 1);
-        assert_eq!(result[0].stmt_type, "Generic");
-        assert_eq!(result[0].content, source);
+        assert_eq!(result[0], ARSStatement::Verbatim { content: source.to_string(), trivia: ARSTrivia::default() });
     }
 }
@@ -1,63 +1,96 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::{self, ARSExpression};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType};
 
-/// Represents a Rust static variable in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+/// Represents a Rust static variable in Animated Rust (.ars) format.
+///
+/// Statics declared in `extern` blocks have no value and are an
+/// `ARSForeignItem::Static` instead.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSStatic {
     pub name: String,
-    pub ty: String,
-    pub value: String,
+    pub ty: ARSType,
+    pub value: ARSExpression,
     pub mutable: bool,
     pub visibility: Option<String>,
-    pub attributes: Vec<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
 /// Generic extract function
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_statics(source)
         .iter()
-        .map(|s| ron::to_string(s).unwrap())
+        .map(|s| to_ron(s).map_err(WaltError::from))
         .collect()
 }
 
+/// Converts a syn static item into an ARSStatic
+pub fn from_syn(item: &syn::ItemStatic, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSStatic {
+    ARSStatic {
+        name: item.ident.to_string(),
+        ty: types::from_syn(&item.ty, source, unsupported),
+        value: expressions::from_syn(&item.expr, source, unsupported),
+        mutable: matches!(item.mutability, syn::StaticMutability::Mut(_)),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia::default(),
+    }
+}
+
 /// Encode all statics in a Rust source file to Animated Rust (.ars)
 pub fn extract_ars_statics(source: &str) -> Vec<ARSStatic> {
-    let static_regex = Regex::new(r"(?m)((?:#\[.*?\]\s*)*)(pub(?:\(crate\))?\s+)?static\s+(mut\s+)?(\w+)\s*:\s*([^=]+)\s*=\s*(.+?);").unwrap();
-    let attr_regex = Regex::new(r"#\[(.*?)\]").unwrap();
-
-    static_regex.captures_iter(source).map(|cap| {
-        let attributes_str = &cap[1];
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let mutable = cap.get(3).is_some();
-        let name = cap[4].to_string();
-        let ty = cap[5].trim().to_string();
-        let value = cap[6].trim().to_string();
-        
-        let attributes = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-
-        ARSStatic {
-            name,
-            ty,
-            value,
-            mutable,
-            visibility,
-            attributes,
-        }
-    }).collect()
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Static(s) => Some(from_syn(s, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
 }
 
 /// Reconstructs Rust code from a slice of ARSStatic structs
 pub fn reconstruct(ars_statics: &[ARSStatic]) -> String {
     let mut output = String::new();
     for s in ars_statics {
-        for attr in &s.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
-        let vis = s.visibility.as_deref().unwrap_or("");
+        output.push_str(&trivia::render_lines(&s.trivia.leading));
+        output.push_str(&attributes::render_docs(&s.docs));
+        output.push_str(&attributes::reconstruct(&s.attributes));
+        let vis = vis_prefix(&s.visibility);
         let mut_str = if s.mutable { "mut " } else { "" };
-        output.push_str(&format!("{}static {}{}: {} = {};\n\n", vis, mut_str, s.name, s.ty, s.value));
+        let text = format!("{}static {}{}: {} = {};", vis, mut_str, s.name, s.ty, expressions::reconstruct_expr(&s.value));
+        output.push_str(&format!("{}\n\n", trivia::with_trailing(&text, &s.trivia)));
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_elements::expressions::ARSExpressionType;
+
+    #[test]
+    fn test_values_are_expressions_and_extern_statics_are_separate() {
+        let source = "pub(crate) static mut COUNTS: [u32; 2] = [0; 2];
+extern \"C\" {
+    static environ: *const *const c_char;
+}
+";
+        let result = extract_ars_statics(source);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].mutable);
+        assert!(matches!(result[0].value.expression_type, ARSExpressionType::Repeat { .. }));
+        assert_eq!(reconstruct(&result), "pub(crate) static mut COUNTS: [u32; 2] = [0; 2];\n\n");
+    }
+}
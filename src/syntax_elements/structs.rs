@@ -1,110 +1,222 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::trivia::{self, ARSTrivia, ARSTriviaPiece, GapStart};
+use crate::syntax_elements::types::{self, ARSType};
 
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_structs(source)
         .iter()
-        .map(|s| ron::to_string(s).unwrap())
+        .map(|s| to_ron(s).map_err(WaltError::from))
         .collect()
 }
 
 pub fn reconstruct(ars_structs: &[ARSStruct]) -> String {
     let mut output = String::new();
     for s in ars_structs {
-        for attr in &s.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
+        output.push_str(&trivia::render_lines(&s.trivia.leading));
+        output.push_str(&attributes::render_docs(&s.docs));
+        output.push_str(&attributes::reconstruct(&s.attributes));
 
-        let vis = s.visibility.as_deref().unwrap_or("");
-        let generics = s.generics.as_deref().unwrap_or("");
-        
-        output.push_str(&format!("{}struct {}{}", vis, s.name, generics));
+        let vis = vis_prefix(&s.visibility);
+        let generics = generics::reconstruct_params(&s.generics);
+        let where_clause = generics::reconstruct_where(&s.generics);
 
+        let mut text = format!("{}struct {}{}", vis, s.name, generics);
         if s.is_unit {
-            output.push_str(";\n\n");
+            text.push_str(&format!("{};", where_clause));
         } else if s.is_tuple {
-            output.push_str(&format!("({});\n\n", s.fields.join(", ")));
+            let fields = reconstruct_fields(&s.fields, false, s.trailing_comma, &s.trivia.closing);
+            text.push_str(&format!("{}{};", fields, where_clause));
         } else {
-            output.push_str(" {\n");
-            for field in &s.fields {
-                output.push_str(&format!("    {},\n", field));
-            }
-            output.push_str("}\n\n");
+            text.push_str(&where_clause);
+            text.push_str(&reconstruct_fields(&s.fields, true, s.trailing_comma, &s.trivia.closing));
         }
+        output.push_str(&trivia::with_trailing(&text, &s.trivia));
+        output.push_str("\n\n");
     }
     output
 }
 
 /// Represents a struct in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSStruct {
     pub name: String,
-    pub fields: Vec<String>,        // field_name:type
+    pub fields: Vec<ARSField>,
     pub is_tuple: bool,             // tuple struct?
     pub is_unit: bool,              // unit struct?
+    #[serde(default)]
+    pub trailing_comma: bool,       // After the last field
     pub visibility: Option<String>,
-    pub generics: Option<String>,   // <T, U>
-    pub attributes: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,          // `closing` holds the lines before the end of the field list
 }
 
-/// Encode all structs in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_structs(source: &str) -> Vec<ARSStruct> {
-    let mut structs = Vec::new();
-    let struct_regex = Regex::new(
-        r"(?s)((?:#\[.*?\]\s*)*)(pub(?:\(\w+\))?\s*)?struct\s+(\w+)\s*(<[^>]*>)?\s*([(;\{])"
-    ).unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
-
-    for cap in struct_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap.get(3).unwrap().as_str().to_string();
-        let generics = cap.get(4).map(|m| m.as_str().to_string());
-        let opener = cap.get(5).unwrap().as_str();
-
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-
-        let is_unit = opener == ";";
-        let is_tuple = opener == "(";
-
-        let mut fields = Vec::new();
-        if !is_unit {
-            let body_start = full_match.end();
-            let closer = if is_tuple { ')' } else { '}' };
-            let mut brace_count = 1;
-            let mut body_end = body_start;
-
-            for (i, c) in source[body_start..].char_indices() {
-                if c == '(' || c == '{' { brace_count += 1; }
-                if c == ')' || c == '}' { brace_count -= 1; }
-                if brace_count == 0 && c == closer {
-                    body_end = body_start + i;
-                    break;
-                }
-            }
-            
-            if body_end > body_start {
-                let body = &source[body_start..body_end];
-                fields = body.split(',')
-                             .map(|s| s.trim().to_string())
-                             .filter(|s| !s.is_empty())
-                             .collect();
-            }
-        }
-        
-        structs.push(ARSStruct {
-            name,
-            fields,
-            is_tuple,
-            is_unit,
-            visibility,
-            generics,
+/// A field of a struct or enum variant
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSField {
+    pub name: Option<String>,       // None for tuple fields
+    pub ty: ARSType,
+    pub visibility: Option<String>, // Including `pub(in path)`
+    pub attributes: Vec<ARSAttribute>,
+    pub docs: Vec<String>,          // Doc comment lines, without the `///`
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// Converts the fields of a struct or variant, with their trivia.
+///
+/// Also returns the trivia between the last field and the closing delimiter.
+pub fn fields_from_syn(
+    fields: &syn::Fields,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> (Vec<ARSField>, Vec<ARSTriviaPiece>) {
+    let delimiters = match fields {
+        syn::Fields::Named(named) => named.brace_token.span,
+        syn::Fields::Unnamed(unnamed) => unnamed.paren_token.span,
+        syn::Fields::Unit => return (Vec::new(), Vec::new()),
+    };
+    let ranges: Vec<_> = fields.iter().map(|field| source.range(field)).collect();
+    let (mut field_trivia, closing) = trivia::for_siblings(
+        source,
+        source.span_range(delimiters.open()).end,
+        GapStart::AfterToken,
+        &ranges,
+        source.span_range(delimiters.close()).start,
+    );
+
+    let fields = fields.iter().zip(field_trivia.iter_mut()).map(|(field, field_trivia)| {
+        let (docs, attributes) = attributes::outer_and_docs(&field.attrs, source);
+        ARSField {
+            name: field.ident.as_ref().map(|ident| ident.to_string()),
+            ty: types::from_syn(&field.ty, source, unsupported),
+            visibility: visibility(&field.vis),
             attributes,
+            docs,
+            trivia: std::mem::take(field_trivia),
+        }
+    }).collect();
+    (fields, closing)
+}
+
+/// Renders a delimited field list, `(u8, String)` or ` { x: i32 }`.
+///
+/// Fields go on one line unless any carries docs, attributes or trivia, or
+/// there is trivia before the closing delimiter.
+pub fn reconstruct_fields(fields: &[ARSField], named: bool, trailing_comma: bool, closing: &[ARSTriviaPiece]) -> String {
+    let (open, close) = if named { (" {", "}") } else { ("(", ")") };
+    let multiline = !closing.is_empty()
+        || fields.iter().any(|field| {
+            !field.docs.is_empty() || !field.attributes.is_empty() || !field.trivia.is_empty()
         });
+
+    let field_source = |field: &ARSField| {
+        let mut text = attributes::render_docs(&field.docs);
+        text.push_str(&attributes::reconstruct(&field.attributes));
+        text.push_str(&vis_prefix(&field.visibility));
+        if let Some(name) = &field.name {
+            text.push_str(&format!("{}: ", name));
+        }
+        text.push_str(&field.ty.to_string());
+        text
+    };
+
+    if !multiline {
+        let mut list = fields.iter().map(&field_source).collect::<Vec<_>>().join(", ");
+        if trailing_comma {
+            list.push(',');
+        }
+        return match (named, list.is_empty()) {
+            (true, true) => " {}".to_string(),
+            (true, false) => format!(" {{ {} }}", list),
+            (false, _) => format!("({})", list),
+        };
+    }
+
+    let mut lines = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        let comma = if i + 1 < fields.len() || trailing_comma { "," } else { "" };
+        lines.push_str(&trivia::render_lines(&field.trivia.leading));
+        lines.push_str(&trivia::with_trailing(&format!("{}{}", field_source(field), comma), &field.trivia));
+        lines.push('\n');
+    }
+    lines.push_str(&trivia::render_lines(closing));
+    format!("{}\n{}\n{}", open, indent(lines.trim_end_matches('\n')), close)
+}
+
+/// Converts a syn struct item into an ARSStruct
+pub fn from_syn(item: &syn::ItemStruct, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSStruct {
+    let (fields, closing) = fields_from_syn(&item.fields, source, unsupported);
+    ARSStruct {
+        name: item.ident.to_string(),
+        fields,
+        is_tuple: matches!(item.fields, syn::Fields::Unnamed(_)),
+        is_unit: matches!(item.fields, syn::Fields::Unit),
+        trailing_comma: match &item.fields {
+            syn::Fields::Named(named) => named.named.trailing_punct(),
+            syn::Fields::Unnamed(unnamed) => unnamed.unnamed.trailing_punct(),
+            syn::Fields::Unit => false,
+        },
+        visibility: visibility(&item.vis),
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
     }
+}
 
-    structs
+/// Encode all structs in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_structs(source: &str) -> Vec<ARSStruct> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Struct(s) => Some(from_syn(s, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_are_structured() {
+        let source = "
+pub struct Cache<K, V> where K: Hash {
+    /// Stored entries
+    pub(in crate::store) entries: Vec<(K, V)>,
+    hits: u64,
+}
+
+struct Pair<T>(pub T, T) where T: Copy;
+";
+        let result = extract_ars_structs(source);
+        let cache = &result[0];
+        assert_eq!(generics::reconstruct_where(&cache.generics), " where K: Hash");
+        assert_eq!(cache.fields[0].visibility.as_deref(), Some("pub(in crate::store)"));
+        assert_eq!(cache.fields[0].docs, vec![" Stored entries"]);
+        assert!(cache.fields[1].docs.is_empty() && cache.trailing_comma);
+        assert!(result[1].is_tuple && result[1].fields.iter().all(|field| field.name.is_none()));
+        assert_eq!(
+            reconstruct(&result),
+            "pub struct Cache<K, V> where K: Hash {\n    \
+             /// Stored entries\n    \
+             pub(in crate::store) entries: Vec<(K, V)>,\n    \
+             hits: u64,\n\
+             }\n\n\
+             struct Pair<T>(pub T, T) where T: Copy;\n\n"
+        );
+    }
 }
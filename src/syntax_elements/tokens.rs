@@ -0,0 +1,372 @@
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+
+/// Renders a syn node back to Rust source.
+///
+/// `TokenStream::to_string` puts a space between every token (`Vec < T >`),
+/// which is valid but unreadable once decoded. This printer applies
+/// rustfmt-like spacing instead, while never gluing two tokens together in a
+/// way that would lex differently.
+pub fn to_source<T: ToTokens>(node: &T) -> String {
+    let mut printer = Printer::default();
+    printer.stream(node.to_token_stream());
+    printer.out
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Role {
+    Separator,
+    Colon,
+    MacroColon,
+    PathSep,
+    Dot,
+    Prefix,
+    MacroBang,
+    Question,
+    GenericOpen,
+    GenericClose,
+    Repetition,
+    Binary,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Last {
+    Start,
+    Ident,
+    Keyword,
+    Literal,
+    Lifetime,
+    MacroVar,
+    Close(Delimiter),
+    Op(Role, char, Spacing),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Next {
+    Word,
+    Op(Role, char),
+    Open(Delimiter),
+}
+
+struct Printer {
+    out: String,
+    last: Last,
+    angle_depth: usize,
+    /// Right after a macro repetition `$(..)` or its separator, where
+    /// `*`, `+` and `?` are repetition operators.
+    repetition: bool,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Printer {
+            out: String::new(),
+            last: Last::Start,
+            angle_depth: 0,
+            repetition: false,
+        }
+    }
+}
+
+impl Printer {
+    fn stream(&mut self, stream: TokenStream) {
+        let tokens: Vec<TokenTree> = stream.into_iter().collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            match &tokens[i] {
+                TokenTree::Group(group) => {
+                    if group.delimiter() == Delimiter::None {
+                        self.stream(group.stream());
+                    } else {
+                        let repeated = group.delimiter() == Delimiter::Parenthesis
+                            && matches!(self.last, Last::Op(_, '$', _));
+                        self.group(group.delimiter(), group.stream());
+                        self.repetition = repeated;
+                    }
+                    i += 1;
+                }
+                TokenTree::Ident(ident) => {
+                    let text = ident.to_string();
+                    let last = if matches!(self.last, Last::Op(_, '$', _)) {
+                        Last::MacroVar
+                    } else if is_keyword(&text) {
+                        Last::Keyword
+                    } else {
+                        Last::Ident
+                    };
+                    self.word(&text, last);
+                    i += 1;
+                }
+                TokenTree::Literal(literal) => {
+                    self.word(&literal.to_string(), Last::Literal);
+                    i += 1;
+                }
+                TokenTree::Punct(punct) => {
+                    // A joint `'` followed by an identifier is a lifetime or label.
+                    if punct.as_char() == '\'' && punct.spacing() == Spacing::Joint {
+                        if let Some(TokenTree::Ident(ident)) = tokens.get(i + 1) {
+                            self.word(&format!("'{}", ident), Last::Lifetime);
+                            i += 2;
+                            continue;
+                        }
+                    }
+
+                    // Collect a run of joint punctuation into one operator.
+                    let mut op = String::new();
+                    let mut spacing = Spacing::Alone;
+                    // `,` and `;` never start a longer operator, as in `$(..),+`, a `'`
+                    // always starts a lifetime, as in `<'a`, and a `$` a macro variable.
+                    while let Some(TokenTree::Punct(p)) = tokens.get(i) {
+                        if matches!(p.as_char(), '\'' | '$') && !op.is_empty() {
+                            break;
+                        }
+                        op.push(p.as_char());
+                        spacing = p.spacing();
+                        i += 1;
+                        if spacing == Spacing::Alone || matches!(p.as_char(), ',' | ';') {
+                            break;
+                        }
+                    }
+                    self.op(&op, spacing, tokens.get(i));
+                }
+            }
+        }
+    }
+
+    fn group(&mut self, delimiter: Delimiter, inner: TokenStream) {
+        let (open, close) = match delimiter {
+            Delimiter::Parenthesis => ('(', ')'),
+            Delimiter::Bracket => ('[', ']'),
+            Delimiter::Brace => ('{', '}'),
+            Delimiter::None => unreachable!(),
+        };
+        // `use a::{b, c}` keeps its braces tight; blocks get padded.
+        let padded = delimiter == Delimiter::Brace
+            && !matches!(self.last, Last::Op(Role::PathSep, _, _));
+
+        self.space_if(self.space_before(Next::Open(delimiter)));
+        self.out.push(open);
+
+        let saved_depth = self.angle_depth;
+        let mut inner_printer = Printer::default();
+        inner_printer.stream(inner);
+        self.angle_depth = saved_depth;
+
+        if !inner_printer.out.is_empty() {
+            if padded {
+                self.out.push(' ');
+            }
+            self.out.push_str(&inner_printer.out);
+            if padded {
+                self.out.push(' ');
+            }
+        }
+        self.out.push(close);
+        self.last = Last::Close(delimiter);
+        self.repetition = false;
+    }
+
+    fn word(&mut self, text: &str, last: Last) {
+        self.repetition = false;
+        self.space_if(self.space_before(Next::Word));
+        self.out.push_str(text);
+        self.last = last;
+    }
+
+    fn op(&mut self, op: &str, spacing: Spacing, next: Option<&TokenTree>) {
+        let repetition = std::mem::take(&mut self.repetition);
+        // `>>` closing two generic lists arrives as a single joint run.
+        if op.len() > 1 && op.chars().all(|c| c == '>') && self.angle_depth >= op.len() {
+            for _ in 0..op.len() {
+                self.emit_op(">", Role::GenericClose, Spacing::Alone);
+                self.angle_depth -= 1;
+            }
+            return;
+        }
+
+        let role = match op {
+            "*" | "+" | "?" if repetition => Role::Repetition,
+            // Whatever else follows `$(..)` separates its repetitions, as in `$(..);*`
+            _ if repetition && matches!(self.last, Last::Close(_)) => {
+                self.repetition = true;
+                Role::Separator
+            }
+            "," | ";" => Role::Separator,
+            ":" if self.last == Last::MacroVar => Role::MacroColon,
+            ":" => Role::Colon,
+            "::" => Role::PathSep,
+            "." | ".." | "..." | "..=" => Role::Dot,
+            // `?Sized` in a bound, as opposed to `x?`
+            "?" if self.expects_operand() => Role::Prefix,
+            "?" => Role::Question,
+            "#" | "$" => Role::Prefix,
+            "!" if self.last == Last::Ident
+                && matches!(next, Some(TokenTree::Group(_)) | Some(TokenTree::Ident(_))) =>
+            {
+                Role::MacroBang
+            }
+            "&" | "&&" | "*" | "-" | "!" if self.expects_operand() => Role::Prefix,
+            "<" if !matches!(self.last, Last::Close(_) | Last::Literal) => {
+                self.angle_depth += 1;
+                Role::GenericOpen
+            }
+            ">" if self.angle_depth > 0 => {
+                self.angle_depth -= 1;
+                Role::GenericClose
+            }
+            _ => Role::Binary,
+        };
+        self.emit_op(op, role, spacing);
+    }
+
+    fn emit_op(&mut self, op: &str, role: Role, spacing: Spacing) {
+        let first = op.chars().next().unwrap_or(' ');
+        let last = op.chars().last().unwrap_or(' ');
+        self.space_if(self.space_before(Next::Op(role, first)));
+        self.out.push_str(op);
+        self.last = Last::Op(role, last, spacing);
+    }
+
+    /// Whether the previous token leaves the parser expecting an operand,
+    /// which makes `&`, `*`, `-` and `!` prefix operators.
+    fn expects_operand(&self) -> bool {
+        match self.last {
+            Last::Start | Last::Keyword => true,
+            Last::Op(role, _, _) => !matches!(role, Role::GenericClose | Role::Question),
+            _ => false,
+        }
+    }
+
+    fn space_before(&self, next: Next) -> bool {
+        let wanted = match (self.last, next) {
+            (Last::Start, _) => false,
+            (_, Next::Op(Role::Separator | Role::Question | Role::Colon | Role::MacroColon, _)) => false,
+            // The separator and operator of a macro repetition, `$(..),*`
+            (_, Next::Op(Role::Repetition, _)) => false,
+            (Last::Op(Role::Separator | Role::Colon, _, _), _) => true,
+            (
+                Last::Op(
+                    Role::MacroColon
+                    | Role::PathSep
+                    | Role::Dot
+                    | Role::Prefix
+                    | Role::MacroBang
+                    | Role::GenericOpen,
+                    _,
+                    _,
+                ),
+                _,
+            ) => false,
+            (_, Next::Op(Role::PathSep | Role::Dot, _)) => {
+                matches!(self.last, Last::Keyword | Last::Op(Role::Binary, _, _))
+            }
+            (_, Next::Op(Role::GenericOpen, _)) => {
+                !matches!(self.last, Last::Ident | Last::Keyword)
+            }
+            (_, Next::Op(Role::GenericClose | Role::MacroBang, _)) => false,
+            (_, Next::Open(Delimiter::Parenthesis | Delimiter::Bracket)) => match self.last {
+                Last::Ident | Last::MacroVar | Last::Close(_) | Last::Op(Role::GenericClose, _, _) => false,
+                // `fn(i32) -> i32` and `pub(crate)` stay tight, `if (a)` does not.
+                Last::Keyword => !(self.out.ends_with("fn") || self.out.ends_with("pub")),
+                _ => true,
+            },
+            _ => true,
+        };
+        wanted || self.must_separate(next)
+    }
+
+    /// Two adjacent tokens that would lex as something else when glued.
+    fn must_separate(&self, next: Next) -> bool {
+        match (self.last, next) {
+            (
+                Last::Ident | Last::Keyword | Last::Literal | Last::Lifetime | Last::MacroVar,
+                Next::Word,
+            ) => true,
+            (Last::Op(_, prev, Spacing::Alone), Next::Op(_, first)) => {
+                let pair: String = [prev, first].iter().collect();
+                matches!(
+                    pair.as_str(),
+                    "::" | "->" | "=>" | "==" | "!=" | "<=" | ">=" | "&&" | "||" | "+=" | "-="
+                        | "*=" | "/=" | "%=" | "^=" | "&=" | "|=" | "<<" | ".." | "//"
+                        | "/*" | "<-" | ".="
+                )
+            }
+            _ => false,
+        }
+    }
+
+    fn space_if(&mut self, space: bool) {
+        if space {
+            self.out.push(' ');
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "as" | "async" | "await" | "break" | "const" | "continue" | "dyn" | "else" | "enum"
+            | "extern" | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod"
+            | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct" | "trait"
+            | "type" | "unsafe" | "use" | "where" | "while" | "yield"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip_type(source: &str) -> String {
+        to_source(&syn::parse_str::<syn::Type>(source).unwrap())
+    }
+
+    #[test]
+    fn test_types_print_tightly() {
+        assert_eq!(roundtrip_type("HashMap<K, Vec<V>>"), "HashMap<K, Vec<V>>");
+        assert_eq!(roundtrip_type("&'a mut [u8; 4]"), "&'a mut [u8; 4]");
+        assert_eq!(roundtrip_type("Box<dyn Fn(i32) -> i32 + Send>"), "Box<dyn Fn(i32) -> i32 + Send>");
+        assert_eq!(roundtrip_type("<T as Iterator>::Item"), "<T as Iterator>::Item");
+        assert_eq!(roundtrip_type("Box<dyn Debug + ?Sized>"), "Box<dyn Debug + ?Sized>");
+    }
+
+    #[test]
+    fn test_attributes_and_paths() {
+        let attr: syn::ItemStruct = syn::parse_str("#[derive(Debug, Clone)] pub(crate) struct A;").unwrap();
+        assert_eq!(to_source(&attr.attrs[0]), "#[derive(Debug, Clone)]");
+        assert_eq!(to_source(&attr.vis), "pub(crate)");
+
+        let tree: syn::ItemUse = syn::parse_str("use std::collections::{HashMap, HashSet};").unwrap();
+        assert_eq!(to_source(&tree.tree), "std::collections::{HashMap, HashSet}");
+
+        let mac: syn::Macro = syn::parse_str("m!(<'a, T> Trait for &'a T)").unwrap();
+        assert_eq!(to_source(&mac.tokens), "<'a, T> Trait for &'a T");
+    }
+
+    #[test]
+    fn test_separate_puncts_stay_apart() {
+        // `& &` in a matcher does not match `&&`, nor `| |` a closure's `||`
+        let mac: syn::Macro = syn::parse_str("m!((& & $x:expr) => (&& $x || | |))").unwrap();
+        assert_eq!(to_source(&mac.tokens), "(& &$x:expr) => (&&$x || | |)");
+    }
+
+    #[test]
+    fn test_macro_repetitions() {
+        let mac: syn::Macro = syn::parse_str("m!(($($x:expr),* $(,)?) => ($($x;)+ &$y * $z))").unwrap();
+        assert_eq!(to_source(&mac.tokens), "($($x:expr),* $(,)?) => ($($x;)+ &$y * $z)");
+
+        let mac: syn::Macro = syn::parse_str("m!($($a)|+ $($b)=>* $(- $c)?)").unwrap();
+        assert_eq!(to_source(&mac.tokens), "$($a)|+ $($b)=>* $(-$c)?");
+    }
+
+    #[test]
+    fn test_output_reparses_to_same_tokens() {
+        let source = "fn f<'a, T: Into<Vec<u8>>>(x: &&'a T) -> Option<&'a T> where T: Clone { let y = -*x; y.0.1; a::<T>(b) }";
+        let file = syn::parse_file(source).unwrap();
+        let printed = to_source(&file);
+        let reparsed = syn::parse_file(&printed).unwrap();
+        assert_eq!(
+            file.to_token_stream().to_string(),
+            reparsed.to_token_stream().to_string()
+        );
+    }
+}
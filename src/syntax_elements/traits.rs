@@ -1,11 +1,20 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::expressions::{self, ARSExpression};
+use crate::syntax_elements::functions::{self, ARSFunction};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, indent, parse_items, tokens, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType, ARSTypeParamBound};
 
 // Generic extract function stub
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_traits(source)
         .iter()
-        .map(|t| ron::to_string(t).unwrap())
+        .map(|t| to_ron(t).map_err(WaltError::from))
         .collect()
 }
 
@@ -13,82 +22,293 @@ pub fn extract(source: &str) -> Vec<String> {
 pub fn reconstruct(ars_traits: &[ARSTrait]) -> String {
     let mut output = String::new();
     for t in ars_traits {
-        for attr in &t.attributes {
-            output.push_str(&format!("{}\n", attr));
+        output.push_str(&trivia::render_lines(&t.trivia.leading));
+        output.push_str(&attributes::render_docs(&t.docs));
+        output.push_str(&attributes::reconstruct(&t.attributes));
+
+        output.push_str(&vis_prefix(&t.visibility));
+        if t.is_unsafe {
+            output.push_str("unsafe ");
+        }
+        if t.is_auto {
+            output.push_str("auto ");
         }
+        output.push_str(&format!("trait {}{}", t.name, generics::reconstruct_params(&t.generics)));
+        if !t.supertraits.is_empty() {
+            output.push_str(&format!(": {}", types::join_bounds(&t.supertraits)));
+        }
+        output.push_str(&generics::reconstruct_where(&t.generics));
 
-        let vis = t.visibility.as_deref().unwrap_or("");
-        let generics = t.generics.as_deref().unwrap_or("");
-        
-        output.push_str(&format!("{}trait {}{} {{\n", vis, t.name, generics));
-        for item in &t.items {
-            output.push_str(&format!("    {};\n", item));
+        if t.items.is_empty() && t.inner_attributes.is_empty() && t.trivia.closing.is_empty() {
+            output.push_str(&trivia::with_trailing(" {}", &t.trivia));
+        } else {
+            output.push_str(" {\n");
+            output.push_str(&indent(&attributes::reconstruct(&t.inner_attributes)));
+            for item in &t.items {
+                output.push_str(&format!("{}\n", indent(&reconstruct_item(item))));
+            }
+            output.push_str(&indent(&trivia::render_lines(&t.trivia.closing)));
+            output.push_str(&trivia::with_trailing("}", &t.trivia));
         }
-        output.push_str("}\n\n");
+        output.push_str("\n\n");
     }
     output
 }
+
+/// Reconstructs a single trait item with its trivia, unindented
+fn reconstruct_item(item: &ARSTraitItem) -> String {
+    let text = match item {
+        // Provided methods render their own trivia
+        ARSTraitItem::ProvidedMethod(method) => {
+            return functions::reconstruct(std::slice::from_ref(method)).trim_end().to_string();
+        }
+        ARSTraitItem::RequiredMethod(method) => format!("{};", functions::reconstruct_signature(method)),
+        ARSTraitItem::Const(c) => {
+            let mut text = attributes::render_docs(&c.docs);
+            text.push_str(&attributes::reconstruct(&c.attributes));
+            text.push_str(&format!("const {}{}: {}", c.name, generics::reconstruct_params(&c.generics), c.ty));
+            if let Some(default) = &c.default {
+                text.push_str(&format!(" = {}", expressions::reconstruct_expr(default)));
+            }
+            text.push_str(&generics::reconstruct_where(&c.generics));
+            text.push(';');
+            text
+        }
+        ARSTraitItem::Type(t) => {
+            let mut text = attributes::render_docs(&t.docs);
+            text.push_str(&attributes::reconstruct(&t.attributes));
+            text.push_str(&format!("type {}{}", t.name, generics::reconstruct_params(&t.generics)));
+            if !t.bounds.is_empty() {
+                text.push_str(&format!(": {}", types::join_bounds(&t.bounds)));
+            }
+            if let Some(default) = &t.default {
+                text.push_str(&format!(" = {}", default));
+            }
+            // Like in impls, the where clause of an associated type follows its value
+            text.push_str(&generics::reconstruct_where(&t.generics));
+            text.push(';');
+            text
+        }
+        ARSTraitItem::Verbatim { content, .. } => content.clone(),
+    };
+    format!("{}{}", trivia::render_lines(&item.trivia().leading), trivia::with_trailing(&text, item.trivia()))
+}
+
 /// Represents a Rust trait in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSTrait {
     pub name: String,
-    pub items: Vec<String>,         // Methods, associated types, constants
+    pub items: Vec<ARSTraitItem>,
     pub visibility: Option<String>,
-    pub generics: Option<String>,
-    pub attributes: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub supertraits: Vec<ARSTypeParamBound>, // Bounds after the colon, e.g. `Clone + Send + 'static`
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inner_attributes: Vec<ARSAttribute>, // `#![...]` at the top of the trait
+    pub is_unsafe: bool,
+    pub is_auto: bool,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
-/// Encode all traits in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_traits(source: &str) -> Vec<ARSTrait> {
-    let mut traits = Vec::new();
-    let trait_regex = Regex::new(
-        r"(?s)((?:#\[.*?\]\s*)*)(pub(?:\(\w+\))?\s*)?(unsafe\s+)?trait\s+(\w+)\s*(<[^>]*>)?\s*\{"
-    ).unwrap();
-    let attr_regex = Regex::new(r"#\[.*?\]").unwrap();
-
-    for cap in trait_regex.captures_iter(source) {
-        let full_match = cap.get(0).unwrap();
-        let attributes_str = cap.get(1).map_or("", |m| m.as_str());
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        // unsafe keyword is part of the signature but not explicitly stored in ARSTrait yet.
-        let name = cap.get(4).unwrap().as_str().to_string();
-        let generics = cap.get(5).map(|m| m.as_str().to_string());
-
-        let attributes: Vec<String> = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
-        
-        // Manually find the end of the trait body by balancing braces
-        let body_start = full_match.end();
-        let mut brace_count = 1;
-        let mut body_end = body_start;
-        for (i, c) in source[body_start..].char_indices() {
-            if c == '{' { brace_count += 1; }
-            if c == '}' { brace_count -= 1; }
-            if brace_count == 0 {
-                body_end = body_start + i;
-                break;
+/// An item declared in a trait
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSTraitItem {
+    RequiredMethod(ARSFunction), // Signature only, its body is empty
+    ProvidedMethod(ARSFunction),
+    Const(ARSTraitConst),
+    Type(ARSTraitType),
+    /// Macro invocations, and anything else syn does not structure
+    Verbatim {
+        content: String,
+        #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+        trivia: ARSTrivia,
+    },
+}
+
+/// An associated constant, `const N: usize;` or with a default value
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSTraitConst {
+    pub name: String,
+    pub ty: ARSType,
+    pub default: Option<ARSExpression>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// An associated type, `type Item: Clone = u8;`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSTraitType {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub bounds: Vec<ARSTypeParamBound>,
+    pub default: Option<ARSType>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+impl ARSTraitItem {
+    /// The comments and blank lines attached to the item
+    pub fn trivia(&self) -> &ARSTrivia {
+        match self {
+            ARSTraitItem::RequiredMethod(m) | ARSTraitItem::ProvidedMethod(m) => &m.trivia,
+            ARSTraitItem::Const(c) => &c.trivia,
+            ARSTraitItem::Type(t) => &t.trivia,
+            ARSTraitItem::Verbatim { trivia, .. } => trivia,
+        }
+    }
+
+    pub fn trivia_mut(&mut self) -> &mut ARSTrivia {
+        match self {
+            ARSTraitItem::RequiredMethod(m) | ARSTraitItem::ProvidedMethod(m) => &mut m.trivia,
+            ARSTraitItem::Const(c) => &mut c.trivia,
+            ARSTraitItem::Type(t) => &mut t.trivia,
+            ARSTraitItem::Verbatim { trivia, .. } => trivia,
+        }
+    }
+}
+
+/// Converts a syn trait item into an ARSTrait
+pub fn from_syn(item: &syn::ItemTrait, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSTrait {
+    let ranges = item.items.iter().map(|member| source.range(member)).collect();
+    let (inner_attributes, mut item_trivia, closing) =
+        attributes::inner_with_members(&item.attrs, ranges, &item.brace_token, source);
+
+    let items = item.items.iter().zip(item_trivia.iter_mut()).map(|(member, member_trivia)| {
+        let mut ars_item = item_from_syn(member, source, unsupported);
+        let target = ars_item.trivia_mut();
+        target.leading = std::mem::take(&mut member_trivia.leading);
+        target.trailing = member_trivia.trailing.take();
+        ars_item
+    }).collect();
+
+    ARSTrait {
+        name: item.ident.to_string(),
+        items,
+        visibility: visibility(&item.vis),
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        supertraits: types::bounds(&item.supertraits, source, unsupported),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        inner_attributes,
+        is_unsafe: item.unsafety.is_some(),
+        is_auto: item.auto_token.is_some(),
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
+    }
+}
+
+/// Converts a single trait item; methods go through the function model
+fn item_from_syn(item: &syn::TraitItem, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSTraitItem {
+    match item {
+        syn::TraitItem::Fn(method) => {
+            let func = functions::from_syn_trait(method, source, unsupported);
+            if method.default.is_some() {
+                ARSTraitItem::ProvidedMethod(func)
+            } else {
+                ARSTraitItem::RequiredMethod(func)
             }
         }
+        syn::TraitItem::Const(c) => ARSTraitItem::Const(ARSTraitConst {
+            name: c.ident.to_string(),
+            ty: types::from_syn(&c.ty, source, unsupported),
+            default: c.default.as_ref().map(|(_, expr)| expressions::from_syn(expr, source, unsupported)),
+            generics: generics::from_syn(&c.generics, source, unsupported),
+            attributes: attributes::outer(&c.attrs, source),
+            docs: attributes::docs(&c.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        syn::TraitItem::Type(t) => ARSTraitItem::Type(ARSTraitType {
+            name: t.ident.to_string(),
+            generics: generics::from_syn(&t.generics, source, unsupported),
+            bounds: types::bounds(&t.bounds, source, unsupported),
+            default: t.default.as_ref().map(|(_, ty)| types::from_syn(ty, source, unsupported)),
+            attributes: attributes::outer(&t.attrs, source),
+            docs: attributes::docs(&t.attrs),
+            trivia: ARSTrivia::default(),
+        }),
+        other => ARSTraitItem::Verbatim { content: tokens::to_source(other), trivia: ARSTrivia::default() },
+    }
+}
 
-        let items = if body_end > body_start {
-            let body = &source[body_start..body_end];
-            body.lines()
-                .map(|l| l.trim().to_string())
-                .filter(|l| !l.is_empty())
-                .collect()
-        } else {
-            Vec::new()
-        };
-
-        traits.push(ARSTrait {
-            name,
-            items,
-            visibility,
-            generics,
-            attributes,
-        });
+/// Encode all traits in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_traits(source: &str) -> Vec<ARSTrait> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Trait(t) => Some(from_syn(t, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trait_items_are_structured() {
+        let source = "
+pub unsafe trait Store<K>: Clone + Send + 'static where K: Hash {
+    type Value: Debug = ();
+    type Iter<'a>: Iterator<Item = &'a K> where Self: 'a;
+    type Entry<'a> = (&'a K, &'a Self::Value) where Self: 'a;
+    const CAPACITY: usize;
+
+    fn get(&self, key: &K) -> Option<&Self::Value>;
+    fn contains(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+auto trait Marker {}
+";
+        let result = extract_ars_traits(source);
+        let store = &result[0];
+        assert!(store.is_unsafe && !store.is_auto);
+        assert_eq!(types::join_bounds(&store.supertraits), "Clone + Send + 'static");
+        assert!(matches!(&store.supertraits[2], ARSTypeParamBound::Lifetime(lifetime) if lifetime == "'static"));
+        assert_eq!(generics::reconstruct_where(&store.generics), " where K: Hash");
+        assert!(matches!(&store.items[0], ARSTraitItem::Type(t) if types::join_bounds(&t.bounds) == "Debug" && matches!(&t.default, Some(ARSType::Tuple { elems, .. }) if elems.is_empty())));
+        assert!(matches!(&store.items[2], ARSTraitItem::Type(t) if t.name == "Entry" && t.generics.where_clause.is_some()));
+        assert!(matches!(&store.items[3], ARSTraitItem::Const(c) if c.default.is_none()));
+        assert!(matches!(&store.items[4], ARSTraitItem::RequiredMethod(_)));
+        assert!(matches!(&store.items[5], ARSTraitItem::ProvidedMethod(m) if m.body.len() == 1));
+        assert!(result[1].is_auto);
+        assert_eq!(
+            reconstruct(&result),
+            "pub unsafe trait Store<K>: Clone + Send + 'static where K: Hash {\n    \
+             type Value: Debug = ();\n    \
+             type Iter<'a>: Iterator<Item = &'a K> where Self: 'a;\n    \
+             type Entry<'a> = (&'a K, &'a Self::Value) where Self: 'a;\n    \
+             const CAPACITY: usize;\n\n    \
+             fn get(&self, key: &K) -> Option<&Self::Value>;\n    \
+             fn contains(&self, key: &K) -> bool {\n        \
+             self.get(key).is_some()\n    \
+             }\n\
+             }\n\n\
+             auto trait Marker {}\n\n"
+        );
     }
 
-    traits
+    #[test]
+    fn test_inner_attributes_of_traits_are_kept() {
+        let source = "trait Marker {\n    #![allow(unused)]\n}\n\n";
+        let result = extract_ars_traits(source);
+        assert_eq!(result[0].inner_attributes[0].to_string(), "#![allow(unused)]");
+        assert_eq!(reconstruct(&result), source);
+    }
 }
+
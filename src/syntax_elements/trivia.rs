@@ -0,0 +1,307 @@
+use std::ops::Range;
+use proc_macro2::{TokenStream, TokenTree};
+use serde::{Serialize, Deserialize};
+use crate::source_map::SourceMap;
+
+/// Comments and blank lines around an element, which syn does not keep.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ARSTrivia {
+    pub leading: Vec<ARSTriviaPiece>,  // Lines above the element, in order
+    pub trailing: Option<String>,      // Comment after the element on its last line
+    pub closing: Vec<ARSTriviaPiece>,  // Lines before the closing brace of the element's body
+}
+
+/// A single line of trivia
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ARSTriviaPiece {
+    Comment(String), // Full comment text, including `//` or `/* */`
+    BlankLine,
+}
+
+impl ARSTrivia {
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_none() && self.closing.is_empty()
+    }
+}
+
+/// Where a gap of trivia begins
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GapStart {
+    LineStart,     // at the beginning of a line, e.g. the start of a file
+    AfterToken,    // after an opening delimiter or an attribute
+    AfterSibling,  // after a previous element, whose line belongs to its trailing comment
+}
+
+/// Collects the trivia of a run of sibling elements.
+///
+/// `ranges` are the byte ranges of the siblings, in order, inside the region
+/// `start..end` of their container. Returns the trivia of each sibling and
+/// the trivia left between the last sibling and `end`.
+pub fn for_siblings(
+    source: &SourceMap,
+    start: usize,
+    start_kind: GapStart,
+    ranges: &[Range<usize>],
+    end: usize,
+) -> (Vec<ARSTrivia>, Vec<ARSTriviaPiece>) {
+    let mut trivia = Vec::with_capacity(ranges.len());
+    let mut gap_start = start;
+    let mut gap_kind = start_kind;
+
+    for (i, range) in ranges.iter().enumerate() {
+        let next_start = ranges.get(i + 1).map_or(end, |next| next.start);
+        let leading = if gap_start <= range.start {
+            scan(source.slice(gap_start..range.start), gap_kind)
+        } else {
+            Vec::new()
+        };
+        let trailing = if range.end <= next_start {
+            trailing_comment(source.slice(range.end..next_start))
+        } else {
+            None
+        };
+        trivia.push(ARSTrivia { leading, trailing, closing: Vec::new() });
+        gap_start = range.end;
+        gap_kind = GapStart::AfterSibling;
+    }
+
+    let closing = if gap_start <= end {
+        scan(source.slice(gap_start..end), gap_kind)
+    } else {
+        Vec::new()
+    };
+    (trivia, closing)
+}
+
+/// Extracts comments and blank lines from text that holds no code.
+pub fn scan(gap: &str, kind: GapStart) -> Vec<ARSTriviaPiece> {
+    let mut pieces = Vec::new();
+    let mut first_line = true;
+    let mut line_empty = true;
+    let mut rest = gap;
+
+    while let Some(c) = rest.chars().next() {
+        let comment_end = if rest.starts_with("//") {
+            Some(rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with("/*") {
+            Some(block_comment_end(rest))
+        } else {
+            None
+        };
+
+        if let Some(end) = comment_end {
+            let comment = &rest[..end];
+            if !(first_line && kind == GapStart::AfterSibling) {
+                pieces.push(ARSTriviaPiece::Comment(comment.trim_end().to_string()));
+            }
+            if comment.contains('\n') {
+                first_line = false;
+            }
+            line_empty = false;
+            rest = &rest[end..];
+            continue;
+        }
+
+        if c == '\n' {
+            let full_line = !first_line || kind == GapStart::LineStart;
+            if line_empty && full_line {
+                pieces.push(ARSTriviaPiece::BlankLine);
+            }
+            first_line = false;
+            line_empty = true;
+        } else if !c.is_whitespace() {
+            line_empty = false;
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    pieces
+}
+
+/// Finds a comment on the same line, right after an element and its separator.
+pub fn trailing_comment(after: &str) -> Option<String> {
+    let line = &after[..after.find('\n').unwrap_or(after.len())];
+    let rest = line.trim_start_matches(|c: char| c.is_whitespace() || c == ',' || c == ';');
+    if rest.starts_with("//") {
+        Some(rest.trim_end().to_string())
+    } else if rest.starts_with("/*") {
+        let end = block_comment_end(rest);
+        (end <= rest.len()).then(|| rest[..end].to_string())
+    } else {
+        None
+    }
+}
+
+/// Length of the whitespace and comments at the start of `text`.
+pub fn trivia_len(text: &str) -> usize {
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_start();
+        let comment = if trimmed.starts_with("//") {
+            trimmed.find('\n').unwrap_or(trimmed.len())
+        } else if trimmed.starts_with("/*") {
+            block_comment_end(trimmed)
+        } else {
+            return text.len() - trimmed.len();
+        };
+        rest = &trimmed[comment..];
+    }
+}
+
+/// Length of the (possibly nested) block comment at the start of `text`.
+fn block_comment_end(text: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    let bytes = text.as_bytes();
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    text.len()
+}
+
+/// Lists the comments of source text with their byte offsets, each line
+/// trimmed so that re-indenting a comment does not change it.
+///
+/// Comments are whatever lies between tokens other than whitespace; doc
+/// comments are tokens, so they are left to the token stream. Text that does
+/// not lex has no comments.
+pub fn comments(source: &str) -> Vec<(usize, String)> {
+    let map = SourceMap::new(source);
+    let Ok(tokens) = source[map.content_start()..].parse::<TokenStream>() else {
+        return Vec::new();
+    };
+    let mut ranges = Vec::new();
+    token_ranges(tokens, &map, &mut ranges);
+    ranges.sort_by_key(|range| range.start);
+
+    let mut comments = Vec::new();
+    let mut cursor = map.content_start();
+    for range in ranges.into_iter().chain(std::iter::once(source.len()..source.len())) {
+        if range.start > cursor {
+            gap_comments(&source[cursor..range.start], cursor, &mut comments);
+        }
+        cursor = cursor.max(range.end);
+    }
+    comments
+}
+
+/// The comments of `original` that `decoded` lacks, matching each with its
+/// first remaining copy there.
+pub fn lost_comments(original: &str, decoded: &str) -> Vec<(usize, String)> {
+    let mut decoded: Vec<_> = comments(decoded).into_iter().map(|(_, text)| text).collect();
+    comments(original)
+        .into_iter()
+        .filter(|(_, comment)| match decoded.iter().position(|text| text == comment) {
+            Some(index) => {
+                decoded.remove(index);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+fn token_ranges(tokens: TokenStream, map: &SourceMap, out: &mut Vec<Range<usize>>) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => {
+                out.push(map.span_range(group.span_open()));
+                out.push(map.span_range(group.span_close()));
+                token_ranges(group.stream(), map, out);
+            }
+            other => out.push(map.span_range(other.span())),
+        }
+    }
+}
+
+/// Collects the comments in text that holds nothing else, starting at `offset`
+fn gap_comments(gap: &str, offset: usize, out: &mut Vec<(usize, String)>) {
+    let mut position = 0;
+    while position < gap.len() {
+        let rest = &gap[position..];
+        let end = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            block_comment_end(rest)
+        } else {
+            position += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        let text = rest[..end].lines().map(str::trim).collect::<Vec<_>>().join("\n");
+        out.push((offset + position, text));
+        position += end;
+    }
+}
+
+/// Renders trivia pieces as lines, each ending in a newline.
+pub fn render_lines(pieces: &[ARSTriviaPiece]) -> String {
+    let mut output = String::new();
+    for piece in pieces {
+        if let ARSTriviaPiece::Comment(comment) = piece {
+            output.push_str(comment);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Appends the trailing comment, if any, to the last line of `text`.
+pub fn with_trailing(text: &str, trivia: &ARSTrivia) -> String {
+    match &trivia.trailing {
+        Some(comment) => format!("{} {}", text.trim_end(), comment),
+        None => text.trim_end().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_comments_and_blank_lines() {
+        let gap = " // belongs to the previous element\n\n    // first\n    /* second */\n    ";
+        let pieces = scan(gap, GapStart::AfterSibling);
+        assert_eq!(
+            pieces,
+            vec![
+                ARSTriviaPiece::BlankLine,
+                ARSTriviaPiece::Comment("// first".to_string()),
+                ARSTriviaPiece::Comment("/* second */".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_are_listed_without_exact_bytes() {
+        let source = "/// Docs are tokens\nfn a() { // trailing\n    /* block\n       comment */\n    let s = \"// not a comment\";\n}\n";
+        assert_eq!(
+            comments(source),
+            vec![(29, "// trailing".to_string()), (45, "/* block\ncomment */".to_string())]
+        );
+        let reindented = "/// Docs are tokens\nfn a() {\n// trailing\n/* block\n   comment */\nlet s = \"// not a comment\";\n}\n";
+        let texts = |source: &str| comments(source).into_iter().map(|(_, text)| text).collect::<Vec<_>>();
+        assert_eq!(texts(reindented), texts(source));
+        assert_eq!(
+            lost_comments(source, "fn a() {\n    // trailing\n}\n"),
+            vec![(45, "/* block\ncomment */".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_trailing_comment_after_separator() {
+        assert_eq!(trailing_comment(", // note\n"), Some("// note".to_string()));
+        assert_eq!(trailing_comment("\n// next line"), None);
+    }
+}
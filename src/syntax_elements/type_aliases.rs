@@ -1,59 +1,79 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::types::{self, ARSType};
 
 /// Represents a Rust type alias in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSTypeAlias {
     pub name: String,
-    pub original_type: String,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub original_type: ARSType,
     pub visibility: Option<String>,
-    pub attributes: Vec<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
 /// Extract type aliases from a string slice.
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_type_aliases(source)
         .iter()
-        .map(|ta| ron::to_string(ta).unwrap())
+        .map(|ta| to_ron(ta).map_err(WaltError::from))
         .collect()
 }
 
-pub fn extract_ars_type_aliases(source: &str) -> Vec<ARSTypeAlias> {
-    let type_alias_regex = Regex::new(r"(?m)((?:#\[.*?\]\s*)*)(pub(?:\(crate\))?\s+)?type\s+(\w+(?:<.*?>)?)\s*=\s*(.+?);").unwrap();
-    let attr_regex = Regex::new(r"#\[(.*?)\]").unwrap();
-
-    type_alias_regex.captures_iter(source).map(|cap| {
-        let attributes_str = &cap[1];
-        let visibility = cap.get(2).map(|m| m.as_str().trim().to_string());
-        let name = cap[3].to_string();
-        let original_type = cap[4].trim().to_string();
-
-        let attributes = attr_regex.captures_iter(attributes_str)
-            .map(|attr_cap| attr_cap[0].to_string())
-            .collect();
+/// Converts a syn type alias into an ARSTypeAlias.
+///
+/// Associated types are not aliases: they stay with their impl or trait as
+/// `ARSImplType` and `ARSTraitType`.
+pub fn from_syn(item: &syn::ItemType, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSTypeAlias {
+    ARSTypeAlias {
+        name: item.ident.to_string(),
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        original_type: types::from_syn(&item.ty, source, unsupported),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia::default(),
+    }
+}
 
-        ARSTypeAlias {
-            name,
-            original_type,
-            visibility,
-            attributes,
-        }
-    }).collect()
+pub fn extract_ars_type_aliases(source: &str) -> Vec<ARSTypeAlias> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Type(ta) => Some(from_syn(ta, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
 }
 
 /// Reconstructs Rust code from a slice of ARSTypeAlias structs
 pub fn reconstruct(ars_type_aliases: &[ARSTypeAlias]) -> String {
     let mut output = String::new();
     for ta in ars_type_aliases {
-        for attr in &ta.attributes {
-            output.push_str(&format!("{}\n", attr));
-        }
-        let vis = if let Some(v) = &ta.visibility {
-            format!("{} ", v)
-        } else {
-            "".to_string()
-        };
-        output.push_str(&format!("{}type {} = {};\n", vis, ta.name, ta.original_type));
+        output.push_str(&trivia::render_lines(&ta.trivia.leading));
+        output.push_str(&attributes::render_docs(&ta.docs));
+        output.push_str(&attributes::reconstruct(&ta.attributes));
+        let text = format!(
+            "{}type {}{}{} = {};",
+            vis_prefix(&ta.visibility),
+            ta.name,
+            generics::reconstruct_params(&ta.generics),
+            generics::reconstruct_where(&ta.generics),
+            ta.original_type,
+        );
+        output.push_str(&format!("{}\n", trivia::with_trailing(&text, &ta.trivia)));
     }
     if !ars_type_aliases.is_empty() {
         output.push('\n');
@@ -65,15 +85,27 @@ pub fn reconstruct(ars_type_aliases: &[ARSTypeAlias]) -> String {
 mod tests {
     use super::*;
     use anyhow::Result;
+    use crate::syntax_elements::types::{ARSPathArguments, ARSPathSegment};
+
+    fn path(name: &str) -> ARSType {
+        ARSType::Path {
+            qself: None,
+            leading_colon: false,
+            segments: vec![ARSPathSegment { name: name.to_string(), arguments: ARSPathArguments::None }],
+        }
+    }
 
     #[test]
     fn test_single_type_alias_reconstruct() -> Result<()> {
         let aliases = vec![
             ARSTypeAlias {
                 name: "MyInt".to_string(),
-                original_type: "i32".to_string(),
+                generics: ARSGenerics::default(),
+                original_type: path("i32"),
                 visibility: None,
                 attributes: vec![],
+                docs: vec![],
+                trivia: ARSTrivia::default(),
             }
         ];
         let reconstructed = reconstruct(&aliases);
@@ -86,15 +118,21 @@ mod tests {
         let aliases = vec![
             ARSTypeAlias {
                 name: "MyInt".to_string(),
-                original_type: "i32".to_string(),
+                generics: ARSGenerics::default(),
+                original_type: path("i32"),
                 visibility: None,
                 attributes: vec![],
+                docs: vec![],
+                trivia: ARSTrivia::default(),
             },
             ARSTypeAlias {
                 name: "MyString".to_string(),
-                original_type: "String".to_string(),
+                generics: ARSGenerics::default(),
+                original_type: path("String"),
                 visibility: Some("pub".to_string()),
                 attributes: vec![],
+                docs: vec![],
+                trivia: ARSTrivia::default(),
             },
         ];
         let reconstructed = reconstruct(&aliases);
@@ -109,6 +147,33 @@ mod tests {
         let extracted = extract_ars_type_aliases(source);
         assert_eq!(extracted.len(), 1);
         assert_eq!(extracted[0].name, "MyResult");
-        assert_eq!(extracted[0].original_type, "Result<String, MyError>");
+        assert_eq!(extracted[0].original_type.to_string(), "Result<String, MyError>");
+    }
+
+    #[test]
+    fn test_only_top_level_aliases_are_extracted() {
+        let source = "
+pub(crate) type Map<K, V> where K: Ord = BTreeMap<K, V>;
+
+impl Iterator for Counter {
+    type Item = u32;
+}
+
+trait Lend {
+    type Item<'a> where Self: 'a;
+}
+
+mod inner {
+    type Hidden = u8;
+}
+";
+        let extracted = extract_ars_type_aliases(source);
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].name, "Map");
+        assert_eq!(extracted[0].generics.params.len(), 2);
+        assert_eq!(
+            reconstruct(&extracted),
+            "pub(crate) type Map<K, V> where K: Ord = BTreeMap<K, V>;\n\n"
+        );
     }
 }
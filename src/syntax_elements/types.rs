@@ -0,0 +1,513 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::ars_file::ARSUnsupported;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::attributes::{self, ARSAttribute};
+use crate::syntax_elements::expressions::{self, ARSDelimiter, ARSExpression};
+use crate::syntax_elements::macros;
+use crate::syntax_elements::tokens::to_source;
+
+/// A type node, mirroring `syn::Type`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSType {
+    /// `std::vec::Vec<T>` or `<T as Iterator>::Item`
+    Path {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        qself: Option<ARSQualifiedSelf>,
+        #[serde(default)]
+        leading_colon: bool,
+        segments: Vec<ARSPathSegment>,
+    },
+    Reference { lifetime: Option<String>, mutable: bool, elem: Box<ARSType> }, // `&'a mut T`
+    Ptr { mutable: bool, elem: Box<ARSType> },                                // `*const T` or `*mut T`
+    Slice { elem: Box<ARSType> },
+    Array { elem: Box<ARSType>, len: Box<ARSExpression> },
+    Tuple { elems: Vec<ARSType>, #[serde(default)] trailing_comma: bool },
+    BareFn(Box<ARSBareFn>),
+    ImplTrait { bounds: Vec<ARSTypeParamBound> },
+    TraitObject { is_dyn: bool, bounds: Vec<ARSTypeParamBound> }, // `is_dyn` is false for 2015 bare trait objects
+    Paren { elem: Box<ARSType> },                     // `&(dyn Any + Send)`
+    Never,
+    Infer,
+    Macro { path: String, delimiter: ARSDelimiter, tokens: String },
+    Verbatim(String), // Anything syn itself could not structure
+}
+
+/// The `<T as Trait>` part of a qualified path
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSQualifiedSelf {
+    pub ty: Box<ARSType>,
+    pub position: usize, // How many of the path's segments name the trait, 0 for `<T>::Assoc`
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSPathSegment {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "ARSPathArguments::is_none")]
+    pub arguments: ARSPathArguments,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum ARSPathArguments {
+    #[default]
+    None,
+    /// `<K, V>`, or `::<T>` with `turbofish`
+    AngleBracketed {
+        #[serde(default)]
+        turbofish: bool,
+        args: Vec<ARSGenericArgument>,
+        #[serde(default)]
+        trailing_comma: bool,
+    },
+    /// `(A, B) -> C`, as in `Fn(A, B) -> C`
+    Parenthesized {
+        inputs: Vec<ARSType>,
+        #[serde(default)]
+        trailing_comma: bool,
+        output: Option<Box<ARSType>>,
+    },
+}
+
+/// A single argument between the angle brackets of a path segment
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSGenericArgument {
+    Lifetime(String),
+    Type(ARSType),
+    Const(ARSExpression), // `4` or `{ N + 1 }`; a bare `N` parses as a type
+    /// `Item = u8`, or `Item<'a> = &'a u8`
+    AssocType {
+        name: String,
+        #[serde(default, skip_serializing_if = "ARSPathArguments::is_none")]
+        arguments: ARSPathArguments,
+        ty: ARSType,
+    },
+    AssocConst { name: String, value: ARSExpression }, // `N = 4`
+    Constraint { name: String, bounds: Vec<ARSTypeParamBound> }, // `Item: Display`
+}
+
+/// A bound on a type, as after `T:`, `impl` or `dyn`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSTypeParamBound {
+    Trait(ARSTraitBound),
+    Lifetime(String),
+    Verbatim(String), // `use<'a, T>` captures, and anything else syn does not structure
+}
+
+/// A trait bound, `?Sized` or `for<'a> Fn(&'a T) -> bool`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSTraitBound {
+    #[serde(default)]
+    pub paren: bool,               // Wrapped in parentheses, `(?Sized)`
+    #[serde(default)]
+    pub maybe: bool,               // `?Sized`
+    pub lifetimes: Option<String>, // `for<'a>`
+    #[serde(default)]
+    pub leading_colon: bool,
+    pub segments: Vec<ARSPathSegment>,
+}
+
+/// A function pointer type, `unsafe extern "C" fn(*const u8, ...) -> i32`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSBareFn {
+    pub lifetimes: Option<String>, // `for<'a>`
+    pub is_unsafe: bool,
+    pub abi: Option<String>,       // The ABI name, or empty for a bare `extern`, as on functions
+    pub inputs: Vec<ARSBareFnArg>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variadic: Option<String>,
+    #[serde(default)]
+    pub trailing_comma: bool,
+    pub output: Option<Box<ARSType>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSBareFnArg {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<ARSAttribute>,
+    pub name: Option<String>, // `fn(len: usize)`
+    pub ty: ARSType,
+}
+
+impl ARSPathArguments {
+    pub fn is_none(&self) -> bool {
+        matches!(self, ARSPathArguments::None)
+    }
+}
+
+/// Converts a syn type into an ARSType tree.
+///
+/// `source` is the text the type was parsed from, which expressions in array
+/// lengths and const arguments need; items in their blocks that the model has
+/// no structure for are recorded in `unsupported`.
+pub fn from_syn(ty: &syn::Type, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSType {
+    let boxed = |ty: &syn::Type, unsupported: &mut Vec<ARSUnsupported>| Box::new(from_syn(ty, source, unsupported));
+    match ty {
+        syn::Type::Array(t) => ARSType::Array {
+            elem: boxed(&t.elem, unsupported),
+            len: Box::new(expressions::from_syn(&t.len, source, unsupported)),
+        },
+        syn::Type::BareFn(t) => ARSType::BareFn(Box::new(ARSBareFn {
+            lifetimes: t.lifetimes.as_ref().map(to_source),
+            is_unsafe: t.unsafety.is_some(),
+            abi: t.abi.as_ref().map(|abi| abi.name.as_ref().map_or(String::new(), |name| name.value())),
+            inputs: t.inputs.iter().map(|arg| ARSBareFnArg {
+                attributes: arg.attrs.iter().map(attributes::from_syn).collect(),
+                name: arg.name.as_ref().map(|(name, _)| name.to_string()),
+                ty: from_syn(&arg.ty, source, unsupported),
+            }).collect(),
+            variadic: t.variadic.as_ref().map(|variadic| to_source(&syn::BareVariadic { comma: None, ..variadic.clone() })),
+            trailing_comma: match &t.variadic {
+                Some(variadic) => variadic.comma.is_some(),
+                None => t.inputs.trailing_punct(),
+            },
+            output: return_type(&t.output, source, unsupported).map(Box::new),
+        })),
+        // Invisible delimiters from macro expansion
+        syn::Type::Group(t) => from_syn(&t.elem, source, unsupported),
+        syn::Type::ImplTrait(t) => ARSType::ImplTrait { bounds: bounds(&t.bounds, source, unsupported) },
+        syn::Type::Infer(_) => ARSType::Infer,
+        syn::Type::Macro(t) => ARSType::Macro {
+            path: to_source(&t.mac.path),
+            delimiter: match t.mac.delimiter {
+                syn::MacroDelimiter::Paren(_) => ARSDelimiter::Paren,
+                syn::MacroDelimiter::Bracket(_) => ARSDelimiter::Bracket,
+                syn::MacroDelimiter::Brace(_) => ARSDelimiter::Brace,
+            },
+            tokens: macros::group_text(&t.mac.tokens, t.mac.delimiter.span(), source),
+        },
+        syn::Type::Never(_) => ARSType::Never,
+        syn::Type::Paren(t) => ARSType::Paren { elem: boxed(&t.elem, unsupported) },
+        syn::Type::Path(t) => ARSType::Path {
+            qself: t
+                .qself
+                .as_ref()
+                .map(|qself| ARSQualifiedSelf { ty: boxed(&qself.ty, unsupported), position: qself.position }),
+            leading_colon: t.path.leading_colon.is_some(),
+            segments: t.path.segments.iter().map(|segment| ARSPathSegment {
+                name: segment.ident.to_string(),
+                arguments: arguments(&segment.arguments, source, unsupported),
+            }).collect(),
+        },
+        syn::Type::Ptr(t) => ARSType::Ptr { mutable: t.mutability.is_some(), elem: boxed(&t.elem, unsupported) },
+        syn::Type::Reference(t) => ARSType::Reference {
+            lifetime: t.lifetime.as_ref().map(to_source),
+            mutable: t.mutability.is_some(),
+            elem: boxed(&t.elem, unsupported),
+        },
+        syn::Type::Slice(t) => ARSType::Slice { elem: boxed(&t.elem, unsupported) },
+        syn::Type::TraitObject(t) => ARSType::TraitObject {
+            is_dyn: t.dyn_token.is_some(),
+            bounds: bounds(&t.bounds, source, unsupported),
+        },
+        syn::Type::Tuple(t) => ARSType::Tuple {
+            elems: t.elems.iter().map(|elem| from_syn(elem, source, unsupported)).collect(),
+            trailing_comma: t.elems.trailing_punct(),
+        },
+        other => ARSType::Verbatim(to_source(other)),
+    }
+}
+
+/// Converts the return type of a function or closure, `None` when it has none
+pub fn return_type(output: &syn::ReturnType, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> Option<ARSType> {
+    match output {
+        syn::ReturnType::Default => None,
+        syn::ReturnType::Type(_, ty) => Some(from_syn(ty, source, unsupported)),
+    }
+}
+
+/// Converts the bounds of a type parameter, `impl` or `dyn` type
+pub fn bounds<'a>(
+    bounds: impl IntoIterator<Item = &'a syn::TypeParamBound>,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> Vec<ARSTypeParamBound> {
+    bounds.into_iter().map(|bound| match bound {
+        syn::TypeParamBound::Trait(t) => ARSTypeParamBound::Trait(ARSTraitBound {
+            paren: t.paren_token.is_some(),
+            maybe: matches!(t.modifier, syn::TraitBoundModifier::Maybe(_)),
+            lifetimes: t.lifetimes.as_ref().map(to_source),
+            leading_colon: t.path.leading_colon.is_some(),
+            segments: t.path.segments.iter().map(|segment| ARSPathSegment {
+                name: segment.ident.to_string(),
+                arguments: arguments(&segment.arguments, source, unsupported),
+            }).collect(),
+        }),
+        syn::TypeParamBound::Lifetime(lifetime) => ARSTypeParamBound::Lifetime(to_source(lifetime)),
+        other => ARSTypeParamBound::Verbatim(to_source(other)),
+    }).collect()
+}
+
+/// Renders bounds as `A + B`
+pub fn join_bounds(bounds: &[ARSTypeParamBound]) -> String {
+    bounds.iter().map(ARSTypeParamBound::to_string).collect::<Vec<String>>().join(" + ")
+}
+
+fn arguments(arguments: &syn::PathArguments, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSPathArguments {
+    match arguments {
+        syn::PathArguments::None => ARSPathArguments::None,
+        syn::PathArguments::AngleBracketed(args) => angle_bracketed(args, source, unsupported),
+        syn::PathArguments::Parenthesized(args) => ARSPathArguments::Parenthesized {
+            inputs: args.inputs.iter().map(|input| from_syn(input, source, unsupported)).collect(),
+            trailing_comma: args.inputs.trailing_punct(),
+            output: return_type(&args.output, source, unsupported).map(Box::new),
+        },
+    }
+}
+
+fn angle_bracketed(
+    args: &syn::AngleBracketedGenericArguments,
+    source: &SourceMap,
+    unsupported: &mut Vec<ARSUnsupported>,
+) -> ARSPathArguments {
+    ARSPathArguments::AngleBracketed {
+        turbofish: args.colon2_token.is_some(),
+        args: args.args.iter().map(|arg| match arg {
+            syn::GenericArgument::Lifetime(lifetime) => ARSGenericArgument::Lifetime(to_source(lifetime)),
+            syn::GenericArgument::Type(ty) => ARSGenericArgument::Type(from_syn(ty, source, unsupported)),
+            syn::GenericArgument::Const(expr) => ARSGenericArgument::Const(expressions::from_syn(expr, source, unsupported)),
+            syn::GenericArgument::AssocType(assoc) => ARSGenericArgument::AssocType {
+                name: assoc.ident.to_string(),
+                arguments: assoc
+                    .generics
+                    .as_ref()
+                    .map_or(ARSPathArguments::None, |args| angle_bracketed(args, source, unsupported)),
+                ty: from_syn(&assoc.ty, source, unsupported),
+            },
+            syn::GenericArgument::AssocConst(assoc) => ARSGenericArgument::AssocConst {
+                name: assoc.ident.to_string(),
+                value: expressions::from_syn(&assoc.value, source, unsupported),
+            },
+            syn::GenericArgument::Constraint(constraint) => ARSGenericArgument::Constraint {
+                name: constraint.ident.to_string(),
+                bounds: bounds(&constraint.bounds, source, unsupported),
+            },
+            // syn's argument enum is non-exhaustive; keep anything new as a verbatim type
+            other => ARSGenericArgument::Type(ARSType::Verbatim(to_source(other))),
+        }).collect(),
+        trailing_comma: args.args.trailing_punct(),
+    }
+}
+
+impl fmt::Display for ARSType {
+    /// Renders the type as source, `&'a mut [u8; 4]` or `impl Fn(u8) -> bool + Send`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ARSType::Path { qself, leading_colon, segments } => {
+                let colon = if *leading_colon { "::" } else { "" };
+                let segments: Vec<String> = segments.iter().map(ARSPathSegment::to_string).collect();
+                match qself {
+                    None => write!(f, "{}{}", colon, segments.join("::")),
+                    Some(qself) if qself.position == 0 => write!(f, "<{}>{}{}", qself.ty, colon, segments.join("::")),
+                    Some(qself) => {
+                        let position = qself.position.min(segments.len());
+                        write!(f, "<{} as {}{}>", qself.ty, colon, segments[..position].join("::"))?;
+                        for segment in &segments[position..] {
+                            write!(f, "::{}", segment)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            ARSType::Reference { lifetime, mutable, elem } => {
+                f.write_str("&")?;
+                if let Some(lifetime) = lifetime {
+                    write!(f, "{} ", lifetime)?;
+                }
+                write!(f, "{}{}", if *mutable { "mut " } else { "" }, elem)
+            }
+            ARSType::Ptr { mutable, elem } => write!(f, "*{} {}", if *mutable { "mut" } else { "const" }, elem),
+            ARSType::Slice { elem } => write!(f, "[{}]", elem),
+            ARSType::Array { elem, len } => write!(f, "[{}; {}]", elem, expressions::reconstruct_expr(len)),
+            ARSType::Tuple { elems, trailing_comma } => write!(f, "({})", join(elems, *trailing_comma)),
+            ARSType::BareFn(bare_fn) => {
+                if let Some(lifetimes) = &bare_fn.lifetimes {
+                    write!(f, "{} ", lifetimes)?;
+                }
+                if bare_fn.is_unsafe {
+                    f.write_str("unsafe ")?;
+                }
+                match bare_fn.abi.as_deref() {
+                    Some("") => f.write_str("extern ")?,
+                    Some(abi) => write!(f, "extern \"{}\" ", abi)?,
+                    None => {}
+                }
+                let mut inputs: Vec<String> = bare_fn.inputs.iter().map(|arg| {
+                    let mut text: String = arg.attributes.iter().map(|attr| format!("{} ", attr)).collect();
+                    if let Some(name) = &arg.name {
+                        text.push_str(&format!("{}: ", name));
+                    }
+                    text + &arg.ty.to_string()
+                }).collect();
+                inputs.extend(bare_fn.variadic.clone());
+                let mut inputs = inputs.join(", ");
+                if bare_fn.trailing_comma {
+                    inputs.push(',');
+                }
+                write!(f, "fn({})", inputs)?;
+                with_output(f, &bare_fn.output)
+            }
+            ARSType::ImplTrait { bounds } => write!(f, "impl {}", join_bounds(bounds)),
+            ARSType::TraitObject { is_dyn, bounds } => {
+                write!(f, "{}{}", if *is_dyn { "dyn " } else { "" }, join_bounds(bounds))
+            }
+            ARSType::Paren { elem } => write!(f, "({})", elem),
+            ARSType::Never => f.write_str("!"),
+            ARSType::Infer => f.write_str("_"),
+            ARSType::Macro { path, delimiter, tokens } => f.write_str(&macros::reconstruct_call(path, *delimiter, tokens)),
+            ARSType::Verbatim(text) => f.write_str(text),
+        }
+    }
+}
+
+impl fmt::Display for ARSPathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.name, self.arguments)
+    }
+}
+
+impl fmt::Display for ARSPathArguments {
+    /// Renders the arguments right after their segment's name, or nothing without any
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ARSPathArguments::None => Ok(()),
+            ARSPathArguments::AngleBracketed { turbofish, args, trailing_comma } => {
+                let mut args = args.iter().map(|arg| match arg {
+                    ARSGenericArgument::Lifetime(lifetime) => lifetime.clone(),
+                    ARSGenericArgument::Type(ty) => ty.to_string(),
+                    ARSGenericArgument::Const(expr) => expressions::reconstruct_expr(expr),
+                    ARSGenericArgument::AssocType { name, arguments, ty } => format!("{}{} = {}", name, arguments, ty),
+                    ARSGenericArgument::AssocConst { name, value } => {
+                        format!("{} = {}", name, expressions::reconstruct_expr(value))
+                    }
+                    ARSGenericArgument::Constraint { name, bounds } => format!("{}: {}", name, join_bounds(bounds)),
+                }).collect::<Vec<String>>().join(", ");
+                if *trailing_comma {
+                    args.push(',');
+                }
+                write!(f, "{}<{}>", if *turbofish { "::" } else { "" }, args)
+            }
+            ARSPathArguments::Parenthesized { inputs, trailing_comma, output } => {
+                write!(f, "({})", join(inputs, *trailing_comma))?;
+                with_output(f, output)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ARSTypeParamBound {
+    /// Renders the bound as source, `?Sized`, `for<'a> Fn(&'a u8)` or `'static`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ARSTypeParamBound::Trait(bound) => {
+                let mut text = String::new();
+                if bound.maybe {
+                    text.push('?');
+                }
+                if let Some(lifetimes) = &bound.lifetimes {
+                    text.push_str(&format!("{} ", lifetimes));
+                }
+                if bound.leading_colon {
+                    text.push_str("::");
+                }
+                text.push_str(&bound.segments.iter().map(ARSPathSegment::to_string).collect::<Vec<String>>().join("::"));
+                if bound.paren {
+                    write!(f, "({})", text)
+                } else {
+                    f.write_str(&text)
+                }
+            }
+            ARSTypeParamBound::Lifetime(lifetime) => f.write_str(lifetime),
+            ARSTypeParamBound::Verbatim(text) => f.write_str(text),
+        }
+    }
+}
+
+/// Writes ` -> T` for a return type, or nothing without one
+fn with_output(f: &mut fmt::Formatter<'_>, output: &Option<Box<ARSType>>) -> fmt::Result {
+    match output {
+        Some(output) => write!(f, " -> {}", output),
+        None => Ok(()),
+    }
+}
+
+fn join(types: &[ARSType], trailing_comma: bool) -> String {
+    let mut output = types.iter().map(ARSType::to_string).collect::<Vec<String>>().join(", ");
+    if trailing_comma {
+        output.push(',');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+
+    fn parse(source: &str) -> (syn::Type, ARSType) {
+        let ty: syn::Type = syn::parse_str(source).unwrap();
+        let ars_type = from_syn(&ty, &SourceMap::new(source), &mut Vec::new());
+        (ty, ars_type)
+    }
+
+    #[test]
+    fn test_types_are_structured() {
+        let (_, ty) = parse("&'a mut HashMap<K, Vec<u8>>");
+        let ARSType::Reference { lifetime, mutable: true, elem } = &ty else { panic!("{:?}", ty) };
+        assert_eq!(lifetime.as_deref(), Some("'a"));
+        let ARSType::Path { segments, .. } = &**elem else { panic!("{:?}", elem) };
+        assert_eq!(segments[0].name, "HashMap");
+        assert!(matches!(
+            &segments[0].arguments,
+            ARSPathArguments::AngleBracketed { args, .. } if matches!(&args[1], ARSGenericArgument::Type(ARSType::Path { .. }))
+        ));
+
+        assert!(matches!(parse("[u8; N * 2]").1, ARSType::Array { .. }));
+        assert!(matches!(parse("Box<dyn Error + Send>").1, ARSType::Path { .. }));
+        assert!(matches!(parse("impl Iterator<Item = u8>").1, ARSType::ImplTrait { bounds } if matches!(&bounds[..], [ARSTypeParamBound::Trait(bound)] if bound.segments[0].name == "Iterator")));
+        let (_, ty) = parse("dyn for<'a> Fn(&'a u8) + ?Sized + 'static");
+        let ARSType::TraitObject { is_dyn: true, bounds } = &ty else { panic!("{:?}", ty) };
+        assert!(matches!(&bounds[0], ARSTypeParamBound::Trait(bound) if bound.lifetimes.as_deref() == Some("for<'a>")
+            && matches!(bound.segments[0].arguments, ARSPathArguments::Parenthesized { .. })));
+        assert!(matches!(&bounds[1], ARSTypeParamBound::Trait(bound) if bound.maybe && bound.segments[0].name == "Sized"));
+        assert!(matches!(&bounds[2], ARSTypeParamBound::Lifetime(lifetime) if lifetime == "'static"));
+        assert!(matches!(parse("!").1, ARSType::Never));
+        assert!(matches!(parse("(u8,)").1, ARSType::Tuple { trailing_comma: true, .. }));
+        assert!(matches!(
+            parse("unsafe extern \"C\" fn(fmt: *const c_char, ...) -> c_int").1,
+            ARSType::BareFn(ref bare_fn) if bare_fn.is_unsafe && bare_fn.variadic.as_deref() == Some("...")
+        ));
+    }
+
+    #[test]
+    fn test_types_roundtrip() {
+        for source in [
+            "&'a mut HashMap<K, Vec<u8>>",
+            "[u8; N * 2]",
+            "&[Option<&'static str>]",
+            "(u8,)",
+            "()",
+            "for<'b> fn(&'b u8, usize) -> bool",
+            "unsafe extern \"C\" fn(fmt: *const c_char, ...) -> c_int",
+            "*mut *const T",
+            "impl Fn(u8) -> bool + Send + 'a",
+            "Box<dyn Error + Send + Sync>",
+            "&(dyn Any + Send)",
+            "Box<dyn for<'a> Fn(&'a u8) -> &'a u8 + (Send) + 'static>",
+            "impl ?Sized + ::core::fmt::Debug + use<'a, T>",
+            "<T as Iterator>::Item",
+            "<Self>::Output",
+            "<Vec<T> as ::core::ops::Index<usize>>::Output",
+            "::std::result::Result<T, E>",
+            "Foo<'a, 4, { N + 1 }, Item = u8, Assoc<'a> = &'a T, Out: Debug>",
+            "Vec::<_>",
+            "fn() -> !",
+            "vec_of!(u8)",
+        ] {
+            let (ty, ars_type) = parse(source);
+            let output = ars_type.to_string();
+            let reparsed: syn::Type = syn::parse_str(&output).unwrap_or_else(|e| panic!("{}: {}", e, output));
+            assert_eq!(ty.to_token_stream().to_string(), reparsed.to_token_stream().to_string(), "{}", output);
+        }
+        assert_eq!(parse("Result<&'a mut [u8; 4], Box<dyn Error + Send>>").1.to_string(), "Result<&'a mut [u8; 4], Box<dyn Error + Send>>");
+    }
+}
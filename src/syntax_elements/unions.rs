@@ -0,0 +1,104 @@
+use serde::{Serialize, Deserialize};
+use crate::ars_file::{to_ron, ARSUnsupported};
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::structs::{self, ARSField};
+use crate::syntax_elements::generics::{self, ARSGenerics};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, parse_items, vis_prefix, visibility};
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
+    extract_ars_unions(source)
+        .iter()
+        .map(|u| to_ron(u).map_err(WaltError::from))
+        .collect()
+}
+
+pub fn reconstruct(ars_unions: &[ARSUnion]) -> String {
+    let mut output = String::new();
+    for u in ars_unions {
+        output.push_str(&trivia::render_lines(&u.trivia.leading));
+        output.push_str(&attributes::render_docs(&u.docs));
+        output.push_str(&attributes::reconstruct(&u.attributes));
+
+        let mut text = format!(
+            "{}union {}{}{}",
+            vis_prefix(&u.visibility),
+            u.name,
+            generics::reconstruct_params(&u.generics),
+            generics::reconstruct_where(&u.generics),
+        );
+        text.push_str(&structs::reconstruct_fields(&u.fields, true, u.trailing_comma, &u.trivia.closing));
+        output.push_str(&trivia::with_trailing(&text, &u.trivia));
+        output.push_str("\n\n");
+    }
+    output
+}
+
+/// Represents a union in Animated Rust (.ars) format
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSUnion {
+    pub name: String,
+    pub fields: Vec<ARSField>,
+    #[serde(default)]
+    pub trailing_comma: bool,       // After the last field
+    pub visibility: Option<String>,
+    #[serde(default, skip_serializing_if = "ARSGenerics::is_empty")]
+    pub generics: ARSGenerics,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,          // `closing` holds the lines before the end of the field list
+}
+
+/// Converts a syn union item into an ARSUnion; its fields use the struct field model
+pub fn from_syn(item: &syn::ItemUnion, source: &SourceMap, unsupported: &mut Vec<ARSUnsupported>) -> ARSUnion {
+    let (fields, closing) = structs::fields_from_syn(&syn::Fields::Named(item.fields.clone()), source, unsupported);
+    ARSUnion {
+        name: item.ident.to_string(),
+        fields,
+        trailing_comma: item.fields.named.trailing_punct(),
+        visibility: visibility(&item.vis),
+        generics: generics::from_syn(&item.generics, source, unsupported),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        trivia: ARSTrivia { closing, ..ARSTrivia::default() },
+    }
+}
+
+/// Encode all unions in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_unions(source: &str) -> Vec<ARSUnion> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Union(u) => Some(from_syn(u, &source_map, &mut Vec::new())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_fields_are_structured() {
+        let source = "#[repr(C)]
+pub union Bits<T: Copy> {
+    int: u32,
+    /// Same bits, read as a float
+    pub float: f32,
+    raw: T,
+}
+";
+        let result = extract_ars_unions(source);
+        let bits = &result[0];
+        assert_eq!(bits.fields.len(), 3);
+        assert_eq!(bits.fields[1].docs, vec![" Same bits, read as a float"]);
+        assert!(bits.trailing_comma);
+        assert_eq!(reconstruct(&result), format!("{}\n", source));
+    }
+}
@@ -1,27 +1,39 @@
-use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::ars_file::to_ron;
+use crate::error::WaltError;
+use crate::source_map::SourceMap;
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+use crate::syntax_elements::attributes::ARSAttribute;
+use crate::syntax_elements::{attributes, parse_items, vis_prefix, visibility};
 
-pub fn extract(source: &str) -> Vec<String> {
+pub fn extract(source: &str) -> Result<Vec<String>, WaltError> {
     extract_ars_uses(source)
         .iter()
-        .map(|ars_use| ron::to_string(ars_use).unwrap())
+        .map(|ars_use| to_ron(ars_use).map_err(WaltError::from))
         .collect()
 }
 
 pub fn reconstruct(ars_uses: &[ARSUse]) -> String {
     let mut output = String::new();
     for ars_use in ars_uses {
-        match ars_use.stmt_type.as_str() {
-            "use" => {
-                let stmt = format!("use {};", ars_use.path);
-                // Note: alias and glob are part of the path from the extractor
-                output.push_str(&stmt);
-            }
-            "extern" => {
-                output.push_str(&format!("extern crate {};", ars_use.path));
-            }
-            _ => {}
+        output.push_str(&trivia::render_lines(&ars_use.trivia.leading));
+        output.push_str(&attributes::render_docs(&ars_use.docs));
+        output.push_str(&attributes::reconstruct(&ars_use.attributes));
+        let mut path = reconstruct_tree(&ars_use.tree);
+        if ars_use.is_glob {
+            path.push_str("::*");
         }
+        if let Some(alias) = &ars_use.alias {
+            path.push_str(&format!(" as {}", alias));
+        }
+        let vis = vis_prefix(&ars_use.visibility);
+        let stmt = match ars_use.stmt_type.as_str() {
+            "use" if ars_use.leading_colon => format!("{}use ::{};", vis, path),
+            "use" => format!("{}use {};", vis, path),
+            "extern" => format!("{}extern crate {};", vis, path),
+            _ => String::new(),
+        };
+        output.push_str(&trivia::with_trailing(&stmt, &ars_use.trivia));
         output.push('\n');
     }
     if !ars_uses.is_empty() {
@@ -30,49 +42,158 @@ pub fn reconstruct(ars_uses: &[ARSUse]) -> String {
     output
 }
 
+/// Renders a use tree, `a::{b as c, d::*}`
+pub fn reconstruct_tree(tree: &ARSUseTree) -> String {
+    match tree {
+        ARSUseTree::Path { segment, tree } => format!("{}::{}", segment, reconstruct_tree(tree)),
+        ARSUseTree::Name(name) => name.clone(),
+        ARSUseTree::Rename { name, rename } => format!("{} as {}", name, rename),
+        ARSUseTree::Glob => "*".to_string(),
+        ARSUseTree::Group { items, trailing_comma } => {
+            let mut list = items.iter().map(reconstruct_tree).collect::<Vec<_>>().join(", ");
+            if *trailing_comma {
+                list.push(',');
+            }
+            format!("{{{}}}", list)
+        }
+    }
+}
+
 /// Represents a Rust `use` or `extern crate` statement in Animated Rust (.ars) format
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ARSUse {
-    pub stmt_type: String, // "use" or "extern"
-    pub path: String,
-    pub alias: Option<String>,
-    pub is_glob: bool,
+    pub stmt_type: String,          // "use" or "extern"
+    pub visibility: Option<String>,
+    pub attributes: Vec<ARSAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docs: Vec<String>,
+    #[serde(default)]
+    pub leading_colon: bool,        // `use ::std::fmt;`
+    pub tree: ARSUseTree,           // For `extern crate`, the crate name
+    pub alias: Option<String>,      // `as` rename at the end of a single path, or of the crate
+    pub is_glob: bool,              // `::*` at the end of a single path
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
 }
 
-/// Encode all `use` and `extern crate` statements in a Rust source file to Animated Rust (.ars)
-pub fn extract_ars_uses(source: &str) -> Vec<ARSUse> {
-    let mut uses = Vec::new();
+/// The paths a `use` imports, mirroring `syn::UseTree`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum ARSUseTree {
+    Path { segment: String, tree: Box<ARSUseTree> }, // `a::` followed by the rest
+    Name(String),                                    // `b`, including `self`
+    Rename { name: String, rename: String },         // `b as c`
+    Glob,                                            // `*`
+    Group {                                          // `{b, c}`
+        items: Vec<ARSUseTree>,
+        #[serde(default)]
+        trailing_comma: bool,
+    },
+}
 
-    // Capture use statements
-    let use_regex = Regex::new(r"(?m)^\s*use\s+([^;]+);").unwrap();
-    for cap in use_regex.captures_iter(source) {
-        let path = cap[1].trim().to_string();
-        let is_glob = path.ends_with("::*");
-        let alias = if path.contains(" as ") {
-            Some(path.split(" as ").nth(1).unwrap().trim().to_string())
-        } else {
-            None
-        };
+/// Converts a syn `use` item into an ARSUse
+pub fn from_syn_use(item: &syn::ItemUse, source: &SourceMap) -> ARSUse {
+    let (tree, alias, is_glob) = split_end(tree_from_syn(&item.tree));
+    ARSUse {
+        stmt_type: "use".to_string(),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        leading_colon: item.leading_colon.is_some(),
+        tree,
+        alias,
+        is_glob,
+        trivia: ARSTrivia::default(),
+    }
+}
 
-        uses.push(ARSUse {
-            stmt_type: "use".to_string(),
-            path,
-            alias,
-            is_glob,
-        });
+/// Converts a syn `extern crate` item into an ARSUse
+pub fn from_syn_extern_crate(item: &syn::ItemExternCrate, source: &SourceMap) -> ARSUse {
+    ARSUse {
+        stmt_type: "extern".to_string(),
+        visibility: visibility(&item.vis),
+        attributes: attributes::outer(&item.attrs, source),
+        docs: attributes::docs(&item.attrs),
+        leading_colon: false,
+        tree: ARSUseTree::Name(item.ident.to_string()),
+        alias: item.rename.as_ref().map(|(_, rename)| rename.to_string()),
+        is_glob: false,
+        trivia: ARSTrivia::default(),
     }
+}
 
-    // Capture extern crate statements
-    let extern_regex = Regex::new(r"(?m)^\s*extern\s+crate\s+([^;]+);").unwrap();
-    for cap in extern_regex.captures_iter(source) {
-        let path = cap[1].trim().to_string();
-        uses.push(ARSUse {
-            stmt_type: "extern".to_string(),
-            path,
-            alias: None,
-            is_glob: false,
-        });
+fn tree_from_syn(tree: &syn::UseTree) -> ARSUseTree {
+    match tree {
+        syn::UseTree::Path(path) => ARSUseTree::Path {
+            segment: path.ident.to_string(),
+            tree: Box::new(tree_from_syn(&path.tree)),
+        },
+        syn::UseTree::Name(name) => ARSUseTree::Name(name.ident.to_string()),
+        syn::UseTree::Rename(rename) => ARSUseTree::Rename {
+            name: rename.ident.to_string(),
+            rename: rename.rename.to_string(),
+        },
+        syn::UseTree::Glob(_) => ARSUseTree::Glob,
+        syn::UseTree::Group(group) => ARSUseTree::Group {
+            items: group.items.iter().map(tree_from_syn).collect(),
+            trailing_comma: group.items.trailing_punct(),
+        },
     }
+}
+
+/// Moves the rename or glob at the end of a single path (`a::b as c`, `a::*`)
+/// out of the tree, into the alias and glob flag of the use
+fn split_end(tree: ARSUseTree) -> (ARSUseTree, Option<String>, bool) {
+    match tree {
+        ARSUseTree::Path { segment, tree } => match *tree {
+            ARSUseTree::Glob => (ARSUseTree::Name(segment), None, true),
+            rest => {
+                let (rest, alias, is_glob) = split_end(rest);
+                (ARSUseTree::Path { segment, tree: Box::new(rest) }, alias, is_glob)
+            }
+        },
+        ARSUseTree::Rename { name, rename } => (ARSUseTree::Name(name), Some(rename), false),
+        other => (other, None, false),
+    }
+}
+
+/// Encode all `use` and `extern crate` statements in a Rust source file to Animated Rust (.ars)
+pub fn extract_ars_uses(source: &str) -> Vec<ARSUse> {
+    let source_map = SourceMap::new(source);
+    parse_items(source)
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Use(u) => Some(from_syn_use(u, &source_map)),
+            syn::Item::ExternCrate(e) => Some(from_syn_extern_crate(e, &source_map)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    uses
+    #[test]
+    fn test_use_trees_are_structured() {
+        let source = "pub(crate) use a::{b as c, d::{e, f}, *};
+#[cfg(test)]
+use ::std::fmt::Write as _;
+pub use crate::prelude::*;
+#[macro_use]
+extern crate serde as serde_core;
+";
+        let result = extract_ars_uses(source);
+        assert_eq!(result[0].visibility.as_deref(), Some("pub(crate)"));
+        assert!(matches!(
+            &result[0].tree,
+            ARSUseTree::Path { segment, tree } if segment == "a" && matches!(&**tree, ARSUseTree::Group { items, .. } if items.len() == 3)
+        ));
+        assert!(result[1].leading_colon);
+        assert_eq!(result[1].alias.as_deref(), Some("_"));
+        assert_eq!(result[1].attributes[0].to_string(), "#[cfg(test)]");
+        assert!(result[2].is_glob && result[2].alias.is_none());
+        assert_eq!(result[3].tree, ARSUseTree::Name("serde".to_string()));
+        assert_eq!(result[3].alias.as_deref(), Some("serde_core"));
+        assert_eq!(reconstruct(&result), format!("{}\n", source));
+    }
 }
@@ -0,0 +1,32 @@
+use serde::{Serialize, Deserialize};
+use crate::syntax_elements::tokens;
+use crate::syntax_elements::trivia::{self, ARSTrivia};
+
+/// An item the .ars model has no structure for yet, kept as its source so it is never dropped
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ARSVerbatimItem {
+    pub kind: String,    // e.g. "trait alias"
+    pub content: String, // The whole item, attributes included
+    #[serde(default, skip_serializing_if = "ARSTrivia::is_empty")]
+    pub trivia: ARSTrivia,
+}
+
+/// Keeps a syn item as its source text
+pub fn from_syn(item: &syn::Item, kind: &str) -> ARSVerbatimItem {
+    ARSVerbatimItem {
+        kind: kind.to_string(),
+        content: tokens::to_source(item),
+        trivia: ARSTrivia::default(),
+    }
+}
+
+/// Reconstructs Rust code from a slice of ARSVerbatimItem structs
+pub fn reconstruct(ars_verbatim_items: &[ARSVerbatimItem]) -> String {
+    let mut output = String::new();
+    for v in ars_verbatim_items {
+        output.push_str(&trivia::render_lines(&v.trivia.leading));
+        output.push_str(&trivia::with_trailing(&v.content, &v.trivia));
+        output.push_str("\n\n");
+    }
+    output
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use quote::ToTokens;
+use crate::{decoder, encoder, project_scanner};
+use crate::ars_file::to_ron;
+use crate::error::WaltError;
+use crate::syntax_elements::tokens::to_source;
+use crate::syntax_elements::trivia;
+
+/// How strict a roundtrip has to be to count as lossless.
+///
+/// Every level also requires the same comments, in the same order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum VerifyLevel {
+    Items,  // the same items, each with the same tokens
+    Tokens, // the same token stream for the whole file
+    Bytes,  // the same bytes
+}
+
+/// The outcome of encoding and decoding one source file in memory
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub bytes_identical: bool,
+    pub tokens_identical: bool,
+    pub comments_identical: bool,           // The same comments in the same order, ignoring indentation
+    pub lost_comments: Vec<String>,         // Comments of the original missing from the decoded source
+    pub reparse_error: Option<String>,      // Set when the decoded source is not valid Rust
+    pub item_mismatches: Vec<ItemMismatch>,
+}
+
+/// An item that did not survive the roundtrip unchanged
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemMismatch {
+    pub item: String, // e.g. "fn network::send"
+    pub kind: MismatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MismatchKind {
+    Missing,    // in the original but not the decoded source
+    Unexpected, // in the decoded source but not the original
+    Changed,    // in both, with different tokens
+}
+
+impl VerifyReport {
+    pub fn items_identical(&self) -> bool {
+        self.reparse_error.is_none() && self.item_mismatches.is_empty()
+    }
+
+    /// Whether the roundtrip is lossless at `level`
+    pub fn passes(&self, level: VerifyLevel) -> bool {
+        match level {
+            VerifyLevel::Bytes => self.bytes_identical,
+            VerifyLevel::Tokens => self.tokens_identical && self.comments_identical,
+            VerifyLevel::Items => self.items_identical() && self.comments_identical,
+        }
+    }
+}
+
+impl fmt::Display for ItemMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            MismatchKind::Missing => "missing after decoding",
+            MismatchKind::Unexpected => "not in the original",
+            MismatchKind::Changed => "tokens differ",
+        };
+        write!(f, "{}: {}", self.item, what)
+    }
+}
+
+/// Encodes source text to .ars and decodes it again in memory, then compares
+/// the result with it.
+///
+/// The encoded file goes through its RON text, as it would on disk.
+pub fn verify_source(source: &str, lossless: bool) -> Result<VerifyReport, WaltError> {
+    let original = syn::parse_file(source)?;
+    let ars_file = if lossless { encoder::encode_lossless(source)? } else { encoder::encode_source(source)? };
+    let decoded = decoder::decode(&decoder::parse_ars(&to_ron(&ars_file)?)?);
+
+    let mut report = VerifyReport {
+        bytes_identical: decoded == source,
+        ..VerifyReport::default()
+    };
+    match syn::parse_file(&decoded) {
+        Ok(roundtrip) => {
+            report.tokens_identical = token_string(&original) == token_string(&roundtrip);
+            report.item_mismatches = compare_items(&original.items, &roundtrip.items);
+            let texts = |source: &str| trivia::comments(source).into_iter().map(|(_, text)| text).collect::<Vec<_>>();
+            report.comments_identical = texts(source) == texts(&decoded);
+            report.lost_comments = trivia::lost_comments(source, &decoded).into_iter().map(|(_, text)| text).collect();
+        }
+        Err(e) => report.reparse_error = Some(e.to_string()),
+    }
+    Ok(report)
+}
+
+/// Verifies a single `.rs` file.
+pub fn verify_file(path: &Path, lossless: bool) -> Result<VerifyReport, WaltError> {
+    std::fs::read_to_string(path)
+        .map_err(WaltError::from)
+        .and_then(|source| verify_source(&source, lossless))
+        .map_err(|e| e.with_path(path))
+}
+
+/// Verifies every `.rs` file under a directory.
+pub fn verify_project(dir: &Path, lossless: bool) -> Result<Vec<(PathBuf, VerifyReport)>, WaltError> {
+    let mut paths = project_scanner::scan_rust_project(dir);
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| verify_file(&path, lossless).map(|report| (path, report)))
+        .collect()
+}
+
+fn token_string(node: &impl ToTokens) -> String {
+    node.to_token_stream().to_string()
+}
+
+/// Pairs up items by kind and path, then compares their tokens.
+fn compare_items(original: &[syn::Item], decoded: &[syn::Item]) -> Vec<ItemMismatch> {
+    let mut original_items = Vec::new();
+    flatten_items(original, "", &mut original_items);
+    let mut decoded_items = Vec::new();
+    flatten_items(decoded, "", &mut decoded_items);
+
+    let mut mismatches = Vec::new();
+    for (name, tokens) in original_items {
+        match decoded_items.iter().position(|(decoded_name, _)| *decoded_name == name) {
+            Some(index) => {
+                let (_, decoded_tokens) = decoded_items.remove(index);
+                if decoded_tokens != tokens {
+                    mismatches.push(ItemMismatch { item: name, kind: MismatchKind::Changed });
+                }
+            }
+            None => mismatches.push(ItemMismatch { item: name, kind: MismatchKind::Missing }),
+        }
+    }
+    mismatches.extend(
+        decoded_items
+            .into_iter()
+            .map(|(name, _)| ItemMismatch { item: name, kind: MismatchKind::Unexpected }),
+    );
+    mismatches
+}
+
+/// Lists items as `(kind and path, tokens)`, descending into inline modules.
+///
+/// A module's own tokens leave out its content, so a change inside it is
+/// reported against the nested item rather than the whole module.
+fn flatten_items(items: &[syn::Item], prefix: &str, out: &mut Vec<(String, String)>) {
+    for item in items {
+        let (kind, name) = describe(item);
+        let path = if name.is_empty() { prefix.trim_end_matches("::").to_string() } else { format!("{}{}", prefix, name) };
+        let label = if path.is_empty() { kind.to_string() } else { format!("{} {}", kind, path) };
+
+        match item {
+            syn::Item::Mod(m) => {
+                let mut header = m.clone();
+                let content = header.content.take();
+                out.push((label, token_string(&header)));
+                if let Some((_, nested)) = content {
+                    flatten_items(&nested, &format!("{}::", path), out);
+                }
+            }
+            _ => out.push((label, token_string(item))),
+        }
+    }
+}
+
+fn describe(item: &syn::Item) -> (&'static str, String) {
+    match item {
+        syn::Item::Const(c) => ("const", c.ident.to_string()),
+        syn::Item::Enum(e) => ("enum", e.ident.to_string()),
+        syn::Item::ExternCrate(e) => ("extern crate", e.ident.to_string()),
+        syn::Item::Fn(f) => ("fn", f.sig.ident.to_string()),
+        syn::Item::ForeignMod(f) => ("extern block", to_source(&f.abi)),
+        syn::Item::Impl(i) => {
+            let target = match &i.trait_ {
+                Some((bang, path, _)) => format!("{}{} for {}", if bang.is_some() { "!" } else { "" }, to_source(path), to_source(&i.self_ty)),
+                None => to_source(&i.self_ty),
+            };
+            ("impl", target)
+        }
+        syn::Item::Macro(m) => ("macro", m.ident.as_ref().map_or_else(|| to_source(&m.mac.path), |ident| ident.to_string())),
+        syn::Item::Mod(m) => ("mod", m.ident.to_string()),
+        syn::Item::Static(s) => ("static", s.ident.to_string()),
+        syn::Item::Struct(s) => ("struct", s.ident.to_string()),
+        syn::Item::Trait(t) => ("trait", t.ident.to_string()),
+        syn::Item::TraitAlias(t) => ("trait alias", t.ident.to_string()),
+        syn::Item::Type(t) => ("type", t.ident.to_string()),
+        syn::Item::Union(u) => ("union", u.ident.to_string()),
+        syn::Item::Use(u) => ("use", to_source(&u.tree)),
+        _ => ("item", String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_reports_dropped_items() {
+        let source = "struct Kept;\nextern \"C\" {\n    fn kept();\n}\n";
+        let report = verify_source(source, false).unwrap();
+        assert!(report.passes(VerifyLevel::Tokens));
+        assert!(report.passes(VerifyLevel::Items));
+
+        let original = syn::parse_file("struct Kept;\nextern \"C\" {\n    fn dropped();\n}\nfn changed() {}\n").unwrap();
+        let decoded = syn::parse_file("fn changed() { todo!() }\nstruct Kept;\n").unwrap();
+        assert_eq!(
+            compare_items(&original.items, &decoded.items),
+            vec![
+                ItemMismatch { item: "extern block extern \"C\"".to_string(), kind: MismatchKind::Missing },
+                ItemMismatch { item: "fn changed".to_string(), kind: MismatchKind::Changed },
+            ]
+        );
+
+        let report = verify_source(source, true).unwrap();
+        assert!(report.passes(VerifyLevel::Bytes));
+        assert!(report.passes(VerifyLevel::Items));
+    }
+
+    #[test]
+    fn test_lost_comments_fail_every_level() {
+        let report = verify_source("#[derive(Debug)] // why\nstruct S;\n", false).unwrap();
+        assert!(report.comments_identical && report.lost_comments.is_empty());
+        assert!(report.passes(VerifyLevel::Tokens));
+
+        // Patterns keep no comments, so this one is dropped while every token survives
+        let report = verify_source("fn f() {\n    let (a, /* ignored */ _) = (1, 2);\n}\n", false).unwrap();
+        assert!(report.tokens_identical && report.items_identical());
+        assert!(!report.comments_identical);
+        assert_eq!(report.lost_comments, vec!["/* ignored */"]);
+        for level in [VerifyLevel::Items, VerifyLevel::Tokens, VerifyLevel::Bytes] {
+            assert!(!report.passes(level), "{:?}", level);
+        }
+    }
+}